@@ -0,0 +1,257 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+
+use crate::types::{ClassDefinition, FunctionDefinition, ParsedFile, Range};
+
+const CACHE_FILE: &str = "index.json";
+/// Bumped whenever the format changes, so old caches are discarded instead of misread.
+const CACHE_VERSION: u32 = 2;
+
+/// What the fast scan extracts from the library path, saved in the XDG cache folder so files that
+/// did not change are not parsed again on the next start.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct IndexCache {
+    version: u32,
+    entries: HashMap<String, CacheEntry>,
+    /// Files looked up or added since loading.
+    #[serde(skip)]
+    seen: HashSet<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CacheEntry {
+    /// Modification time, in seconds and nanoseconds since the epoch.
+    modified: (u64, u32),
+    size: u64,
+    package: String,
+    is_script: bool,
+    function: Option<FunctionDefinition>,
+    class: Option<ClassDefinition>,
+    method_range: Option<Range>,
+}
+
+impl IndexCache {
+    /// Loads the cache. A missing, unreadable or outdated one is replaced by an empty one.
+    pub fn load() -> IndexCache {
+        let cache = cache_path()
+            .and_then(|path| Ok(std::fs::read_to_string(path)?))
+            .and_then(|contents| Ok(serde_json::from_str::<IndexCache>(&contents)?));
+        match cache {
+            Ok(cache) if cache.version == CACHE_VERSION => cache,
+            Ok(_) => IndexCache::default(),
+            Err(err) => {
+                debug!("Not using the index cache: {err}");
+                IndexCache::default()
+            }
+        }
+    }
+
+    /// The file as the fast scan would return it, if it did not change since it was cached.
+    pub fn get(
+        &mut self,
+        package: &str,
+        path: &str,
+    ) -> Option<(ParsedFile, Option<FunctionDefinition>)> {
+        self.seen.insert(path.to_string());
+        let entry = self.entry(package, path)?;
+        // An empty tree, the file is parsed again if its contents are ever loaded.
+        let mut parsed_file = ParsedFile::new(path.to_string(), Some(String::new())).ok()?;
+        parsed_file.package = entry.package.clone();
+        parsed_file.is_script = entry.is_script;
+        parsed_file.class = entry.class.clone();
+        parsed_file.method_range = entry.method_range;
+        Some((parsed_file, entry.function.clone()))
+    }
+
+    /// The entry of the file, if it did not change since it was cached.
+    fn entry(&self, package: &str, path: &str) -> Option<&CacheEntry> {
+        let entry = self.entries.get(path)?;
+        if entry.package != package || stamp(path)? != (entry.modified, entry.size) {
+            return None;
+        }
+        Some(entry)
+    }
+
+    pub fn insert(&mut self, parsed_file: &ParsedFile, function: &Option<FunctionDefinition>) {
+        self.seen.insert(parsed_file.path.clone());
+        let Some((modified, size)) = stamp(&parsed_file.path) else {
+            return;
+        };
+        self.entries.insert(
+            parsed_file.path.clone(),
+            CacheEntry {
+                modified,
+                size,
+                package: parsed_file.package.clone(),
+                is_script: parsed_file.is_script,
+                function: function.clone(),
+                class: parsed_file.class.clone(),
+                method_range: parsed_file.method_range,
+            },
+        );
+    }
+
+    /// Saves the cache. Other servers may share it, so it is read again just before writing and
+    /// the entries they saved since it was loaded are kept, unless the file is gone. The entries
+    /// of files scanned by this server replace theirs.
+    pub fn save(mut self) {
+        let seen = std::mem::take(&mut self.seen);
+        for (path, entry) in IndexCache::load().entries {
+            if !seen.contains(&path) {
+                self.entries.insert(path, entry);
+            }
+        }
+        self.entries
+            .retain(|path, _| seen.contains(path) || Path::new(path).is_file());
+        self.version = CACHE_VERSION;
+        if let Err(err) = self.write() {
+            error!("Could not save the index cache: {err}");
+        }
+    }
+
+    fn write(&self) -> Result<()> {
+        let path = cache_path()?;
+        // Written aside and moved in place, so a server reading it never sees half of it.
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&temporary, serde_json::to_string(self)?)?;
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(not(test))]
+fn cache_path() -> Result<std::path::PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("matlab-lsp");
+    Ok(xdg_dirs.place_cache_file(CACHE_FILE)?)
+}
+
+/// A cache per test thread, so tests neither share it nor touch the user's.
+#[cfg(test)]
+fn cache_path() -> Result<std::path::PathBuf> {
+    let thread = format!("{:?}", std::thread::current().id());
+    let thread: String = thread.chars().filter(char::is_ascii_digit).collect();
+    let folder =
+        std::env::temp_dir().join(format!("matlab-lsp-cache-{}-{thread}", std::process::id()));
+    std::fs::create_dir_all(&folder)?;
+    Ok(folder.join(CACHE_FILE))
+}
+
+/// Modification time and size of a file, which tell whether it changed.
+fn stamp(path: &str) -> Option<((u64, u32), u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((
+        (modified.as_secs(), modified.subsec_nanos()),
+        metadata.len(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file written in the folder of the test's cache, with its entry as the fast scan would
+    /// record it.
+    fn cached_file(name: &str, contents: &str) -> (String, CacheEntry) {
+        let path = cache_path().unwrap().with_file_name(name);
+        std::fs::write(&path, contents).unwrap();
+        let path = path.to_string_lossy().to_string();
+        let (modified, size) = stamp(&path).unwrap();
+        let entry = CacheEntry {
+            modified,
+            size,
+            package: "pkg".into(),
+            is_script: false,
+            function: None,
+            class: None,
+            method_range: Some(Range::default()),
+        };
+        (path, entry)
+    }
+
+    fn clear() {
+        let _ = std::fs::remove_dir_all(cache_path().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn restores_unchanged_files() {
+        clear();
+        let (path, entry) = cached_file("unchanged.m", "function f\nend\n");
+        let mut cache = IndexCache::load();
+        cache.entries.insert(path.clone(), entry);
+        cache.seen.insert(path.clone());
+        cache.save();
+        let cache = IndexCache::load();
+        let entry = cache.entry("pkg", &path).unwrap();
+        assert_eq!(entry.method_range, Some(Range::default()));
+        assert!(cache.entry("other", &path).is_none());
+        clear();
+    }
+
+    #[test]
+    fn invalidates_changed_files() {
+        clear();
+        let (path, entry) = cached_file("changed.m", "function f\nend\n");
+        let mut cache = IndexCache::load();
+        cache.entries.insert(path.clone(), entry);
+        std::fs::write(&path, "function g(x)\nend\n").unwrap();
+        assert!(cache.entry("pkg", &path).is_none());
+        clear();
+    }
+
+    #[test]
+    fn discards_other_versions() {
+        clear();
+        let (path, entry) = cached_file("version.m", "x = 1;\n");
+        let mut cache = IndexCache::load();
+        cache.entries.insert(path.clone(), entry);
+        cache.version = CACHE_VERSION - 1;
+        cache.write().unwrap();
+        assert!(IndexCache::load().entries.is_empty());
+        clear();
+    }
+
+    #[test]
+    fn keeps_entries_saved_by_other_servers() {
+        clear();
+        let (first, first_entry) = cached_file("first.m", "x = 1;\n");
+        let (second, second_entry) = cached_file("second.m", "y = 2;\n");
+        let mut one = IndexCache::load();
+        let mut other = IndexCache::load();
+        one.entries.insert(first.clone(), first_entry);
+        one.seen.insert(first.clone());
+        other.entries.insert(second.clone(), second_entry);
+        other.seen.insert(second.clone());
+        one.save();
+        other.save();
+        let cache = IndexCache::load();
+        assert!(cache.entry("pkg", &first).is_some());
+        assert!(cache.entry("pkg", &second).is_some());
+        clear();
+    }
+
+    #[test]
+    fn drops_deleted_files() {
+        clear();
+        let (path, entry) = cached_file("deleted.m", "x = 1;\n");
+        let mut cache = IndexCache::load();
+        cache.entries.insert(path.clone(), entry);
+        cache.save();
+        assert_eq!(IndexCache::load().entries.len(), 1);
+        std::fs::remove_file(&path).unwrap();
+        IndexCache::load().save();
+        assert!(IndexCache::load().entries.is_empty());
+        clear();
+    }
+}
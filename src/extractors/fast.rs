@@ -5,6 +5,8 @@
  */
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use anyhow::{anyhow, Context, Result};
@@ -14,12 +16,15 @@ use lsp_server::Message;
 use tree_sitter::Node;
 
 use crate::code_loc;
+use crate::extractors::cache::IndexCache;
+use crate::extractors::matlab_path::genpath_includes;
+use crate::settings::{glob_match, ScanMode, Settings};
 use crate::threads::db::db_set_packages;
 use crate::types::{
-    FunctionDefinition, FunctionSignature, MessagePayload, ParsedFile, Range, SenderThread,
-    ThreadMessage,
+    ClassDefinition, FunctionDefinition, FunctionSignature, MessagePayload, ParsedFile, Range,
+    SenderThread, ThreadMessage,
 };
-use crate::utils::{send_progress_begin, send_progress_end, send_progress_report};
+use crate::utils::{parallel_map, report_progress, send_progress_begin, send_progress_end};
 
 /// It's called a fast scan because it only extracts public information, so mostly function
 /// definition. Those files are not analysed for symbols or anything.
@@ -27,6 +32,7 @@ pub fn fast_scan(
     lsp_sender: Sender<Message>,
     sender: Sender<ThreadMessage>,
     folders: Vec<String>,
+    settings: &Settings,
     id: i32,
 ) -> Result<()> {
     let mut folders = folders;
@@ -35,7 +41,7 @@ pub fn fast_scan(
     let mut files = vec![];
     let mut packages = vec![];
     for folder in folders {
-        let (fs, ps) = traverse_folder(folder.clone(), String::new());
+        let (fs, ps) = traverse_folder(folder.clone(), String::new(), settings);
         files.extend(fs);
         packages.extend(ps);
     }
@@ -48,20 +54,41 @@ pub fn fast_scan(
         "Scanning files.",
         format!("0/{}", files.len()),
     )?;
-    for (i, (pkg, path)) in files.iter().enumerate() {
-        if let Ok((pf, fs)) = parse(pkg.clone(), path.clone()) {
+    // Cached files are restored first, the others are parsed on every core.
+    let mut cache = IndexCache::load();
+    let cached: Vec<_> = files
+        .iter()
+        .map(|(pkg, path)| cache.get(pkg, path))
+        .collect();
+    let stale: Vec<&(String, String)> = files
+        .iter()
+        .zip(&cached)
+        .filter(|(_, c)| c.is_none())
+        .map(|(f, _)| f)
+        .collect();
+    let done = AtomicUsize::new(files.len() - stale.len());
+    let parsed = parallel_map(&stale, |(pkg, path)| {
+        let result = parse(pkg.clone(), path.clone());
+        report_progress(&lsp_sender, id, "Scanning files.", &done, files.len());
+        result
+    });
+    let mut parsed = parsed.into_iter();
+    for cached in cached {
+        let result = match cached {
+            Some(cached) => Ok(cached),
+            None => parsed
+                .next()
+                .unwrap_or_else(|| Err(code_loc!()))
+                .inspect(|(pf, fs)| cache.insert(pf, fs)),
+        };
+        if let Ok((pf, fs)) = result {
             parsed_files.push(Arc::new(pf));
             if let Some(fs) = fs {
                 functions.push(Arc::new(fs));
             }
         }
-        send_progress_report(
-            lsp_sender.clone(),
-            id,
-            "Scanning files.",
-            (100 * i / files.len()).try_into()?,
-        )?;
     }
+    cache.save();
     send_progress_end(lsp_sender.clone(), id, "Finished scanning files.")?;
     sender.send(ThreadMessage {
         sender: SenderThread::BackgroundWorker,
@@ -70,11 +97,20 @@ pub fn fast_scan(
     Ok(())
 }
 
-pub fn traverse_folder(folder: String, package: String) -> (Vec<(String, String)>, Vec<String>) {
+/// Lists the `.m` files under `folder` with their packages, and the packages found on the way.
+/// Files and folders matching the excludes in `settings` are skipped.
+pub fn traverse_folder(
+    folder: String,
+    package: String,
+    settings: &Settings,
+) -> (Vec<(String, String)>, Vec<String>) {
     let mut packages = vec![];
     let mut files = vec![];
     if let Ok(dir) = std::fs::read_dir(folder).context(code_loc!()) {
         for entry in dir.flatten() {
+            if settings.is_excluded(&entry.path().to_string_lossy()) {
+                continue;
+            }
             if let Ok(metadata) = entry.metadata() {
                 if metadata.is_file() {
                     let name = entry.file_name().to_string_lossy().to_string();
@@ -93,9 +129,13 @@ pub fn traverse_folder(folder: String, package: String) -> (Vec<(String, String)
                             .unwrap_or(package_name);
                         packages.push(package_name.clone());
                         let (sub_files, sub_packages) =
-                            traverse_folder(path.clone(), package_name.clone());
+                            traverse_folder(path.clone(), package_name.clone(), settings);
                         packages.extend(sub_packages);
                         files.extend(sub_files);
+                    } else if name.starts_with('@') {
+                        let (sub_files, _) =
+                            traverse_folder(path.clone(), package.clone(), settings);
+                        files.extend(sub_files);
                     }
                 }
             }
@@ -104,6 +144,134 @@ pub fn traverse_folder(folder: String, package: String) -> (Vec<(String, String)
     (files, packages)
 }
 
+/// Whether `traverse_folder` on `root` reaches the file at `path`, which it does if all the
+/// folders in between are packages or classes.
+pub fn reached_by_traversal(root: &str, path: &str) -> bool {
+    let Ok(relative) = Path::new(path).strip_prefix(root) else {
+        return false;
+    };
+    let mut folders: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    folders.pop();
+    folders
+        .iter()
+        .all(|f| f.starts_with('+') || f.starts_with('@'))
+}
+
+/// Folders to scan for a workspace root. With `ScanMode::Genpath` those are the root and the
+/// subfolders `genpath` would list, minus dot folders, folders ignored by a `.gitignore` and the
+/// excludes. Otherwise it is just the root.
+pub fn workspace_folders(root: &str, settings: &Settings) -> Vec<String> {
+    if settings.workspace_scan != ScanMode::Genpath {
+        return vec![root.to_string()];
+    }
+    let mut folders = vec![];
+    let mut ignores = vec![];
+    let mut visited = vec![];
+    workspace_folders_impl(
+        Path::new(root),
+        settings,
+        &mut ignores,
+        &mut visited,
+        &mut folders,
+    );
+    folders
+}
+
+fn workspace_folders_impl(
+    folder: &Path,
+    settings: &Settings,
+    ignores: &mut Vec<(PathBuf, Vec<String>)>,
+    visited: &mut Vec<PathBuf>,
+    folders: &mut Vec<String>,
+) {
+    // Symbolic links may point back up the tree.
+    let Ok(canonical) = folder.canonicalize() else {
+        return;
+    };
+    if visited.contains(&canonical) {
+        return;
+    }
+    visited.push(canonical);
+    folders.push(folder.to_string_lossy().to_string());
+    let Ok(dir) = std::fs::read_dir(folder) else {
+        return;
+    };
+    let gitignore = std::fs::read_to_string(folder.join(".gitignore")).ok();
+    if let Some(gitignore) = &gitignore {
+        let patterns = gitignore
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(String::from)
+            .collect();
+        ignores.push((folder.to_path_buf(), patterns));
+    }
+    let mut entries: Vec<PathBuf> = dir
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .collect();
+    entries.sort();
+    for entry in entries {
+        let name = entry
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if name.starts_with('.')
+            || !genpath_includes(&name)
+            || settings.is_excluded(&entry.to_string_lossy())
+            || git_ignored(&entry, &name, ignores)
+        {
+            continue;
+        }
+        workspace_folders_impl(&entry, settings, ignores, visited, folders);
+    }
+    if gitignore.is_some() {
+        ignores.pop();
+    }
+}
+
+/// Whether a folder is ignored by the `.gitignore` files above it. Negated patterns are not
+/// supported and never match.
+fn git_ignored(folder: &Path, name: &str, ignores: &[(PathBuf, Vec<String>)]) -> bool {
+    ignores.iter().any(|(base, patterns)| {
+        let Ok(relative) = folder.strip_prefix(base) else {
+            return false;
+        };
+        let relative = format!("/{}", relative.to_string_lossy());
+        patterns.iter().any(|pattern| {
+            let pattern = pattern.trim_end_matches('/');
+            if pattern.starts_with('!') {
+                false
+            } else if pattern.contains('/') {
+                // Patterns with a slash are relative to the folder of the `.gitignore`.
+                glob_match(&format!("/{}", pattern.trim_start_matches('/')), &relative)
+            } else {
+                glob_match(pattern, name)
+            }
+        })
+    })
+}
+
+/// Package of the files in `folder`, from the `+` folders it is nested in.
+pub fn folder_package(folder: &Path) -> String {
+    let mut segments = vec![];
+    for ancestor in folder.ancestors() {
+        let Some(name) = ancestor.file_name().and_then(|n| n.to_str()) else {
+            break;
+        };
+        if let Some(segment) = name.strip_prefix('+') {
+            segments.push(segment);
+        } else if !name.starts_with('@') {
+            break;
+        }
+    }
+    segments.iter().rev().join(".")
+}
+
 pub fn parse(package: String, path: String) -> Result<(ParsedFile, Option<FunctionDefinition>)> {
     let mut parsed_file = ParsedFile::new(path.clone(), None)?;
     parsed_file.package = package.clone();
@@ -120,7 +288,11 @@ pub fn public_function(parsed_file: &mut ParsedFile) -> Option<FunctionDefinitio
         .named_children(&mut cursor)
         .find(|n| n.kind() != "comment")
     {
-        if node.kind() == "function_definition" {
+        if node.kind() == "function_definition" && class_folder(&parsed_file.path).is_some() {
+            // Method files of a class folder are not callable on their own.
+            parsed_file.is_script = false;
+            parsed_file.method_range = node.child_by_field_name("name").map(|n| n.range().into());
+        } else if node.kind() == "function_definition" {
             if let Ok(signature) = function_signature(parsed_file, node) {
                 parsed_file.is_script = false;
                 function = Some(FunctionDefinition {
@@ -133,12 +305,68 @@ pub fn public_function(parsed_file: &mut ParsedFile) -> Option<FunctionDefinitio
             }
         } else if node.kind() == "class_definition" {
             parsed_file.is_script = false;
+            parsed_file.class = class_definition(parsed_file, node).ok();
         }
     }
     drop(cursor);
     function
 }
 
+/// Name of the class whose `@` folder contains the file at `path`, if any.
+pub fn class_folder(path: &str) -> Option<String> {
+    let folder = Path::new(path).parent()?.file_name()?.to_str()?;
+    folder.strip_prefix('@').map(String::from)
+}
+
+pub fn class_definition(parsed_file: &ParsedFile, node: Node) -> Result<ClassDefinition> {
+    let name_node = node
+        .child_by_field_name("name")
+        .ok_or(code_loc!("Could not find class name"))?;
+    let name = name_node
+        .utf8_text(parsed_file.contents.as_bytes())?
+        .to_string();
+    let mut superclasses = vec![];
+    let mut cursor = node.walk();
+    if let Some(supers) = node
+        .named_children(&mut cursor)
+        .find(|n| n.kind() == "superclasses")
+    {
+        let mut cursor = supers.walk();
+        for superclass in supers
+            .named_children(&mut cursor)
+            .filter(|n| n.kind() != "comment" && n.kind() != "line_continuation")
+        {
+            let superclass = superclass.utf8_text(parsed_file.contents.as_bytes())?;
+            superclasses.push(superclass.split_whitespace().collect());
+        }
+    }
+    let mut methods = vec![];
+    for block in node
+        .named_children(&mut cursor)
+        .filter(|n| n.kind() == "methods")
+    {
+        let mut cursor = block.walk();
+        for method in block
+            .named_children(&mut cursor)
+            .filter(|n| n.kind() == "function_definition" || n.kind() == "function_signature")
+        {
+            if let Some(name) = method.child_by_field_name("name") {
+                let text = name.utf8_text(parsed_file.contents.as_bytes())?;
+                methods.push((text.to_string(), name.range().into()));
+            }
+        }
+    }
+    Ok(ClassDefinition {
+        loc: node.range().into(),
+        name,
+        name_range: name_node.range().into(),
+        path: parsed_file.path.clone(),
+        package: parsed_file.package.clone(),
+        superclasses,
+        methods,
+    })
+}
+
 pub fn function_signature(parsed_file: &ParsedFile, node: Node) -> Result<FunctionSignature> {
     let (name, name_range) = if let Some(name) = node.child_by_field_name("name") {
         let name_range = name.range();
@@ -267,3 +495,97 @@ pub fn function_signature(parsed_file: &ParsedFile, node: Node) -> Result<Functi
     };
     Ok(function)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignores(base: &str, patterns: &[&str]) -> Vec<(PathBuf, Vec<String>)> {
+        vec![(
+            PathBuf::from(base),
+            patterns.iter().map(|p| p.to_string()).collect(),
+        )]
+    }
+
+    #[test]
+    fn gitignore_names_match_at_any_depth() {
+        let ignores = ignores("/code", &["build/", "*.tmp"]);
+        assert!(git_ignored(Path::new("/code/build"), "build", &ignores));
+        assert!(git_ignored(Path::new("/code/src/build"), "build", &ignores));
+        assert!(git_ignored(Path::new("/code/src/a.tmp"), "a.tmp", &ignores));
+        assert!(!git_ignored(Path::new("/code/src"), "src", &ignores));
+    }
+
+    #[test]
+    fn gitignore_paths_are_relative_to_the_file() {
+        let ignores = ignores("/code", &["/out", "docs/build", "**/cache"]);
+        assert!(git_ignored(Path::new("/code/out"), "out", &ignores));
+        assert!(!git_ignored(Path::new("/code/src/out"), "out", &ignores));
+        assert!(git_ignored(
+            Path::new("/code/docs/build"),
+            "build",
+            &ignores
+        ));
+        assert!(!git_ignored(
+            Path::new("/code/src/docs/build"),
+            "build",
+            &ignores
+        ));
+        assert!(git_ignored(Path::new("/code/cache"), "cache", &ignores));
+        assert!(git_ignored(Path::new("/code/src/cache"), "cache", &ignores));
+    }
+
+    #[test]
+    fn gitignore_ignores_other_trees_and_negations() {
+        let ignores = ignores("/code", &["!keep", "out"]);
+        assert!(!git_ignored(Path::new("/other/out"), "out", &ignores));
+        assert!(!git_ignored(Path::new("/code/keep"), "keep", &ignores));
+    }
+
+    #[test]
+    fn traversal_reaches_packages_and_classes_only() {
+        assert!(reached_by_traversal("/code", "/code/a.m"));
+        assert!(reached_by_traversal("/code", "/code/+pkg/@Class/a.m"));
+        assert!(!reached_by_traversal("/code", "/code/utils/a.m"));
+        assert!(!reached_by_traversal("/code", "/code/+pkg/private/a.m"));
+        assert!(!reached_by_traversal("/code", "/other/a.m"));
+    }
+
+    #[test]
+    fn genpath_scan_skips_what_matlab_and_git_leave_out() {
+        let root = std::env::temp_dir().join(format!("matlab-lsp-genpath-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for folder in [
+            "utils/deep",
+            "+pkg",
+            "@Class",
+            "private",
+            ".git",
+            "build",
+            "generated",
+        ] {
+            std::fs::create_dir_all(root.join(folder)).unwrap();
+        }
+        std::fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, root.join("utils/loop")).unwrap();
+        let settings = Settings {
+            workspace_scan: ScanMode::Genpath,
+            exclude: vec!["**/generated".into()],
+            ..Settings::default()
+        };
+        let path = root.to_string_lossy().to_string();
+        let folders = workspace_folders(&path, &settings);
+        let packages = workspace_folders(&path, &Settings::default());
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            folders,
+            vec![
+                path.clone(),
+                format!("{path}/utils"),
+                format!("{path}/utils/deep")
+            ]
+        );
+        assert_eq!(packages, vec![path]);
+    }
+}
@@ -0,0 +1,840 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+
+use atomic_refcell::AtomicRefCell;
+use log::debug;
+use regex::Regex;
+use tree_sitter::{Node, Point};
+
+use crate::extractors::symbols::parent_of_kind;
+use crate::types::{
+    BodyDefinitions, ParsedFile, Range, ReachingDefinitions, ReferenceTarget, VariableDefinition,
+    Workspace,
+};
+
+#[derive(Debug, Clone)]
+enum Event {
+    /// A variable is used. Holds the index of the reference.
+    Use(usize),
+    /// Variables are removed from the workspace.
+    Clear(ClearSpec),
+    /// A variable is assigned. Holds the index of the assignment and whether the assignment
+    /// replaces the whole variable (as opposed to `x(2) = 1` or `s.a = 1`).
+    Def(usize, bool),
+}
+
+#[derive(Debug, Clone, Default)]
+struct ClearSpec {
+    delete: Vec<Regex>,
+    keep: Vec<Regex>,
+    /// Whether global variables are removed.
+    globals: bool,
+    /// Whether variables that are not global are removed.
+    locals: bool,
+}
+
+impl ClearSpec {
+    /// Parses the arguments of `clear` and `clearvars`. Other commands, and forms that are not
+    /// understood, give `None` and are taken as clearing nothing.
+    fn parse(command: &str, args: &[&str]) -> Option<ClearSpec> {
+        let command = command.to_lowercase();
+        let mut spec = ClearSpec {
+            locals: true,
+            ..Default::default()
+        };
+        let mut args = args;
+        match (command.as_str(), args.first().copied()) {
+            ("clear", Some("all")) => {
+                spec.globals = true;
+                return Some(spec);
+            }
+            ("clear", Some("variables" | "vars" | "classes" | "java")) => return Some(spec),
+            ("clear", Some("functions" | "mex" | "import")) => return None,
+            ("clear", Some("global")) => {
+                spec.globals = true;
+                spec.locals = false;
+                args = &args[1..];
+            }
+            ("clear" | "clearvars", _) => {}
+            _ => return None,
+        }
+        let mut regexp = false;
+        let mut except = false;
+        for arg in args {
+            match *arg {
+                "-regexp" => regexp = true,
+                "-except" if command == "clearvars" => {
+                    except = true;
+                    regexp = false;
+                }
+                "-global" if command == "clearvars" => {
+                    spec.globals = true;
+                    spec.locals = false;
+                }
+                _ if arg.starts_with('-') => return None,
+                _ => {
+                    let pattern = if regexp {
+                        arg.to_string()
+                    } else {
+                        format!("^{}$", regex::escape(arg).replace(r"\*", ".*"))
+                    };
+                    let regex = Regex::new(&pattern).ok()?;
+                    if except {
+                        spec.keep.push(regex);
+                    } else {
+                        spec.delete.push(regex);
+                    }
+                }
+            }
+        }
+        // `clear -regexp` without expressions clears nothing, instead of everything.
+        if regexp && !except && spec.delete.is_empty() {
+            return None;
+        }
+        Some(spec)
+    }
+
+    fn matches(&self, name: &str, is_global: bool) -> bool {
+        (if is_global { self.globals } else { self.locals })
+            && (self.delete.is_empty() || self.delete.iter().any(|r| r.is_match(name)))
+            && !self.keep.iter().any(|r| r.is_match(name))
+    }
+}
+
+struct Assignment {
+    loc: Range,
+    name: String,
+    variable: Arc<AtomicRefCell<VariableDefinition>>,
+}
+
+#[derive(Default)]
+struct FlowNode {
+    /// Location of the statement, without its blocks.
+    key: Option<Range>,
+    events: Vec<(Range, Event)>,
+    successors: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct FlowState {
+    assignments: BTreeSet<usize>,
+    undefined: BTreeSet<String>,
+}
+
+impl FlowState {
+    fn merge(&mut self, other: &FlowState) -> bool {
+        let size = self.assignments.len() + self.undefined.len();
+        self.assignments.extend(other.assignments.iter().copied());
+        self.undefined.extend(other.undefined.iter().cloned());
+        size != self.assignments.len() + self.undefined.len()
+    }
+}
+
+#[derive(Default)]
+struct CfgBuilder {
+    nodes: Vec<FlowNode>,
+    /// Maps tree-sitter node ids to the flow node that owns them.
+    owners: HashMap<usize, usize>,
+    /// Stack of enclosing loops: the loop header and the nodes that break out of it.
+    loops: Vec<(usize, Vec<usize>)>,
+    /// Nodes that return from the body.
+    returns: Vec<usize>,
+}
+
+impl CfgBuilder {
+    fn node(&mut self, owner: Node, preds: &[usize]) -> usize {
+        let index = self.join(preds);
+        self.nodes[index].key = Some(header_range(owner));
+        self.owners.insert(owner.id(), index);
+        index
+    }
+
+    /// A node owning no code, where the flow from `preds` meets.
+    fn join(&mut self, preds: &[usize]) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(FlowNode::default());
+        for pred in preds {
+            self.nodes[*pred].successors.push(index);
+        }
+        index
+    }
+
+    fn sequence(&mut self, nodes: &[Node], preds: Vec<usize>) -> Vec<usize> {
+        let mut preds = preds;
+        for node in nodes {
+            preds = self.statement(*node, preds);
+        }
+        preds
+    }
+
+    fn block_of(&mut self, node: Node, preds: Vec<usize>) -> Vec<usize> {
+        match child_of_kind(node, "block") {
+            Some(block) => self.statement(block, preds),
+            None => preds,
+        }
+    }
+
+    /// Adds the statement to the graph, returning the nodes that fall through to the next one.
+    fn statement(&mut self, node: Node, preds: Vec<usize>) -> Vec<usize> {
+        match node.kind() {
+            "comment" | "function_definition" | "class_definition" | "arguments_statement" => preds,
+            "block" => self.sequence(&named_children(node), preds),
+            "if_statement" => {
+                let head = self.node(node, &preds);
+                let mut outs = self.block_of(node, vec![head]);
+                let mut last = head;
+                let mut has_else = false;
+                for clause in named_children(node) {
+                    if clause.kind() == "elseif_clause" {
+                        last = self.node(clause, &[last]);
+                        outs.extend(self.block_of(clause, vec![last]));
+                    } else if clause.kind() == "else_clause" {
+                        outs.extend(self.block_of(clause, vec![last]));
+                        has_else = true;
+                    }
+                }
+                if !has_else {
+                    outs.push(last);
+                }
+                outs
+            }
+            "switch_statement" => {
+                let head = self.node(node, &preds);
+                let mut outs = vec![];
+                let mut last = head;
+                let mut has_otherwise = false;
+                for clause in named_children(node) {
+                    if clause.kind() == "case_clause" {
+                        last = self.node(clause, &[last]);
+                        outs.extend(self.block_of(clause, vec![last]));
+                    } else if clause.kind() == "otherwise_clause" {
+                        outs.extend(self.block_of(clause, vec![last]));
+                        has_otherwise = true;
+                    }
+                }
+                if !has_otherwise {
+                    outs.push(last);
+                }
+                outs
+            }
+            "for_statement" | "while_statement" => {
+                let head = self.node(node, &preds);
+                self.loops.push((head, vec![]));
+                for out in self.block_of(node, vec![head]) {
+                    self.nodes[out].successors.push(head);
+                }
+                let (_, breaks) = self.loops.pop().unwrap_or_default();
+                let mut outs = vec![head];
+                outs.extend(breaks);
+                outs
+            }
+            "try_statement" => {
+                let first = self.nodes.len();
+                let mut outs = self.block_of(node, preds.clone());
+                let last = self.nodes.len();
+                if let Some(catch) = child_of_kind(node, "catch_clause") {
+                    // Any statement of the try block may throw, so all of them lead to the catch.
+                    let mut sources = preds;
+                    sources.extend(first..last);
+                    let head = self.node(catch, &sources);
+                    outs.extend(self.block_of(catch, vec![head]));
+                }
+                outs
+            }
+            "break_statement" => {
+                if let Some((_, breaks)) = self.loops.last_mut() {
+                    breaks.extend(preds);
+                }
+                vec![]
+            }
+            "continue_statement" => {
+                if let Some((head, _)) = self.loops.last() {
+                    let head = *head;
+                    for pred in preds {
+                        self.nodes[pred].successors.push(head);
+                    }
+                }
+                vec![]
+            }
+            "return_statement" => {
+                self.returns.extend(preds);
+                vec![]
+            }
+            _ => vec![self.node(node, &preds)],
+        }
+    }
+
+    /// Finds the flow node owning a tree-sitter node, which is the node of its statement.
+    fn owner(&self, node: Node) -> Option<usize> {
+        let mut node = node;
+        loop {
+            if let Some(index) = self.owners.get(&node.id()) {
+                return Some(*index);
+            }
+            node = node.parent()?;
+        }
+    }
+}
+
+/// Builds a control flow graph for the script code and for every function of the file, and runs
+/// reaching definitions on it. For every variable use, this tells which assignments may reach it
+/// and whether it can be reached with the variable undefined (because of a branch, a loop or a
+/// `clear`). References that the sequential analysis could not resolve, or resolved to an
+/// assignment that cannot reach them, are retargeted to the assignments that do, and references
+/// no assignment reaches become unknown.
+pub fn reaching_definitions(parsed_file: &ParsedFile, workspace: &mut Workspace) {
+    let tree = parsed_file.tree.clone();
+    let root = tree.root_node();
+    let mut bodies = vec![root];
+    collect_kind(root, "function_definition", &mut bodies);
+    for body in bodies {
+        analyze_body(parsed_file, workspace, root, body);
+    }
+    for reference in &workspace.references {
+        let mut r_mr = reference.borrow_mut();
+        if let Some(reaching) = workspace.reaching.uses.get(&r_mr.loc) {
+            let reaches = |v: &Arc<AtomicRefCell<VariableDefinition>>| {
+                reaching.assignments.iter().any(|(_, a)| Arc::ptr_eq(a, v))
+            };
+            let retarget = match &r_mr.target {
+                ReferenceTarget::UnknownVariable => true,
+                ReferenceTarget::Variable(v) => !reaches(v),
+                _ => false,
+            };
+            if retarget {
+                if let Some((_, variable)) = reaching.assignments.last() {
+                    debug!("Retargeting reference to {} at {}", r_mr.name, r_mr.loc);
+                    r_mr.target = ReferenceTarget::Variable(Arc::clone(variable));
+                }
+            }
+        }
+    }
+}
+
+fn analyze_body(parsed_file: &ParsedFile, workspace: &mut Workspace, root: Node, body: Node) {
+    let mut builder = CfgBuilder::default();
+    let entry = builder.node(body, &[]);
+    let mut outs = if body.kind() == "function_definition" {
+        builder.block_of(body, vec![entry])
+    } else {
+        builder.sequence(&named_children(body), vec![entry])
+    };
+    outs.extend(std::mem::take(&mut builder.returns));
+    let exit = builder.join(&outs);
+    let in_body = |node: Node| body_of(node, root).is_some_and(|b| b.id() == body.id());
+    let mut assignments: Vec<Assignment> = vec![];
+    let mut names: BTreeSet<String> = BTreeSet::new();
+    let mut globals: BTreeSet<String> = BTreeSet::new();
+    for variable in &workspace.variables {
+        let v_ref = variable.borrow();
+        if v_ref.name.contains('.') {
+            continue;
+        }
+        let Some(node) = root.named_descendant_for_point_range(v_ref.loc.start, v_ref.loc.end)
+        else {
+            continue;
+        };
+        if !in_body(node) || parent_of_kind("function_output", node).is_some() {
+            continue;
+        }
+        if let Some(owner) = builder.owner(node) {
+            let kills = v_ref.script.is_some() || write_kind(node).unwrap_or(true);
+            builder.nodes[owner]
+                .events
+                .push((v_ref.loc, Event::Def(assignments.len(), kills)));
+            if v_ref.is_global {
+                globals.insert(v_ref.name.clone());
+            }
+            names.insert(v_ref.name.clone());
+            assignments.push(Assignment {
+                loc: v_ref.loc,
+                name: v_ref.name.clone(),
+                variable: Arc::clone(variable),
+            });
+        }
+    }
+    for (i, reference) in workspace.references.iter().enumerate() {
+        let r_ref = reference.borrow();
+        if r_ref.name.contains('.') {
+            continue;
+        }
+        let variable = match &r_ref.target {
+            ReferenceTarget::Variable(v) => Some(Arc::clone(v)),
+            ReferenceTarget::UnknownVariable => None,
+            _ => continue,
+        };
+        let Some(node) = root.named_descendant_for_point_range(r_ref.loc.start, r_ref.loc.end)
+        else {
+            continue;
+        };
+        if !in_body(node) {
+            continue;
+        }
+        if let Some(owner) = builder.owner(node) {
+            match (write_kind(node), variable) {
+                (Some(kills), Some(variable)) => {
+                    builder.nodes[owner]
+                        .events
+                        .push((r_ref.loc, Event::Def(assignments.len(), kills)));
+                    assignments.push(Assignment {
+                        loc: r_ref.loc,
+                        name: r_ref.name.clone(),
+                        variable,
+                    });
+                }
+                (Some(_), None) => {}
+                (None, _) => builder.nodes[owner].events.push((r_ref.loc, Event::Use(i))),
+            }
+            names.insert(r_ref.name.clone());
+        }
+    }
+    let mut commands = vec![];
+    collect_kind(body, "command", &mut commands);
+    for command in commands.into_iter().filter(|c| in_body(*c)) {
+        if let Some(spec) = clear_spec(parsed_file, command) {
+            if let Some(owner) = builder.owner(command) {
+                builder.nodes[owner]
+                    .events
+                    .push((command.range().into(), Event::Clear(spec)));
+            }
+        }
+    }
+    // Within a statement, the right side is evaluated before anything is assigned.
+    for node in builder.nodes.iter_mut() {
+        node.events.sort_by_key(|(loc, event)| {
+            let rank = match event {
+                Event::Use(_) => 0,
+                Event::Clear(_) => 1,
+                Event::Def(_, _) => 2,
+            };
+            (rank, *loc)
+        });
+    }
+    let transfer = |state: &mut FlowState, event: &Event| match event {
+        Event::Use(_) => {}
+        Event::Clear(spec) => {
+            state.assignments.retain(|a| {
+                let assignment = &assignments[*a];
+                !spec.matches(&assignment.name, assignment.variable.borrow().is_global)
+            });
+            for name in &names {
+                if spec.matches(name, globals.contains(name)) {
+                    state.undefined.insert(name.clone());
+                }
+            }
+        }
+        Event::Def(a, kills) => {
+            let name = &assignments[*a].name;
+            if *kills {
+                state.assignments.retain(|b| assignments[*b].name != *name);
+            }
+            state.assignments.insert(*a);
+            state.undefined.remove(name);
+        }
+    };
+    let nodes = builder.nodes;
+    let mut states = vec![FlowState::default(); nodes.len()];
+    states[entry].undefined = names.clone();
+    let mut worklist: VecDeque<usize> = (0..nodes.len()).collect();
+    while let Some(index) = worklist.pop_front() {
+        let mut state = states[index].clone();
+        for (_, event) in &nodes[index].events {
+            transfer(&mut state, event);
+        }
+        for successor in &nodes[index].successors {
+            if states[*successor].merge(&state) && !worklist.contains(successor) {
+                worklist.push_back(*successor);
+            }
+        }
+    }
+    let variables = |state: &FlowState| {
+        let mut variables: Vec<Arc<AtomicRefCell<VariableDefinition>>> = vec![];
+        for a in &state.assignments {
+            let variable = &assignments[*a].variable;
+            if !variables.iter().any(|v| Arc::ptr_eq(v, variable)) {
+                variables.push(Arc::clone(variable));
+            }
+        }
+        variables
+    };
+    let mut definitions = BodyDefinitions {
+        range: body.range().into(),
+        statements: vec![],
+        exit: variables(&states[exit]),
+    };
+    for (index, node) in nodes.iter().enumerate() {
+        let mut state = states[index].clone();
+        if let Some(key) = node.key.filter(|_| index != entry) {
+            definitions.statements.push((key, variables(&state)));
+        }
+        for (_, event) in &node.events {
+            if let Event::Use(r) = event {
+                let mut r_mr = workspace.references[*r].borrow_mut();
+                let reaching = ReachingDefinitions {
+                    assignments: state
+                        .assignments
+                        .iter()
+                        .map(|a| &assignments[*a])
+                        .filter(|a| a.name == r_mr.name)
+                        .map(|a| (a.loc, Arc::clone(&a.variable)))
+                        .collect(),
+                    maybe_undefined: state.undefined.contains(&r_mr.name),
+                };
+                // A variable of this body that no assignment reaches is undefined there, as after a
+                // `clear`.
+                let cleared = match &r_mr.target {
+                    ReferenceTarget::Variable(v) => {
+                        reaching.assignments.is_empty()
+                            && assignments.iter().any(|a| Arc::ptr_eq(&a.variable, v))
+                    }
+                    _ => false,
+                };
+                if cleared {
+                    debug!(
+                        "Reference to cleared variable {} at {}",
+                        r_mr.name, r_mr.loc
+                    );
+                    r_mr.target = ReferenceTarget::UnknownVariable;
+                }
+                let loc = r_mr.loc;
+                drop(r_mr);
+                workspace.reaching.uses.insert(loc, reaching);
+            }
+            transfer(&mut state, event);
+        }
+    }
+    definitions.statements.sort_by_key(|(key, _)| *key);
+    workspace.reaching.bodies.push(definitions);
+}
+
+/// Variables that may be defined at a point of the file, according to the assignments reaching
+/// the statement at the point, or the next one of the same body.
+pub fn defined_at(
+    workspace: &Workspace,
+    point: Point,
+) -> Vec<Arc<AtomicRefCell<VariableDefinition>>> {
+    let in_range = |range: &Range| range.start <= point && point <= range.end;
+    let Some(body) = workspace
+        .reaching
+        .bodies
+        .iter()
+        .filter(|b| in_range(&b.range))
+        .max_by_key(|b| b.range.start)
+    else {
+        return vec![];
+    };
+    body.statements
+        .iter()
+        .find(|(key, _)| in_range(key) || key.start > point)
+        .map_or(&body.exit, |(_, variables)| variables)
+        .clone()
+}
+
+/// Whether the node is assigned to, and if so, whether the whole variable is replaced.
+pub fn write_kind(node: Node) -> Option<bool> {
+    if node.kind() == "command_argument" {
+        return Some(true);
+    }
+    let parent = node.parent()?;
+    match parent.kind() {
+        "global_operator" | "persistent_operator" | "multioutput_variable" => return Some(true),
+        "iterator" | "catch_clause" => {
+            return parent
+                .named_child(0)
+                .filter(|c| c.id() == node.id())
+                .map(|_| true);
+        }
+        "assignment" => {
+            if let Some(left) = parent.child_by_field_name("left") {
+                if left.id() == node.id() {
+                    return Some(true);
+                }
+            }
+        }
+        _ => {}
+    }
+    let assignment = parent_of_kind("assignment", node)?;
+    let left = assignment.child_by_field_name("left")?;
+    Range::from(left.range())
+        .contains(node.start_position())
+        .then_some(false)
+}
+
+/// Reads the arguments of `clear` and `clearvars` commands.
+fn clear_spec(parsed_file: &ParsedFile, command: Node) -> Option<ClearSpec> {
+    let contents = parsed_file.contents.as_bytes();
+    let name = command.named_child(0)?.utf8_text(contents).ok()?;
+    let args: Vec<&str> = named_children(command)
+        .into_iter()
+        .filter(|c| c.kind() == "command_argument")
+        .map(|c| c.utf8_text(contents))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    ClearSpec::parse(name, &args)
+}
+
+/// The code of a statement before its first block, or the whole statement.
+fn header_range(node: Node) -> Range {
+    let end = named_children(node)
+        .into_iter()
+        .find(|c| {
+            matches!(
+                c.kind(),
+                "block"
+                    | "elseif_clause"
+                    | "else_clause"
+                    | "case_clause"
+                    | "otherwise_clause"
+                    | "catch_clause"
+            )
+        })
+        .map_or(node.end_position(), |c| c.start_position());
+    Range {
+        start: node.start_position(),
+        end,
+    }
+}
+
+/// The body a node belongs to: its function, or the root for script code. Code inside lambdas
+/// runs when the lambda is called, so it belongs to no body.
+pub fn body_of<'a>(node: Node<'a>, root: Node<'a>) -> Option<Node<'a>> {
+    let mut node = node;
+    while let Some(parent) = node.parent() {
+        match parent.kind() {
+            "lambda" => return None,
+            "function_definition" => return Some(parent),
+            _ => node = parent,
+        }
+    }
+    Some(root)
+}
+
+fn child_of_kind<'a>(node: Node<'a>, kind: &str) -> Option<Node<'a>> {
+    named_children(node).into_iter().find(|c| c.kind() == kind)
+}
+
+fn named_children(node: Node) -> Vec<Node> {
+    let mut cursor = node.walk();
+    node.named_children(&mut cursor).collect()
+}
+
+fn collect_kind<'a>(node: Node<'a>, kind: &str, nodes: &mut Vec<Node<'a>>) {
+    for child in named_children(node) {
+        if child.kind() == kind {
+            nodes.push(child);
+        }
+        collect_kind(child, kind, nodes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{open_script, Fixture};
+
+    fn analyze(test: &str, code: &str) -> Workspace {
+        open_script(test, code).workspace.clone()
+    }
+
+    /// How many assignments reach the use of a variable on a line, and whether it may be
+    /// undefined there.
+    fn reaching(workspace: &Workspace, name: &str, row: usize) -> (usize, bool) {
+        let reference = workspace
+            .references
+            .iter()
+            .map(|r| r.borrow())
+            .find(|r| r.name == name && r.loc.start.row == row)
+            .unwrap();
+        let reaching = &workspace.reaching.uses[&reference.loc];
+        (reaching.assignments.len(), reaching.maybe_undefined)
+    }
+
+    fn names(variables: &[Arc<AtomicRefCell<VariableDefinition>>]) -> Vec<String> {
+        let mut names: Vec<String> = variables.iter().map(|v| v.borrow().name.clone()).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn clear(command: &str, args: &[&str]) -> Option<ClearSpec> {
+        ClearSpec::parse(command, args)
+    }
+
+    #[test]
+    fn if_else_merges_both_branches() {
+        let code = "if c\n    x = 1;\nelse\n    x = 2;\nend\ndisp(x)\n";
+        let workspace = analyze("flow-if-else", code);
+        assert_eq!(reaching(&workspace, "x", 5), (2, false));
+    }
+
+    #[test]
+    fn if_without_else_may_skip_the_assignment() {
+        let code = "if c\n    x = 1;\nelseif d\n    x = 2;\nend\ndisp(x)\n";
+        let workspace = analyze("flow-elseif", code);
+        assert_eq!(reaching(&workspace, "x", 5), (2, true));
+    }
+
+    #[test]
+    fn break_leaves_the_loop() {
+        let code = "x = 0;\nwhile c\n    if d\n        x = 1;\n        break\n    end\n    x = 2;\nend\ndisp(x)\n";
+        let workspace = analyze("flow-break", code);
+        assert_eq!(reaching(&workspace, "x", 8), (3, false));
+    }
+
+    #[test]
+    fn continue_goes_back_to_the_loop() {
+        let code = "x = 0;\nfor i = 1:3\n    disp(x)\n    if d\n        continue\n    end\n    x = 1;\nend\n";
+        let workspace = analyze("flow-continue", code);
+        assert_eq!(reaching(&workspace, "x", 2), (2, false));
+    }
+
+    #[test]
+    fn loop_body_may_not_run() {
+        let code = "for i = 1:3\n    x = i;\nend\ndisp(x)\ndisp(i)\n";
+        let workspace = analyze("flow-loop", code);
+        assert_eq!(reaching(&workspace, "x", 3), (1, true));
+        assert_eq!(reaching(&workspace, "i", 4), (1, false));
+    }
+
+    #[test]
+    fn catch_is_reached_from_any_statement_of_try() {
+        let code =
+            "try\n    x = 1;\n    y = 2;\ncatch err\n    disp(x)\n    disp(err)\nend\ndisp(y)\n";
+        let workspace = analyze("flow-catch", code);
+        assert_eq!(reaching(&workspace, "x", 4), (1, true));
+        assert_eq!(reaching(&workspace, "err", 5), (1, false));
+        assert_eq!(reaching(&workspace, "y", 7), (1, true));
+    }
+
+    #[test]
+    fn partial_assignment_keeps_previous_one() {
+        let code = "x = 1;\nif c\n    x(2) = 2;\nend\ndisp(x)\n";
+        let workspace = analyze("flow-partial", code);
+        assert_eq!(reaching(&workspace, "x", 4), (2, false));
+    }
+
+    #[test]
+    fn clear_removes_variables() {
+        let code = "x = 1;\ny = 2;\nclear x\ndisp(x)\ndisp(y)\n";
+        let workspace = analyze("flow-clear", code);
+        assert_eq!(reaching(&workspace, "x", 3), (0, true));
+        assert_eq!(reaching(&workspace, "y", 4), (1, false));
+        let reference = workspace.references.iter().find(|r| {
+            let r = r.borrow();
+            r.name == "x" && r.loc.start.row == 3
+        });
+        assert!(matches!(
+            reference.unwrap().borrow().target,
+            ReferenceTarget::UnknownVariable
+        ));
+    }
+
+    #[test]
+    fn clear_in_a_branch_may_remove_variables() {
+        let code = "x = 1;\nif c\n    clear x\nend\ndisp(x)\n";
+        let workspace = analyze("flow-clear-branch", code);
+        assert_eq!(reaching(&workspace, "x", 4), (1, true));
+    }
+
+    #[test]
+    fn clear_in_a_loop_reaches_the_next_iteration() {
+        let code = "x = 1;\nfor i = 1:3\n    disp(x)\n    clear x\nend\n";
+        let workspace = analyze("flow-clear-loop", code);
+        assert_eq!(reaching(&workspace, "x", 2), (1, true));
+        let code = "x = 1;\nfor i = 1:3\n    disp(x)\n    clear x\n    x = 2;\nend\n";
+        let workspace = analyze("flow-clear-loop-assigned", code);
+        assert_eq!(reaching(&workspace, "x", 2), (2, false));
+    }
+
+    #[test]
+    fn clearvars_except_keeps_variables() {
+        let code = "x = 1;\ny = 2;\nclearvars -except y\ndisp(x)\ndisp(y)\n";
+        let workspace = analyze("flow-clearvars", code);
+        assert_eq!(reaching(&workspace, "x", 3), (0, true));
+        assert_eq!(reaching(&workspace, "y", 4), (1, false));
+    }
+
+    #[test]
+    fn defined_at_follows_the_flow() {
+        let code = "x = 1;\nif c\n    y = 2;\nend\nclear x\n\nz = 3;\n";
+        let workspace = analyze("flow-defined-at", code);
+        let point = |row| Point { row, column: 0 };
+        assert_eq!(names(&defined_at(&workspace, point(1))), vec!["x"]);
+        assert_eq!(names(&defined_at(&workspace, point(5))), vec!["y"]);
+        assert_eq!(names(&defined_at(&workspace, point(7))), vec!["y", "z"]);
+    }
+
+    #[test]
+    fn script_leaves_variables_it_did_not_clear() {
+        let setup = "a = 1;\nb = 2;\nclear b\n";
+        let main = "setup\ndisp(a)\ndisp(b)\n";
+        let fixture = Fixture::new("flow-script", &[("setup.m", setup), ("main.m", main)]);
+        let workspace = fixture.file("main.m").workspace.clone();
+        let target = |name: &str| {
+            let reference = workspace
+                .references
+                .iter()
+                .find(|r| r.borrow().name == name)
+                .unwrap();
+            let target = reference.borrow().target.clone();
+            target
+        };
+        assert!(matches!(target("a"), ReferenceTarget::Variable(_)));
+        assert!(matches!(target("b"), ReferenceTarget::UnknownVariable));
+    }
+
+    #[test]
+    fn clear_keywords() {
+        let spec = clear("clear", &[]).unwrap();
+        assert!(spec.matches("x", false) && !spec.matches("x", true));
+        let spec = clear("clear", &["all"]).unwrap();
+        assert!(spec.matches("x", false) && spec.matches("all", true));
+        for keyword in ["variables", "vars"] {
+            let spec = clear("clear", &[keyword]).unwrap();
+            assert!(spec.matches("x", false) && !spec.matches("x", true));
+            assert!(spec.matches(keyword, false));
+        }
+        assert!(clear("clear", &["functions"]).is_none());
+        assert!(clear("close", &["all"]).is_none());
+    }
+
+    #[test]
+    fn clear_globals() {
+        let spec = clear("clear", &["global"]).unwrap();
+        assert!(spec.matches("g", true) && !spec.matches("x", false));
+        let spec = clear("clear", &["global", "g"]).unwrap();
+        assert!(spec.matches("g", true) && !spec.matches("h", true));
+        let spec = clear("clearvars", &["-global"]).unwrap();
+        assert!(spec.matches("g", true) && !spec.matches("x", false));
+        let spec = clear("clear", &["g"]).unwrap();
+        assert!(!spec.matches("g", true));
+    }
+
+    #[test]
+    fn clear_names_and_wildcards() {
+        let spec = clear("clear", &["x", "temp*"]).unwrap();
+        assert!(spec.matches("x", false) && spec.matches("temp1", false));
+        assert!(!spec.matches("xy", false) && !spec.matches("atemp", false));
+        let spec = clear("clear", &["a.b"]).unwrap();
+        assert!(!spec.matches("axb", false));
+    }
+
+    #[test]
+    fn clear_regexp() {
+        let spec = clear("clear", &["-regexp", "^tmp", "\\d$"]).unwrap();
+        assert!(spec.matches("tmpx", false) && spec.matches("x2", false));
+        assert!(!spec.matches("x", false));
+        assert!(clear("clear", &["-regexp"]).is_none());
+        let spec = clear("clearvars", &["-regexp", "^a", "-except", "ab*"]).unwrap();
+        assert!(spec.matches("ax", false) && !spec.matches("abb", false));
+        let spec = clear("clearvars", &["-except", "-regexp", "^k"]).unwrap();
+        assert!(spec.matches("x", false) && !spec.matches("keep", false));
+        assert!(clear("clear", &["-unknown"]).is_none());
+    }
+}
@@ -4,23 +4,35 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use anyhow::Result;
 use crossbeam_channel::{Receiver, Sender};
+use itertools::Itertools;
 use log::error;
 use lsp_server::Message;
 
+use crate::features::diagnostics::{clear_diagnostics, publish_diagnostics};
 use crate::threads::db::{
-    db_delete_file_function, db_fetch_parsed_files, db_set_function, db_set_packages,
-    db_set_parsed_file,
+    db_delete_file_function, db_delete_package, db_delete_parsed_file, db_fetch_parsed_files,
+    db_get_package, db_get_settings, db_set_function, db_set_packages, db_set_parsed_file,
+};
+use crate::types::{ParsedFile, ReferenceTarget, SenderThread, ThreadMessage};
+use crate::utils::{
+    parallel_map, report_progress, send_progress_begin, send_progress_end, send_progress_report,
 };
-use crate::types::{ParsedFile, SenderThread, ThreadMessage};
-use crate::utils::{send_progress_begin, send_progress_end, send_progress_report};
 
-use super::fast::{parse, traverse_folder};
+use super::fast::{folder_package, parse, traverse_folder, workspace_folders};
+use super::matlab_path::genpath_includes;
+use super::matlab_project::find_project;
 use super::symbols::extract_symbols;
 
+/// Files parsed at once during a full scan, bounding the trees held before their analysis.
+const SCAN_CHUNK: usize = 256;
+
 pub fn full_scan(
     lsp_sender: Sender<Message>,
     sender: Sender<ThreadMessage>,
@@ -31,44 +43,76 @@ pub fn full_scan(
     let mut folders = folders;
     folders.sort();
     folders.dedup();
+    let settings = db_get_settings(&sender, &receiver, SenderThread::BackgroundWorker);
     let mut files = vec![];
     let mut packages = vec![];
-    for folder in folders {
-        let (fs, ps) = traverse_folder(folder.clone(), String::new());
+    for folder in folders.iter().flat_map(|f| workspace_folders(f, &settings)) {
+        let (fs, ps) = traverse_folder(folder, String::new(), &settings);
         files.extend(fs);
         packages.extend(ps);
     }
     db_set_packages(&sender, packages, SenderThread::BackgroundWorker)?;
-    send_progress_begin(
-        lsp_sender.clone(),
-        id,
-        "Scanning workspace.",
-        format!("0/{}", files.len()),
-    )?;
-    for (i, (pkg, path)) in files.iter().enumerate() {
-        if let Ok((file, fun)) = parse(pkg.clone(), path.clone()) {
-            db_delete_file_function(&sender, path.clone(), SenderThread::BackgroundWorker)?;
-            if let Some(fun) = fun {
-                db_set_function(&sender, Arc::new(fun), SenderThread::BackgroundWorker)?;
-            }
-            match extract_symbols(
-                sender.clone(),
-                receiver.clone(),
-                SenderThread::BackgroundWorker,
-                Arc::new(file),
-            ) {
-                Ok(file) => db_set_parsed_file(&sender, file, SenderThread::BackgroundWorker)?,
-                Err(err) => error!("Error analyzing file: {err:?}"),
+    // Shows which MATLAB Projects are loaded, if any.
+    let projects: Vec<String> = folders
+        .iter()
+        .flat_map(|f| find_project(f))
+        .map(|p| p.name)
+        .unique()
+        .collect();
+    let scanned = if projects.is_empty() {
+        String::from("workspace")
+    } else {
+        format!("MATLAB project {}", projects.join(", "))
+    };
+    let title = format!("Scanning {scanned}.");
+    send_progress_begin(lsp_sender.clone(), id, &title, format!("0/{}", files.len()))?;
+    // Files are parsed on every core, but analysed one at a time and in order, as the analysis
+    // talks to the dispatcher and sees the functions of the files before it. Each half counts
+    // for half of the progress.
+    let done = AtomicUsize::new(0);
+    let total = 2 * files.len();
+    // When each file was analysed, and each script by name, to find the files analysed before
+    // the scripts they call.
+    let mut order: HashMap<String, usize> = HashMap::new();
+    let mut scripts: HashMap<String, usize> = HashMap::new();
+    for chunk in files.chunks(SCAN_CHUNK) {
+        let parsed = parallel_map(chunk, |(pkg, path)| {
+            let result = parse(pkg.clone(), path.clone());
+            report_progress(&lsp_sender, id, &title, &done, total);
+            result
+        });
+        for ((_, path), parsed) in chunk.iter().zip(parsed) {
+            if let Ok((file, fun)) = parsed {
+                db_delete_file_function(&sender, path.clone(), SenderThread::BackgroundWorker)?;
+                if let Some(fun) = fun {
+                    db_set_function(&sender, Arc::new(fun), SenderThread::BackgroundWorker)?;
+                }
+                match extract_symbols(
+                    sender.clone(),
+                    receiver.clone(),
+                    SenderThread::BackgroundWorker,
+                    Arc::new(file),
+                ) {
+                    Ok(file) => {
+                        let index = order.len();
+                        order.insert(file.path.clone(), index);
+                        if file.is_script {
+                            scripts.insert(qualified_name(&file), index);
+                        }
+                        db_set_parsed_file(&sender, file, SenderThread::BackgroundWorker)?
+                    }
+                    Err(err) => error!("Error analyzing file: {err:?}"),
+                }
             }
+            report_progress(&lsp_sender, id, &title, &done, total);
         }
-        send_progress_report(
-            lsp_sender.clone(),
-            id,
-            "Scanning workspace.",
-            (100 * i / files.len()).try_into()?,
-        )?;
     }
-    send_progress_end(lsp_sender.clone(), id, "Finished scanning workspace.")?;
+    analyse_script_callers(&sender, &receiver, &scripts, &order)?;
+    send_progress_end(
+        lsp_sender.clone(),
+        id,
+        format!("Finished scanning {scanned}."),
+    )?;
     sender.send(ThreadMessage {
         sender: SenderThread::BackgroundWorker,
         payload: crate::types::MessagePayload::ScanOpen,
@@ -101,7 +145,14 @@ pub fn scan_open(
                 SenderThread::Handler,
                 Arc::clone(file),
             )?;
-            db_set_parsed_file(&sender, file, SenderThread::Handler)?;
+            db_set_parsed_file(&sender, Arc::clone(&file), SenderThread::Handler)?;
+            publish_diagnostics(
+                &lsp_sender,
+                &sender,
+                &receiver,
+                SenderThread::Handler,
+                &file,
+            )?;
             send_progress_report(
                 lsp_sender.clone(),
                 id,
@@ -113,3 +164,345 @@ pub fn scan_open(
     }
     Ok(())
 }
+
+/// Updates the index after files or folders were created, changed or deleted on disk, without
+/// scanning the whole workspace. The files whose references may resolve differently are analysed
+/// again.
+pub fn rescan_files(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    changed: Vec<String>,
+    deleted: Vec<String>,
+) -> Result<()> {
+    let thread = SenderThread::BackgroundWorker;
+    let stored = db_fetch_parsed_files(&sender, &receiver, thread.clone()).unwrap_or_default();
+    let settings = db_get_settings(&sender, &receiver, thread.clone());
+    // Names that may now resolve to something else, and files whose definitions changed.
+    let mut names: Vec<String> = vec![];
+    let mut paths: Vec<String> = vec![];
+    let mut parsed = vec![];
+    for path in deleted {
+        let folder = format!("{path}/");
+        for file in stored.values() {
+            // Open files are kept, the editor still holds their contents.
+            if file.open || (file.path != path && !file.path.starts_with(&folder)) {
+                continue;
+            }
+            names.push(qualified_name(file));
+            paths.push(file.path.clone());
+            db_delete_parsed_file(&sender, file.path.clone(), thread.clone())?;
+            db_delete_file_function(&sender, file.path.clone(), thread.clone())?;
+            clear_diagnostics(&lsp_sender, &file.path)?;
+        }
+        let folder = Path::new(&path);
+        if folder
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('+'))
+        {
+            let package = folder_package(folder);
+            db_delete_package(&sender, package.clone(), thread.clone())?;
+            names.push(package);
+        }
+        if folder.file_name().is_some_and(|n| n == "private") {
+            for file in stored
+                .values()
+                .filter(|f| f.path.starts_with(&format!("{path}/")))
+            {
+                names.push(file.name.clone());
+            }
+        }
+    }
+    for path in changed {
+        if settings.is_excluded(&path) {
+            continue;
+        }
+        let folder = Path::new(&path);
+        let (files, mut packages) = if folder.is_dir() {
+            let package = folder_package(folder);
+            // Package and class folders are never scanned like `genpath` does.
+            let folders = if package.is_empty()
+                && folder
+                    .file_name()
+                    .is_some_and(|n| genpath_includes(&n.to_string_lossy()))
+            {
+                workspace_folders(&path, &settings)
+            } else {
+                vec![path.clone()]
+            };
+            let mut files = vec![];
+            let mut packages = vec![package.clone()];
+            for folder in folders {
+                let (fs, ps) = traverse_folder(folder, package.clone(), &settings);
+                files.extend(fs);
+                packages.extend(ps);
+            }
+            (files, packages)
+        } else if path.ends_with(".m") {
+            let package = folder.parent().map(folder_package).unwrap_or_default();
+            (vec![(package.clone(), path.clone())], vec![package])
+        } else {
+            continue;
+        };
+        // Parent packages may be new as well.
+        for package in packages.clone() {
+            let mut segments: Vec<&str> = package.split('.').collect();
+            while segments.pop().is_some() && !segments.is_empty() {
+                packages.push(segments.join("."));
+            }
+        }
+        packages.retain(|p| {
+            !p.is_empty()
+                && !db_get_package(&sender, &receiver, p.clone(), thread.clone()).contains(p)
+        });
+        packages.sort();
+        packages.dedup();
+        names.extend(packages.clone());
+        db_set_packages(&sender, packages, thread.clone())?;
+        for (pkg, path) in files {
+            if stored.get(&path).is_some_and(|f| f.open) {
+                continue;
+            }
+            let file_path = Path::new(&path);
+            if file_path
+                .parent()
+                .and_then(|p| p.file_name())
+                .is_some_and(|n| n == "private")
+            {
+                if let Some(stem) = file_path.file_stem() {
+                    names.push(stem.to_string_lossy().to_string());
+                }
+                continue;
+            }
+            let Ok((file, function)) = parse(pkg, path.clone()) else {
+                continue;
+            };
+            names.push(qualified_name(&file));
+            paths.push(path.clone());
+            db_delete_file_function(&sender, path.clone(), thread.clone())?;
+            if let Some(function) = function {
+                db_set_function(&sender, Arc::new(function), thread.clone())?;
+            }
+            parsed.push(file);
+        }
+    }
+    // The new files are only analysed once all their functions are known, as they may call each
+    // other.
+    for file in parsed {
+        match extract_symbols(
+            sender.clone(),
+            receiver.clone(),
+            thread.clone(),
+            Arc::new(file),
+        ) {
+            Ok(file) => db_set_parsed_file(&sender, file, thread.clone())?,
+            Err(err) => error!("Error analyzing file: {err:?}"),
+        }
+    }
+    analyse_dependents(lsp_sender, sender, receiver, names, paths)
+}
+
+/// Analyses again the files referencing one of the names, or a function or script defined in one
+/// of the paths, as their references may now resolve differently.
+pub fn analyse_dependents(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    names: Vec<String>,
+    paths: Vec<String>,
+) -> Result<()> {
+    if names.is_empty() && paths.is_empty() {
+        return Ok(());
+    }
+    let thread = SenderThread::BackgroundWorker;
+    let stored = db_fetch_parsed_files(&sender, &receiver, thread.clone()).unwrap_or_default();
+    for file in stored.values() {
+        let depends = file.workspace.references.iter().any(|r| {
+            let r_ref = r.borrow();
+            match &r_ref.target {
+                ReferenceTarget::Function(f) => {
+                    paths.contains(&f.borrow().path) || names.contains(&r_ref.name)
+                }
+                ReferenceTarget::Script(p) => paths.contains(p) || names.contains(&r_ref.name),
+                ReferenceTarget::Variable(_) => false,
+                _ => names.contains(&r_ref.name),
+            }
+        });
+        if !depends || paths.contains(&file.path) {
+            continue;
+        }
+        let file = if file.open {
+            Arc::clone(file)
+        } else {
+            match parse(file.package.clone(), file.path.clone()) {
+                Ok((file, _)) => Arc::new(file),
+                Err(_) => continue,
+            }
+        };
+        match extract_symbols(sender.clone(), receiver.clone(), thread.clone(), file) {
+            Ok(file) => {
+                db_set_parsed_file(&sender, Arc::clone(&file), thread.clone())?;
+                if file.open {
+                    publish_diagnostics(&lsp_sender, &sender, &receiver, thread.clone(), &file)?;
+                }
+            }
+            Err(err) => error!("Error analyzing file: {err:?}"),
+        }
+    }
+    Ok(())
+}
+
+/// Analyses again the files that were analysed before a script they call, as they could not see
+/// the variables the script defines. Callers of scripts whose variables changed by that are
+/// analysed again too, until a chain of scripts calling each other is settled.
+fn analyse_script_callers(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    scripts: &HashMap<String, usize>,
+    order: &HashMap<String, usize>,
+) -> Result<()> {
+    let thread = SenderThread::BackgroundWorker;
+    let stored = db_fetch_parsed_files(sender, receiver, thread.clone()).unwrap_or_default();
+    let mut late: Vec<Arc<ParsedFile>> = stored
+        .values()
+        .filter(|f| {
+            let analysed = order.get(&f.path).copied().unwrap_or_default();
+            calls_script(f, |name| scripts.get(name).is_some_and(|i| *i > analysed))
+        })
+        .map(Arc::clone)
+        .collect();
+    for _ in 0..scripts.len() {
+        if late.is_empty() {
+            break;
+        }
+        let mut changed: Vec<String> = vec![];
+        for file in late {
+            let before = variable_names(&file);
+            match extract_symbols(sender.clone(), receiver.clone(), thread.clone(), file) {
+                Ok(file) => {
+                    if file.is_script && variable_names(&file) != before {
+                        changed.push(qualified_name(&file));
+                    }
+                    db_set_parsed_file(sender, file, thread.clone())?;
+                }
+                Err(err) => error!("Error analyzing file: {err:?}"),
+            }
+        }
+        let stored = db_fetch_parsed_files(sender, receiver, thread.clone()).unwrap_or_default();
+        late = stored
+            .values()
+            .filter(|f| calls_script(f, |name| changed.iter().any(|c| c == name)))
+            .map(Arc::clone)
+            .collect();
+    }
+    Ok(())
+}
+
+/// Whether the file calls one of the scripts, including the calls that did not resolve because
+/// the script was not analysed yet.
+fn calls_script(file: &ParsedFile, is_script: impl Fn(&str) -> bool) -> bool {
+    file.workspace.references.iter().any(|r| {
+        let r_ref = r.borrow();
+        matches!(
+            r_ref.target,
+            ReferenceTarget::Script(_)
+                | ReferenceTarget::UnknownVariable
+                | ReferenceTarget::UnknownFunction
+        ) && is_script(&r_ref.name)
+    })
+}
+
+fn variable_names(file: &ParsedFile) -> Vec<String> {
+    file.workspace
+        .variables
+        .iter()
+        .map(|v| v.borrow().name.clone())
+        .collect()
+}
+
+/// Name other files call the file by.
+pub fn qualified_name(file: &ParsedFile) -> String {
+    if file.package.is_empty() {
+        file.name.clone()
+    } else {
+        format!("{}.{}", file.package, file.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+    use crossbeam_channel::unbounded;
+
+    fn target(fixture: &Fixture, name: &str) -> ReferenceTarget {
+        let file = fixture.file("main.m");
+        let reference = file
+            .workspace
+            .references
+            .iter()
+            .find(|r| r.borrow().name == name)
+            .unwrap();
+        let target = reference.borrow().target.clone();
+        target
+    }
+
+    #[test]
+    fn created_and_deleted_files_update_their_callers() {
+        let fixture = Fixture::new("full-rescan", &[("main.m", "helper();\n")]);
+        let (lsp_sender, _client) = unbounded();
+        let rescan = |changed, deleted| {
+            rescan_files(
+                lsp_sender.clone(),
+                fixture.sender.clone(),
+                fixture.receiver.clone(),
+                changed,
+                deleted,
+            )
+            .unwrap()
+        };
+        assert!(matches!(
+            target(&fixture, "helper"),
+            ReferenceTarget::UnknownFunction
+        ));
+
+        let helper = fixture.path("helper.m");
+        std::fs::write(&helper, "function helper\nend\n").unwrap();
+        rescan(vec![helper.clone()], vec![]);
+        assert!(matches!(
+            target(&fixture, "helper"),
+            ReferenceTarget::Function(_)
+        ));
+
+        std::fs::remove_file(&helper).unwrap();
+        rescan(vec![], vec![helper.clone()]);
+        assert!(matches!(
+            target(&fixture, "helper"),
+            ReferenceTarget::UnknownFunction
+        ));
+        let files =
+            db_fetch_parsed_files(&fixture.sender, &fixture.receiver, SenderThread::Handler);
+        assert!(!files.unwrap().contains_key(&helper));
+    }
+
+    #[test]
+    fn genpath_scan_resolves_calls_into_subfolders() {
+        let settings = crate::settings::Settings {
+            workspace_scan: crate::settings::ScanMode::Genpath,
+            ..Default::default()
+        };
+        let fixture = Fixture::with_settings(
+            "full-genpath",
+            &[
+                ("main.m", "helper();\n"),
+                ("utils/helper.m", "function helper\nend\n"),
+            ],
+            &[],
+            settings,
+        );
+        assert!(matches!(
+            target(&fixture, "helper"),
+            ReferenceTarget::Function(_)
+        ));
+    }
+}
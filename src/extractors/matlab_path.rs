@@ -0,0 +1,441 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::{debug, error};
+use tree_sitter::Node;
+
+use crate::types::ParsedFile;
+
+/// Files of a workspace root that set up the MATLAB path.
+pub const PATH_FILES: [&str; 2] = ["pathdef.m", "startup.m"];
+
+/// Stands for the parts of a value that can not be known without running MATLAB, like
+/// `matlabroot`. Folders containing it are dropped.
+const UNKNOWN: &str = "\0";
+
+/// Folders in the `MATLABPATH` environment variable.
+pub fn env_path() -> Vec<String> {
+    std::env::var("MATLABPATH")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|p| !p.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Folders added to the path by the `pathdef.m` and `startup.m` of each workspace root, found by
+/// evaluating the calls to `addpath` and `path` in them. Only what can be known without running
+/// MATLAB is evaluated: literals, variables, concatenation and a few functions, like `genpath`,
+/// `fullfile` and `fileparts(mfilename('fullpath'))`. Folders that do not exist are dropped.
+pub fn startup_path(roots: &[String]) -> Vec<String> {
+    let mut folders: Vec<String> = vec![];
+    for root in roots {
+        for name in PATH_FILES {
+            let path = Path::new(root).join(name);
+            if !path.is_file() {
+                continue;
+            }
+            match evaluate(&path) {
+                Ok(fs) => folders.extend(fs),
+                Err(err) => error!("Could not evaluate {}: {err}", path.display()),
+            }
+        }
+    }
+    let mut result: Vec<String> = vec![];
+    for folder in folders {
+        if Path::new(&folder).is_dir() && !result.contains(&folder) {
+            result.push(folder);
+        }
+    }
+    result
+}
+
+/// The folder and all its subfolders, except for the ones MATLAB never puts in the path: `@`
+/// class folders, `+` package folders, `private` and `resources`.
+pub fn genpath(folder: &Path) -> Vec<String> {
+    let mut folders = vec![];
+    let mut visited = vec![];
+    genpath_impl(folder, &mut folders, &mut visited);
+    folders
+}
+
+fn genpath_impl(folder: &Path, folders: &mut Vec<String>, visited: &mut Vec<PathBuf>) {
+    // Symbolic links may point back up the tree.
+    let Ok(canonical) = folder.canonicalize() else {
+        return;
+    };
+    if visited.contains(&canonical) {
+        return;
+    }
+    visited.push(canonical);
+    folders.push(folder.to_string_lossy().to_string());
+    let Ok(dir) = std::fs::read_dir(folder) else {
+        return;
+    };
+    let mut entries: Vec<PathBuf> = dir.flatten().map(|e| e.path()).collect();
+    entries.sort();
+    for entry in entries {
+        let name = entry
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if entry.is_dir() && genpath_includes(&name) {
+            genpath_impl(&entry, folders, visited);
+        }
+    }
+}
+
+/// Whether `genpath` lists a subfolder with this name.
+pub fn genpath_includes(name: &str) -> bool {
+    !name.starts_with('@') && !name.starts_with('+') && name != "private" && name != "resources"
+}
+
+fn evaluate(path: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    let tree = ParsedFile::ts_parse(&contents)?;
+    let mut evaluator = Evaluator {
+        contents: &contents,
+        path: path.to_path_buf(),
+        variables: HashMap::new(),
+        folders: vec![],
+    };
+    let root = tree.root_node();
+    evaluator.visit(root);
+    // `pathdef.m` returns the path instead of setting it.
+    let mut cursor = root.walk();
+    let output = root
+        .named_children(&mut cursor)
+        .find(|n| n.kind() == "function_definition")
+        .and_then(|f| {
+            let mut cursor = f.walk();
+            let output = f
+                .named_children(&mut cursor)
+                .find(|c| c.kind() == "function_output");
+            output
+        })
+        .and_then(|o| o.named_child(0))
+        .filter(|o| o.kind() == "identifier");
+    if let Some(value) = output.and_then(|o| evaluator.variables.get(&evaluator.text(o)).cloned()) {
+        evaluator.add_folders(&value);
+    }
+    debug!(
+        "Folders added by {}: {:?}",
+        path.display(),
+        evaluator.folders
+    );
+    Ok(evaluator.folders)
+}
+
+struct Evaluator<'a> {
+    contents: &'a str,
+    path: PathBuf,
+    variables: HashMap<String, String>,
+    folders: Vec<String>,
+}
+
+impl Evaluator<'_> {
+    /// Runs the statements under `node`, in order.
+    fn visit(&mut self, node: Node) {
+        match node.kind() {
+            "assignment" => self.assign(node),
+            "function_call" | "command" => {
+                self.eval(node);
+            }
+            _ => {
+                let mut cursor = node.walk();
+                for child in node.named_children(&mut cursor) {
+                    self.visit(child);
+                }
+            }
+        }
+    }
+
+    fn assign(&mut self, node: Node) {
+        let (Some(left), Some(right)) = (
+            node.child_by_field_name("left"),
+            node.child_by_field_name("right"),
+        ) else {
+            return;
+        };
+        if left.kind() == "identifier" {
+            let value = self.eval(right);
+            self.variables.insert(self.text(left), value);
+        } else if left.kind() == "multioutput_variable" {
+            let values = self.eval_outputs(right);
+            let mut cursor = left.walk();
+            for (i, output) in left.named_children(&mut cursor).enumerate() {
+                if output.kind() == "identifier" {
+                    let value = values.get(i).cloned().unwrap_or(UNKNOWN.into());
+                    self.variables.insert(self.text(output), value);
+                }
+            }
+        } else {
+            self.eval(right);
+        }
+    }
+
+    /// All the outputs of an expression. Only `fileparts` has more than one.
+    fn eval_outputs(&mut self, node: Node) -> Vec<String> {
+        if node.kind() == "function_call" && self.name(node) == "fileparts" {
+            let arguments = self.arguments(node);
+            if let Some(path) = arguments.first().filter(|a| !a.contains(UNKNOWN)) {
+                let (folder, file) = path.rsplit_once('/').unwrap_or(("", path));
+                let (name, ext) = match file.rfind('.') {
+                    Some(i) => (&file[..i], &file[i..]),
+                    None => (file, ""),
+                };
+                return vec![folder.into(), name.into(), ext.into()];
+            }
+            return vec![];
+        }
+        vec![self.eval(node)]
+    }
+
+    fn eval(&mut self, node: Node) -> String {
+        match node.kind() {
+            "string" => {
+                let text = self.text(node);
+                let quote = if text.starts_with('"') { "\"" } else { "'" };
+                let inner = text
+                    .strip_prefix(quote)
+                    .and_then(|t| t.strip_suffix(quote))
+                    .unwrap_or(&text);
+                inner.replace(&quote.repeat(2), quote)
+            }
+            "identifier" => {
+                let name = self.text(node);
+                match self.variables.get(&name) {
+                    Some(value) => value.clone(),
+                    None => self.call(&name, vec![]),
+                }
+            }
+            "parenthesis" => {
+                let mut cursor = node.walk();
+                let child = node.named_children(&mut cursor).next();
+                child.map(|c| self.eval(c)).unwrap_or(UNKNOWN.into())
+            }
+            "matrix" | "row" => {
+                let mut value = String::new();
+                let mut cursor = node.walk();
+                let children: Vec<Node> = node
+                    .named_children(&mut cursor)
+                    .filter(|c| !c.is_extra())
+                    .collect();
+                for child in children {
+                    value += &self.eval(child);
+                }
+                value
+            }
+            "binary_operator" => {
+                let mut cursor = node.walk();
+                let is_plus = node
+                    .children(&mut cursor)
+                    .any(|c| !c.is_named() && c.kind() == "+");
+                match (
+                    node.child_by_field_name("left"),
+                    node.child_by_field_name("right"),
+                ) {
+                    (Some(left), Some(right)) if is_plus => self.eval(left) + &self.eval(right),
+                    _ => UNKNOWN.into(),
+                }
+            }
+            "function_call" => {
+                let name = self.name(node);
+                let arguments = self.arguments(node);
+                self.call(&name, arguments)
+            }
+            "command" => {
+                let mut cursor = node.walk();
+                let children: Vec<Node> = node.named_children(&mut cursor).collect();
+                let name = children
+                    .iter()
+                    .find(|c| c.kind() == "command_name")
+                    .map(|c| self.text(*c))
+                    .unwrap_or_default();
+                let arguments = children
+                    .iter()
+                    .filter(|c| c.kind() == "command_argument")
+                    .map(|c| self.text(*c))
+                    .collect();
+                self.call(&name, arguments)
+            }
+            _ => UNKNOWN.into(),
+        }
+    }
+
+    /// Evaluates a call to a function, recording the folders added to the path.
+    fn call(&mut self, name: &str, arguments: Vec<String>) -> String {
+        let first = || arguments.first().cloned().unwrap_or_default();
+        match name {
+            "addpath" | "path" => {
+                for argument in arguments.iter().filter(|a| !a.starts_with('-')) {
+                    self.add_folders(argument);
+                }
+                UNKNOWN.into()
+            }
+            "genpath" if !first().contains(UNKNOWN) => {
+                genpath(Path::new(&self.absolute(&first()))).join(":")
+            }
+            "fullfile" => {
+                let parts: Vec<&str> = arguments
+                    .iter()
+                    .map(|a| a.as_str())
+                    .filter(|a| !a.is_empty())
+                    .collect();
+                let mut path = parts.join("/");
+                while path.contains("//") {
+                    path = path.replace("//", "/");
+                }
+                path
+            }
+            "fileparts" if !first().contains(UNKNOWN) => first()
+                .rsplit_once('/')
+                .map(|(folder, _)| folder.to_string())
+                .unwrap_or_default(),
+            "mfilename" => {
+                if first() == "fullpath" {
+                    self.path.with_extension("").to_string_lossy().to_string()
+                } else {
+                    self.path
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                }
+            }
+            // MATLAB starts in the folder of `startup.m` when it picks it up.
+            "pwd" => self
+                .path
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or(UNKNOWN.into()),
+            "pathsep" => ":".into(),
+            "filesep" => "/".into(),
+            "strcat" | "horzcat" => arguments.concat(),
+            "char" | "string" | "strtrim" | "deblank" => first(),
+            _ => UNKNOWN.into(),
+        }
+    }
+
+    /// Records the folders of a path, as given to `addpath`.
+    fn add_folders(&mut self, path: &str) {
+        for folder in path.split(':') {
+            if !folder.is_empty() && !folder.contains(UNKNOWN) {
+                let folder = self.absolute(folder.trim_end_matches('/'));
+                self.folders.push(folder);
+            }
+        }
+    }
+
+    /// Relative folders are relative to the folder MATLAB runs the file in, which is its own.
+    fn absolute(&self, folder: &str) -> String {
+        let relative = folder.trim_start_matches("./");
+        match self.path.parent() {
+            Some(parent) if !folder.starts_with('/') => match relative {
+                "." => parent.to_string_lossy().to_string(),
+                _ => parent.join(relative).to_string_lossy().to_string(),
+            },
+            _ => folder.to_string(),
+        }
+    }
+
+    fn name(&self, node: Node) -> String {
+        node.child_by_field_name("name")
+            .map(|n| self.text(n))
+            .unwrap_or_default()
+    }
+
+    fn arguments(&mut self, node: Node) -> Vec<String> {
+        let mut cursor = node.walk();
+        let Some(arguments) = node
+            .named_children(&mut cursor)
+            .find(|n| n.kind() == "arguments")
+        else {
+            return vec![];
+        };
+        let mut cursor = arguments.walk();
+        let children: Vec<Node> = arguments.named_children(&mut cursor).collect();
+        children.into_iter().map(|c| self.eval(c)).collect()
+    }
+
+    fn text(&self, node: Node) -> String {
+        node.utf8_text(self.contents.as_bytes())
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates `contents` as the file `name` of a new folder holding the given subfolders.
+    fn evaluate_in(
+        test: &str,
+        name: &str,
+        contents: &str,
+        folders: &[&str],
+    ) -> (String, Vec<String>) {
+        let root = std::env::temp_dir().join(format!("matlab-lsp-{test}-{}", std::process::id()));
+        for folder in folders {
+            std::fs::create_dir_all(root.join(folder)).unwrap();
+        }
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join(name);
+        std::fs::write(&path, contents).unwrap();
+        let result = evaluate(&path).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        (root.to_string_lossy().to_string(), result)
+    }
+
+    #[test]
+    fn evaluates_genpath_of_the_file_folder() {
+        let code =
+            "root = fileparts(mfilename('fullpath'));\naddpath(genpath(fullfile(root, 'src')));\n";
+        let folders = ["src/utils", "src/+pkg", "src/private"];
+        let (root, result) = evaluate_in("genpath", "startup.m", code, &folders);
+        assert_eq!(
+            result,
+            vec![format!("{root}/src"), format!("{root}/src/utils")]
+        );
+    }
+
+    #[test]
+    fn resolves_relative_folders_against_the_file() {
+        let code = "addpath('lib', ['tools' filesep 'bin'])\naddpath .\n";
+        let (root, result) = evaluate_in("relative", "startup.m", code, &[]);
+        assert_eq!(
+            result,
+            vec![format!("{root}/lib"), format!("{root}/tools/bin"), root]
+        );
+    }
+
+    #[test]
+    fn evaluates_pathdef_output() {
+        let code = "function p = pathdef\np = [...\n    matlabroot, '/toolbox/matlab/general:', ...\n    '/opt/lib:', ...\n];\np = [userpath, pathsep, p];\n";
+        let (_, result) = evaluate_in("pathdef", "pathdef.m", code, &[]);
+        assert_eq!(result, vec![String::from("/opt/lib")]);
+    }
+
+    #[test]
+    fn startup_path_keeps_existing_folders_once() {
+        let root = std::env::temp_dir().join(format!("matlab-lsp-startup-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("startup.m"), "addpath('lib', 'missing')\n").unwrap();
+        std::fs::write(
+            root.join("pathdef.m"),
+            "function p = pathdef\np = [fileparts(mfilename('fullpath')) '/lib:'];\n",
+        )
+        .unwrap();
+        let roots = [root.to_string_lossy().to_string()];
+        let result = startup_path(&roots);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(result, vec![format!("{}/lib", roots[0])]);
+    }
+}
@@ -0,0 +1,102 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::path::{Path, PathBuf};
+
+use log::info;
+use regex::Regex;
+
+/// Folder holding the metadata of a MATLAB Project, relative to the project root.
+pub const PROJECT_METADATA: &str = "resources/project";
+
+/// A MATLAB Project, as saved by MATLAB next to its `.prj` file.
+#[derive(Debug, Clone)]
+pub struct MatlabProject {
+    pub name: String,
+    /// Folders the project adds to the path.
+    pub path: Vec<String>,
+}
+
+/// Finds the MATLAB Project containing a workspace root. The project root is the closest
+/// folder, starting at the workspace root and going up, with a `.prj` file and the project
+/// metadata folder.
+pub fn find_project(root: &str) -> Option<MatlabProject> {
+    let root = Path::new(root)
+        .ancestors()
+        .find(|f| f.join(PROJECT_METADATA).is_dir() && prj_file(f).is_some())?;
+    let metadata = root.join(PROJECT_METADATA);
+    let name = read_attribute(&metadata.join("ProjectData.type.Info.xml"), "Name")
+        .into_iter()
+        .next()
+        .filter(|n| !n.is_empty())
+        .or_else(|| {
+            prj_file(root).and_then(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+        })
+        .unwrap_or_default();
+    let mut locations = vec![];
+    path_entries(&metadata, &mut locations);
+    let mut path: Vec<String> = vec![];
+    for location in locations {
+        // Locations are relative to the project root, an empty one is the root itself.
+        let folder = root.join(location.trim_start_matches('/'));
+        let folder = folder.to_string_lossy().trim_end_matches('/').to_string();
+        if Path::new(&folder).is_dir() && !path.contains(&folder) {
+            path.push(folder);
+        }
+    }
+    info!("Found MATLAB project {name} at {}", root.display());
+    Some(MatlabProject { name, path })
+}
+
+fn prj_file(folder: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(folder)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .find(|p| p.is_file() && p.extension().is_some_and(|e| e == "prj"))
+}
+
+/// Collects the locations of the project path entries, which MATLAB saves one per file in the
+/// `ProjectPath` folders of the metadata.
+fn path_entries(folder: &Path, locations: &mut Vec<String>) {
+    let Ok(dir) = std::fs::read_dir(folder) else {
+        return;
+    };
+    let in_path = folder
+        .file_name()
+        .is_some_and(|n| n.to_string_lossy().contains("ProjectPath"));
+    let mut entries: Vec<PathBuf> = dir.flatten().map(|e| e.path()).collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            path_entries(&entry, locations);
+        } else if in_path && entry.extension().is_some_and(|e| e == "xml") {
+            locations.extend(read_attribute(&entry, "Location"));
+        }
+    }
+}
+
+/// Values of an XML attribute anywhere in a file.
+fn read_attribute(path: &Path, attribute: &str) -> Vec<String> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return vec![];
+    };
+    let Ok(regex) = Regex::new(&format!(r#"\b{attribute}="([^"]*)""#)) else {
+        return vec![];
+    };
+    regex
+        .captures_iter(&contents)
+        .map(|c| unescape(&c[1]))
+        .collect()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
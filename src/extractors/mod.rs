@@ -4,6 +4,10 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub mod cache;
 pub mod fast;
+pub mod flow;
 pub mod full;
+pub mod matlab_path;
+pub mod matlab_project;
 pub mod symbols;
@@ -10,6 +10,7 @@ use std::time::Instant;
 
 use crate::code_loc;
 use crate::extractors::fast::{function_signature, public_function};
+use crate::extractors::flow::reaching_definitions;
 use crate::threads::db::{
     db_fetch_functions, db_get_function, db_get_package, db_get_script, db_set_function,
 };
@@ -58,14 +59,15 @@ pub fn extract_symbols(
             captures.push((capture_name, node))
         }
     }
-    captures.sort_by(|(_, n1), (_, n2)| n1.start_byte().cmp(&n2.start_byte()));
-    let ws = analyze_impl(
+    captures.sort_by_key(|(_, n)| n.start_byte());
+    let mut ws = analyze_impl(
         sender.clone(),
         receiver.clone(),
         thread,
         &captures,
         &mut pf_mr,
     )?;
+    reaching_definitions(&pf_mr, &mut ws);
     pf_mr.workspace = ws;
     pf_mr.dump_contents();
     info!("Analysis finished: {}", pf_mr.path.as_str());
@@ -233,6 +235,17 @@ fn analyze_impl(
                     if let Some(v) = vs.first() {
                         let vref = Arc::new(AtomicRefCell::new(v.clone()));
                         workspace.references.push(vref);
+                    } else if let Some(ms) =
+                        db_get_script(&sender, &receiver, name.clone(), thread.clone())
+                    {
+                        let vref = Reference {
+                            loc: node.range().into(),
+                            name,
+                            target: ReferenceTarget::Script(ms.path.clone()),
+                        };
+                        let vref = Arc::new(AtomicRefCell::new(vref));
+                        workspace.references.push(vref);
+                        def_script_vars(&ms, &mut workspace, &scopes, &mut functions, *node);
                     } else {
                         let vref = Reference {
                             loc: node.range().into(),
@@ -318,92 +331,7 @@ fn command_capture_impl(
             }
         }
         "clear" | "clearvars" => {
-            debug!("It's a clear.");
-            if let Some(parent) = node.parent() {
-                let mut cursor = parent.walk();
-                let args: Vec<Node> = parent
-                    .named_children(&mut cursor)
-                    .filter(|n| n.kind() == "command_argument")
-                    .collect();
-                if args.is_empty() {
-                    for (_, ws) in scopes.iter().flat_map(|s| functions.get(s)) {
-                        for var in &ws.variables {
-                            let mut var_mr = var.borrow_mut();
-                            if var_mr.cleared == 0 && !var_mr.is_global {
-                                var_mr.cleared = node.range().start_point.row;
-                            }
-                        }
-                    }
-                    if scopes.is_empty() {
-                        for var in &workspace.variables {
-                            let mut var_mr = var.borrow_mut();
-                            if var_mr.cleared == 0 && !var_mr.is_global {
-                                var_mr.cleared = node.range().start_point.row;
-                            }
-                        }
-                    }
-                }
-                let mut delete = vec![];
-                let mut keep = vec![];
-                let mut except = false;
-                let mut globals = false;
-                for arg in args {
-                    let text = arg.utf8_text(parsed_file.contents.as_bytes())?;
-                    if text.to_lowercase() == "global" {
-                        globals = true;
-                        continue;
-                    }
-                    if text.to_lowercase() == "-except" && name.to_lowercase() == "clearvars" {
-                        except = true;
-                        continue;
-                    }
-                    if text.starts_with('-') {
-                        debug!("It's an option argument, we dont that here.");
-                        break;
-                    }
-                    if !except {
-                        delete.push(text);
-                    } else {
-                        keep.push(text);
-                    }
-                }
-                if delete.is_empty() {
-                    delete.push("*");
-                }
-                let mut ws: Vec<&Workspace> = scopes
-                    .iter()
-                    .flat_map(|s| functions.get(s))
-                    .map(|(_, ws)| ws)
-                    .collect();
-                if ws.is_empty() {
-                    ws.push(workspace);
-                }
-                for ws in ws {
-                    'var: for var in &ws.variables {
-                        let mut var_mr = var.borrow_mut();
-                        for text in &delete {
-                            let text = text.replace('*', ".*");
-                            let text = format!("^{}$", text);
-                            if let Ok(sw) = Regex::new(text.as_str()) {
-                                if sw.is_match(var_mr.name.as_str()) {
-                                    for text in &keep {
-                                        let text = text.replace('*', ".*");
-                                        let text = format!("^{}$", text);
-                                        if let Ok(sw) = Regex::new(text.as_str()) {
-                                            if sw.is_match(var_mr.name.as_str()) {
-                                                continue 'var;
-                                            }
-                                        }
-                                    }
-                                    if var_mr.cleared == 0 && (!var_mr.is_global || globals) {
-                                        var_mr.cleared = node.range().start_point.row;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+            debug!("It's a clear, left to the flow analysis.");
         }
         "syms" => {
             debug!("It's a syms.");
@@ -449,6 +377,7 @@ fn command_capture_impl(
                 };
                 let r = Arc::new(AtomicRefCell::new(r));
                 workspace.references.push(r);
+                def_script_vars(&ms, workspace, scopes, functions, *node);
             } else {
                 debug!("Not a script.");
                 let fs = ref_to_fn(
@@ -565,7 +494,7 @@ fn import_capture_impl(
 ) -> Result<()> {
     if let Ok(path) = node.utf8_text(parsed_file.contents.as_bytes()) {
         debug!("Importing {path}");
-        let functions = db_fetch_functions(&sender, &receiver, thread).unwrap_or(HashMap::new());
+        let functions = db_fetch_functions(&sender, &receiver, thread).unwrap_or_default();
         if let Some(path) = path.strip_suffix(".*") {
             debug!("Importing all functions from {path}");
             for (f_name, f_def) in &functions {
@@ -693,7 +622,7 @@ fn field_capture_impl(
                 _ => break,
             }
         }
-        let bo = vec![(base_name, object)];
+        let bo = [(base_name, object)];
         let fields: Vec<(String, Node)> =
             bo.iter().chain(fields.iter()).map(Clone::clone).collect();
         let mut is_pack = false;
@@ -738,7 +667,7 @@ fn field_capture_impl(
                         *field,
                         parsed_file,
                     )?;
-                    is_pack = vref.first().is_none();
+                    is_pack = vref.is_empty();
                 }
                 if is_pack {
                     debug!("It's a package.");
@@ -881,9 +810,6 @@ fn ref_to_var(
     for (_, ws) in scopes.iter().flat_map(|i| functions.get(i)) {
         for v in ws.variables.iter().rev() {
             let v_ref = v.borrow();
-            if v_ref.cleared > 0 {
-                continue;
-            }
             if v_ref.name == name {
                 if is_assignment && p_range.fully_contains(v_ref.loc) {
                     continue;
@@ -914,9 +840,6 @@ fn ref_to_var(
     {
         for v in workspace.variables.iter().rev() {
             let v_ref = v.borrow();
-            if v_ref.cleared > 0 {
-                continue;
-            }
             if v_ref.name == name {
                 if is_assignment && p_range.fully_contains(v_ref.loc) {
                     continue;
@@ -948,7 +871,7 @@ fn ref_to_fn_in_ws(
 ) -> Result<Vec<Reference>> {
     let mut references = vec![];
     for fn_def in db_fetch_functions(&sender, &receiver, thread)
-        .unwrap_or(HashMap::new())
+        .unwrap_or_default()
         .values()
     {
         if fn_def.name == name && (fn_def.package.is_empty() || pkg) {
@@ -1067,6 +990,15 @@ fn def_var(
     if parent_of_kind("function_call", node).is_some() && !vref.is_empty() {
         return Ok(());
     }
+    // Assigning to a global writes to the shared variable instead of creating a local one.
+    if let Some(global) = vref.iter().find(|r| match &r.target {
+        ReferenceTarget::Variable(v) => v.borrow().is_global,
+        _ => false,
+    }) {
+        let global = Arc::new(AtomicRefCell::new(global.clone()));
+        workspace.references.push(global);
+        return Ok(());
+    }
     if soft_scope_parent(node).is_some() && !vref.is_empty() {
         let vref = Arc::new(AtomicRefCell::new(vref.first().unwrap().clone()));
         workspace.references.push(vref);
@@ -1077,9 +1009,9 @@ fn def_var(
         let definition = VariableDefinition {
             loc: node.range().into(),
             name: name.clone(),
-            cleared: 0,
             is_parameter,
             is_global,
+            script: None,
         };
         let definition = Arc::new(AtomicRefCell::new(definition));
         if let Some(scope) = scopes.first() {
@@ -1093,6 +1025,64 @@ fn def_var(
     Ok(())
 }
 
+/// Scripts run in the workspace of their caller, so the variables a script leaves behind become
+/// visible after the call: those assigned on some path to the end of the script code, with the
+/// fields of the ones left behind. They are defined at the call site, remembering where the
+/// script defines them.
+fn def_script_vars(
+    script: &ParsedFile,
+    workspace: &mut Workspace,
+    scopes: &[usize],
+    functions: &mut HashMap<usize, (Node, Workspace)>,
+    node: Node,
+) {
+    debug!("Importing variables from script {}", script.path);
+    let Some(body) = script.workspace.reaching.bodies.first() else {
+        return;
+    };
+    let root = script.tree.root_node();
+    let exported = |name: &str| {
+        body.exit
+            .iter()
+            .any(|v| v.borrow().name == name.split('.').next().unwrap_or(name))
+    };
+    let fields = script.workspace.variables.iter().filter(|var| {
+        let v_ref = var.borrow();
+        // Variables of local functions are not part of the script's workspace.
+        v_ref.name.contains('.')
+            && exported(&v_ref.name)
+            && root
+                .named_descendant_for_point_range(v_ref.loc.start, v_ref.loc.start)
+                .is_none_or(|ndef| parent_function(ndef).is_none())
+    });
+    let mut definitions: Vec<VariableDefinition> = vec![];
+    for var in body.exit.iter().chain(fields) {
+        let v_ref = var.borrow();
+        let origin = v_ref
+            .script
+            .clone()
+            .unwrap_or((script.path.clone(), v_ref.loc));
+        definitions.retain(|d| d.name != v_ref.name);
+        definitions.push(VariableDefinition {
+            loc: node.range().into(),
+            name: v_ref.name.clone(),
+            is_parameter: false,
+            is_global: v_ref.is_global,
+            script: Some(origin),
+        });
+    }
+    for definition in definitions {
+        let definition = Arc::new(AtomicRefCell::new(definition));
+        if let Some(scope) = scopes.first() {
+            if let Some((_, ws)) = functions.get_mut(scope) {
+                ws.variables.push(definition);
+            }
+        } else {
+            workspace.variables.push(definition);
+        }
+    }
+}
+
 /// Verifies if some and other are in the same soft-scope. A soft-scope is introduced by any
 /// statement with multiple blocks. This definition is necessary to avoid variables in a branch of
 /// an if/elseif/else or case/otherwise or try/catch to reference each other instead of the
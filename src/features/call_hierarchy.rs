@@ -0,0 +1,295 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{Receiver, Sender};
+use log::debug;
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, SymbolKind, Url,
+};
+use serde_json::json;
+use tree_sitter::Point;
+
+use crate::code_loc;
+use crate::extractors::symbols::{extract_symbols, parent_of_kind};
+use crate::threads::db::{db_fetch_parsed_files, db_fetch_workspace_paths, db_get_parsed_file};
+use crate::types::{
+    FunctionDefinition, ParsedFile, Range, ReferenceTarget, SenderThread, ThreadMessage,
+};
+use crate::utils::read_to_string;
+
+/// Finds the function at `loc`, either called there or defined there.
+pub fn prepare_call_hierarchy(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+    loc: Point,
+) -> Result<Vec<CallHierarchyItem>> {
+    let file = db_get_parsed_file(&sender, &receiver, path, SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if r_ref.loc.contains(loc) {
+            if let ReferenceTarget::Function(function) = &r_ref.target {
+                return Ok(vec![function_item(&function.borrow())?]);
+            }
+        }
+    }
+    for function in file.workspace.functions.values() {
+        if function.path == file.path && function.signature.name_range.contains(loc) {
+            return Ok(vec![function_item(function)?]);
+        }
+    }
+    Ok(vec![])
+}
+
+/// Lists the calls to the function of `item` in all parsed files, grouped by the function making
+/// them. Files of the library path are only indexed, so the ones mentioning the function are
+/// analysed here.
+pub fn incoming_calls(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    item: CallHierarchyItem,
+) -> Result<Vec<CallHierarchyIncomingCall>> {
+    let (path, name) = item_function(&item);
+    debug!("Incoming calls of {name} in {path}.");
+    let workspace = db_fetch_workspace_paths(&sender, &receiver, SenderThread::Handler);
+    let in_workspace = |p: &str| workspace.iter().any(|w| Path::new(p).starts_with(w));
+    let mut calls: Vec<CallHierarchyIncomingCall> = vec![];
+    for file in db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler)
+        .unwrap_or_default()
+        .values()
+    {
+        let file = if file.open || in_workspace(&file.path) {
+            Arc::clone(file)
+        } else {
+            match library_references(&sender, &receiver, file, &name) {
+                Some(file) => file,
+                None => continue,
+            }
+        };
+        for reference in &file.workspace.references {
+            let r_ref = reference.borrow();
+            if let ReferenceTarget::Function(target) = &r_ref.target {
+                let target = target.borrow();
+                if target.path != path || target.name != name {
+                    continue;
+                }
+                let from = enclosing_item(&file, r_ref.loc)?;
+                match calls
+                    .iter_mut()
+                    .find(|c| c.from.uri == from.uri && c.from.range == from.range)
+                {
+                    Some(call) => call.from_ranges.push(r_ref.loc.into()),
+                    None => calls.push(CallHierarchyIncomingCall {
+                        from,
+                        from_ranges: vec![r_ref.loc.into()],
+                    }),
+                }
+            }
+        }
+    }
+    Ok(calls)
+}
+
+/// The library file analysed like the workspace files, if it mentions `name`. The analysis is not
+/// kept, the file stays indexed only.
+fn library_references(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    file: &ParsedFile,
+    name: &str,
+) -> Option<Arc<ParsedFile>> {
+    let mut reader = std::fs::File::open(&file.path).ok()?;
+    let (contents, _, _) = read_to_string(&mut reader, None).ok()?;
+    if !contents.contains(name) {
+        return None;
+    }
+    let mut file = file.clone();
+    file.load_contents().ok()?;
+    match extract_symbols(
+        sender.clone(),
+        receiver.clone(),
+        SenderThread::Handler,
+        Arc::new(file),
+    ) {
+        Ok(file) => Some(file),
+        Err(err) => {
+            debug!("Could not analyse a library file: {err}");
+            None
+        }
+    }
+}
+
+/// Lists the functions called from the body of `item`, leaving out the calls made by its nested
+/// functions.
+pub fn outgoing_calls(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    item: CallHierarchyItem,
+) -> Result<Vec<CallHierarchyOutgoingCall>> {
+    let path = item.uri.path().to_string();
+    let file = db_get_parsed_file(&sender, &receiver, path, SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    let mut calls: Vec<CallHierarchyOutgoingCall> = vec![];
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if let ReferenceTarget::Function(target) = &r_ref.target {
+            let from = enclosing_item(&file, r_ref.loc)?;
+            if from.range != item.range {
+                continue;
+            }
+            let to = function_item(&target.borrow())?;
+            match calls
+                .iter_mut()
+                .find(|c| c.to.uri == to.uri && c.to.name == to.name)
+            {
+                Some(call) => call.from_ranges.push(r_ref.loc.into()),
+                None => calls.push(CallHierarchyOutgoingCall {
+                    to,
+                    from_ranges: vec![r_ref.loc.into()],
+                }),
+            }
+        }
+    }
+    Ok(calls)
+}
+
+fn function_item(function: &FunctionDefinition) -> Result<CallHierarchyItem> {
+    let path = String::from("file://") + function.path.as_str();
+    let name_range = function.signature.name_range;
+    let range = if function.loc.fully_contains(name_range) {
+        function.loc
+    } else {
+        name_range
+    };
+    let name = if function.package.is_empty() {
+        function.name.clone()
+    } else {
+        format!("{}.{}", function.package, function.name)
+    };
+    Ok(CallHierarchyItem {
+        name,
+        kind: SymbolKind::FUNCTION,
+        tags: None,
+        detail: Some(function.path.clone()),
+        uri: Url::parse(path.as_str())?,
+        range: range.into(),
+        selection_range: name_range.into(),
+        data: Some(json!({ "path": function.path, "name": function.name })),
+    })
+}
+
+/// The item of the function enclosing `loc`, or of the file itself for code outside functions.
+fn enclosing_item(file: &ParsedFile, loc: Range) -> Result<CallHierarchyItem> {
+    let tree = file.syntax_tree()?;
+    let definition = tree
+        .root_node()
+        .named_descendant_for_point_range(loc.start, loc.end)
+        .and_then(|node| parent_of_kind("function_definition", node));
+    if let Some(name) = definition.and_then(|d| d.child_by_field_name("name")) {
+        let name_range: Range = name.range().into();
+        for function in file.workspace.functions.values() {
+            if function.path == file.path && function.signature.name_range == name_range {
+                return function_item(function);
+            }
+        }
+    }
+    let path = String::from("file://") + file.path.as_str();
+    Ok(CallHierarchyItem {
+        name: file.name.clone(),
+        kind: SymbolKind::FILE,
+        tags: None,
+        detail: Some(file.path.clone()),
+        uri: Url::parse(path.as_str())?,
+        range: Range::from(tree.root_node().range()).into(),
+        selection_range: Range::default().into(),
+        data: Some(json!({ "path": file.path, "name": file.name })),
+    })
+}
+
+fn item_function(item: &CallHierarchyItem) -> (String, String) {
+    let field = |key: &str| {
+        item.data
+            .as_ref()
+            .and_then(|d| d.get(key))
+            .and_then(|v| v.as_str())
+            .map(String::from)
+    };
+    let path = field("path").unwrap_or(item.uri.path().to_string());
+    let name = field("name").unwrap_or(item.name.clone());
+    (path, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    fn fixture(test: &str) -> Fixture {
+        Fixture::with_library(
+            test,
+            &[
+                ("helper.m", "function helper\nend\n"),
+                ("main.m", "function main\nhelper();\nend\n"),
+            ],
+            &[("user.m", "function user\nhelper();\nhelper();\nend\n")],
+        )
+    }
+
+    fn helper_item(fixture: &Fixture) -> CallHierarchyItem {
+        let items = prepare_call_hierarchy(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("main.m"),
+            Point { row: 1, column: 0 },
+        )
+        .unwrap();
+        assert_eq!(items.len(), 1);
+        items[0].clone()
+    }
+
+    #[test]
+    fn prepares_the_called_function() {
+        let fixture = fixture("call-prepare");
+        let item = helper_item(&fixture);
+        assert_eq!(item.name, "helper");
+        assert_eq!(item.uri.path(), fixture.path("helper.m"));
+    }
+
+    #[test]
+    fn incoming_calls_include_library_callers() {
+        let fixture = fixture("call-incoming");
+        let item = helper_item(&fixture);
+        let mut calls = incoming_calls(fixture.sender.clone(), fixture.receiver.clone(), item)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.from.name, c.from_ranges.len()))
+            .collect::<Vec<_>>();
+        calls.sort();
+        assert_eq!(calls, vec![("main".into(), 1), ("user".into(), 2)]);
+    }
+
+    #[test]
+    fn outgoing_calls_of_a_function() {
+        let fixture = fixture("call-outgoing");
+        let main = prepare_call_hierarchy(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("main.m"),
+            Point { row: 0, column: 10 },
+        )
+        .unwrap()
+        .remove(0);
+        let calls = outgoing_calls(fixture.sender.clone(), fixture.receiver.clone(), main).unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].to.name, "helper");
+        assert_eq!(calls[0].from_ranges.len(), 1);
+    }
+}
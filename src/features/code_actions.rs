@@ -0,0 +1,59 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{Receiver, Sender};
+use lsp_types::{CodeAction, CodeActionOrCommand, CodeActionParams};
+
+use crate::code_loc;
+use crate::features::create_function::create_function_actions;
+use crate::features::extract::extract_function_actions;
+use crate::features::inline::inline_actions;
+use crate::features::script_to_function::{script_to_function, script_to_function_actions};
+use crate::threads::db::db_get_parsed_file;
+use crate::types::{Range, SenderThread, ThreadMessage};
+
+pub fn code_actions(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    params: CodeActionParams,
+) -> Result<Vec<CodeActionOrCommand>> {
+    let path = params.text_document.uri.path().to_string();
+    let range: Range = params.range.into();
+    let file = db_get_parsed_file(&sender, &receiver, path, SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    let mut actions = vec![];
+    actions.extend(create_function_actions(&file, range)?);
+    actions.extend(extract_function_actions(&file, range)?);
+    actions.extend(inline_actions(&file, range)?);
+    actions.extend(script_to_function_actions(&file));
+    Ok(actions
+        .into_iter()
+        .map(CodeActionOrCommand::CodeAction)
+        .collect())
+}
+
+/// Computes the edit of the actions that are too costly to compute for every request.
+pub fn resolve_code_action(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    action: CodeAction,
+) -> Result<CodeAction> {
+    let mut action = action;
+    let data = action
+        .data
+        .clone()
+        .ok_or(code_loc!("Nothing to resolve."))?;
+    let field = |key: &str| data.get(key).and_then(|v| v.as_str()).map(String::from);
+    let path = field("path").ok_or(code_loc!("Missing path."))?;
+    match field("action").as_deref() {
+        Some("script_to_function") => {
+            action.edit = Some(script_to_function(sender, receiver, path)?);
+        }
+        _ => return Err(code_loc!("Unknown action.")),
+    }
+    Ok(action)
+}
@@ -0,0 +1,277 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use atomic_refcell::AtomicRefCell;
+use crossbeam_channel::{Receiver, Sender};
+use lsp_types::{CodeLens, Command, Location, Position, Url};
+use serde_json::json;
+use tree_sitter::Node;
+
+use crate::code_loc;
+use crate::features::references::find_references_to_function;
+use crate::features::type_hierarchy::{fetch_classes, qualified_name};
+use crate::threads::db::db_get_parsed_file;
+use crate::types::{FunctionDefinition, ParsedFile, Range, SenderThread, ThreadMessage};
+
+/// Lenses of a file. The reference counts are left to `resolve_code_lens`, as counting them means
+/// going through every file.
+pub fn code_lenses(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+) -> Result<Vec<CodeLens>> {
+    let file = db_get_parsed_file(&sender, &receiver, path, SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    let uri = Url::parse((String::from("file://") + file.path.as_str()).as_str())?;
+    let mut lenses = vec![];
+    let mut functions: Vec<&Arc<FunctionDefinition>> = file
+        .workspace
+        .functions
+        .values()
+        .filter(|f| f.path == file.path)
+        .collect();
+    functions.sort_by_key(|f| f.signature.name_range.start);
+    functions.dedup_by_key(|f| f.signature.name_range);
+    for function in functions {
+        let range: lsp_types::Range = function.signature.name_range.into();
+        lenses.push(CodeLens {
+            range,
+            command: None,
+            data: Some(json!({
+                "path": function.path,
+                "name": function.name,
+                "position": range.start,
+            })),
+        });
+    }
+    for (i, line) in file.contents.lines().enumerate() {
+        if line.trim_start().starts_with("%%") {
+            let position = Position::new(i.try_into()?, 0);
+            lenses.push(CodeLens {
+                range: lsp_types::Range::new(position, position),
+                command: Some(Command {
+                    title: "Run section".into(),
+                    command: "matlab.runSection".into(),
+                    arguments: Some(vec![json!(uri), json!(i)]),
+                }),
+                data: None,
+            });
+        }
+    }
+    lenses.extend(test_lenses(&sender, &receiver, &file, &uri));
+    Ok(lenses)
+}
+
+pub fn resolve_code_lens(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    lens: CodeLens,
+) -> Result<CodeLens> {
+    let mut lens = lens;
+    let data = lens.data.clone().ok_or(code_loc!("Nothing to resolve."))?;
+    let field = |key: &str| data.get(key).and_then(|v| v.as_str()).map(String::from);
+    let path = field("path").ok_or(code_loc!("Missing path."))?;
+    let name = field("name").ok_or(code_loc!("Missing name."))?;
+    let function = FunctionDefinition {
+        path: path.clone(),
+        name,
+        ..FunctionDefinition::default()
+    };
+    let refs = find_references_to_function(
+        sender,
+        receiver,
+        Arc::new(AtomicRefCell::new(function)),
+        false,
+    )?;
+    let locations: Vec<Location> = refs.into_iter().map(|(l, _)| l).collect();
+    let uri = Url::parse((String::from("file://") + path.as_str()).as_str())?;
+    let title = match locations.len() {
+        1 => "1 reference".to_string(),
+        n => format!("{n} references"),
+    };
+    lens.command = Some(Command {
+        title,
+        command: "editor.action.showReferences".into(),
+        arguments: Some(vec![json!(uri), json!(lens.range.start), json!(locations)]),
+    });
+    Ok(lens)
+}
+
+/// Lenses running the test methods of a `matlab.unittest.TestCase` subclass, one per method and
+/// one for the whole class.
+fn test_lenses(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    file: &ParsedFile,
+    uri: &Url,
+) -> Vec<CodeLens> {
+    let mut lenses = vec![];
+    let Some(class) = &file.class else {
+        return lenses;
+    };
+    let classes = fetch_classes(sender, receiver);
+    let mut supers = class.superclasses.clone();
+    let mut seen = vec![];
+    let mut is_test = false;
+    while let Some(name) = supers.pop() {
+        if name == "matlab.unittest.TestCase" {
+            is_test = true;
+            break;
+        }
+        if seen.contains(&name) {
+            continue;
+        }
+        if let Some(superclass) = classes.iter().find(|c| qualified_name(c) == name) {
+            supers.extend(superclass.superclasses.clone());
+        }
+        seen.push(name);
+    }
+    if !is_test {
+        return lenses;
+    }
+    let class_name = qualified_name(class);
+    lenses.push(CodeLens {
+        range: class.name_range.into(),
+        command: Some(Command {
+            title: "Run tests".into(),
+            command: "matlab.runTest".into(),
+            arguments: Some(vec![json!(uri), json!(class_name)]),
+        }),
+        data: None,
+    });
+    let Ok(tree) = file.syntax_tree() else {
+        return lenses;
+    };
+    let root = tree.root_node();
+    for (method, range) in &class.methods {
+        let Some(node) = root.named_descendant_for_point_range(range.start, range.end) else {
+            continue;
+        };
+        if !in_test_block(file, node) {
+            continue;
+        }
+        let range: Range = *range;
+        lenses.push(CodeLens {
+            range: range.into(),
+            command: Some(Command {
+                title: "Run test".into(),
+                command: "matlab.runTest".into(),
+                arguments: Some(vec![json!(uri), json!(format!("{class_name}/{method}"))]),
+            }),
+            data: None,
+        });
+    }
+    lenses
+}
+
+/// Whether the node is in a `methods (Test)` block.
+fn in_test_block(file: &ParsedFile, node: Node) -> bool {
+    let mut node = node;
+    while let Some(parent) = node.parent() {
+        if parent.kind() == "methods" {
+            let mut cursor = parent.walk();
+            let attributes = parent
+                .named_children(&mut cursor)
+                .find(|n| n.kind() == "attributes");
+            let Some(attributes) = attributes else {
+                return false;
+            };
+            let Ok(text) = attributes.utf8_text(file.contents.as_bytes()) else {
+                return false;
+            };
+            return text
+                .trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace())
+                .split(',')
+                .any(|a| {
+                    let mut parts = a.split('=').map(str::trim);
+                    parts.next() == Some("Test") && parts.next() != Some("false")
+                });
+        }
+        node = parent;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    fn lenses(fixture: &Fixture, name: &str) -> Vec<CodeLens> {
+        fixture.open(name);
+        code_lenses(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path(name),
+        )
+        .unwrap()
+    }
+
+    fn titles(lenses: &[CodeLens]) -> Vec<String> {
+        lenses
+            .iter()
+            .filter_map(|l| l.command.as_ref())
+            .map(|c| c.title.clone())
+            .collect()
+    }
+
+    #[test]
+    fn function_lens_counts_references() {
+        let fixture = Fixture::new(
+            "code-lens-references",
+            &[
+                ("helper.m", "function helper\nend\n"),
+                ("main.m", "helper();\nhelper();\n"),
+            ],
+        );
+        let lenses = lenses(&fixture, "helper.m");
+        assert_eq!(lenses.len(), 1);
+        assert!(lenses[0].command.is_none());
+        let lens = resolve_code_lens(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            lenses[0].clone(),
+        )
+        .unwrap();
+        assert_eq!(lens.command.unwrap().title, "2 references");
+    }
+
+    #[test]
+    fn sections_can_be_run() {
+        let fixture = Fixture::new(
+            "code-lens-sections",
+            &[("main.m", "%% First\nx = 1;\n  %% Second\ny = 2;\n")],
+        );
+        let lenses = lenses(&fixture, "main.m");
+        let rows: Vec<u32> = lenses.iter().map(|l| l.range.start.line).collect();
+        assert_eq!(rows, vec![0, 2]);
+        assert_eq!(titles(&lenses), vec!["Run section", "Run section"]);
+    }
+
+    #[test]
+    fn test_methods_can_be_run() {
+        let fixture = Fixture::new(
+            "code-lens-tests",
+            &[(
+                "MyTest.m",
+                "classdef MyTest < matlab.unittest.TestCase\n\
+                 methods (Test)\nfunction checks(testCase)\nend\nend\n\
+                 methods\nfunction helper(testCase)\nend\nend\nend\n",
+            )],
+        );
+        let lenses = lenses(&fixture, "MyTest.m");
+        let runs: Vec<serde_json::Value> = lenses
+            .iter()
+            .filter_map(|l| l.command.as_ref())
+            .filter(|c| c.command == "matlab.runTest")
+            .map(|c| c.arguments.as_ref().unwrap()[1].clone())
+            .collect();
+        assert_eq!(runs, vec![json!("MyTest"), json!("MyTest/checks")]);
+    }
+}
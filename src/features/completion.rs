@@ -4,9 +4,9 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::HashMap;
 use std::sync::Arc;
 
+use atomic_refcell::AtomicRefCell;
 use crossbeam_channel::{Receiver, Sender};
 use itertools::Itertools;
 use lsp_types::{
@@ -14,10 +14,13 @@ use lsp_types::{
 };
 use tree_sitter::Point;
 
+use crate::extractors::flow::defined_at;
 use crate::extractors::symbols::parent_of_kind;
 use crate::impls::range::PosToPoint;
 use crate::threads::db::{db_fetch_functions, db_fetch_script, db_get_package};
-use crate::types::{ParsedFile, Range, ReferenceTarget, SenderThread, ThreadMessage};
+use crate::types::{
+    ParsedFile, Range, ReferenceTarget, SenderThread, ThreadMessage, VariableDefinition,
+};
 use anyhow::Result;
 
 pub fn complete(
@@ -68,14 +71,30 @@ fn identifier(pf_mr: Arc<ParsedFile>, pos: Point) -> String {
     line.chars().rev().collect()
 }
 
+/// Whether a variable may be defined at a point. Fields are not followed by the flow analysis, so
+/// they are taken as defined after their assignment, while their variable is.
+fn is_defined(
+    defined: &[Arc<AtomicRefCell<VariableDefinition>>],
+    var: &Arc<AtomicRefCell<VariableDefinition>>,
+    point: Point,
+) -> bool {
+    let v_ref = var.borrow();
+    match v_ref.name.split_once('.') {
+        Some((base, _)) => {
+            v_ref.loc.start < point && defined.iter().any(|d| d.borrow().name == base)
+        }
+        None => defined.iter().any(|d| Arc::ptr_eq(d, var)),
+    }
+}
+
 fn variable_completions(pf_mr: Arc<ParsedFile>, text: &str, point: Point) -> Vec<CompletionItem> {
     let mut completions = vec![];
+    let defined = defined_at(&pf_mr.workspace, point);
     for var in &pf_mr.workspace.variables {
-        let var_ref = var.borrow();
-        if var_ref.loc.start.row >= point.row || var_ref.cleared > 0 && var_ref.cleared < point.row
-        {
+        if !is_defined(&defined, var, point) {
             continue;
         }
+        let var_ref = var.borrow();
         if var_ref.name.starts_with(text) {
             let mut code = String::new();
             let tree = pf_mr.tree.clone();
@@ -113,11 +132,11 @@ fn variable_completions(pf_mr: Arc<ParsedFile>, text: &str, point: Point) -> Vec
 
 fn reference_completions(pf_mr: Arc<ParsedFile>, text: &str, point: Point) -> Vec<CompletionItem> {
     let mut completions = vec![];
+    let defined = defined_at(&pf_mr.workspace, point);
     for var in &pf_mr.workspace.references {
         let var = var.borrow();
         if let ReferenceTarget::Variable(def) = &var.target {
-            let def = def.borrow();
-            if def.loc.start.row > point.row || def.cleared > 0 && def.cleared < point.row {
+            if !is_defined(&defined, def, point) {
                 continue;
             }
         }
@@ -168,7 +187,7 @@ fn function_completions(
 ) -> Vec<CompletionItem> {
     let mut completions = vec![];
     let functions =
-        db_fetch_functions(&sender, &receiver, SenderThread::Handler).unwrap_or(HashMap::new());
+        db_fetch_functions(&sender, &receiver, SenderThread::Handler).unwrap_or_default();
     let functions = functions.iter().chain(pf_mr.workspace.functions.iter());
     for (name, function) in functions {
         if name.starts_with(text) {
@@ -0,0 +1,255 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use lsp_types::{
+    CodeAction, CodeActionKind, CreateFile, CreateFileOptions, DocumentChangeOperation,
+    DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier, ResourceOp, TextDocumentEdit,
+    TextEdit, Url, WorkspaceEdit,
+};
+use tree_sitter::Node;
+
+use crate::code_loc;
+use crate::extractors::symbols::parent_of_kind;
+use crate::types::{ParsedFile, Range, ReferenceTarget};
+
+/// Quick fixes creating the file of an unknown function called in `range`, with a stub whose
+/// arguments and outputs are inferred from the call.
+pub fn create_function_actions(file: &ParsedFile, range: Range) -> Result<Vec<CodeAction>> {
+    let mut actions = vec![];
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if !matches!(r_ref.target, ReferenceTarget::UnknownFunction)
+            || !(r_ref.loc.contains(range.start) || range.fully_contains(r_ref.loc))
+        {
+            continue;
+        }
+        let Some(node) = file
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(r_ref.loc.start, r_ref.loc.end)
+        else {
+            continue;
+        };
+        let mut segments: Vec<&str> = r_ref.name.split('.').collect();
+        let Some(name) = segments.pop() else {
+            continue;
+        };
+        let relative = segments
+            .iter()
+            .map(|s| format!("+{s}/"))
+            .chain([format!("{name}.m")])
+            .join("");
+        let path = package_root(&file.path)?.join(&relative);
+        let path = path.to_string_lossy().to_string();
+        let uri = Url::parse((String::from("file://") + path.as_str()).as_str())?;
+        let call = parent_of_kind("function_call", node);
+        let stub = function_stub(file, name, call);
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                    uri: uri.clone(),
+                    options: Some(CreateFileOptions {
+                        overwrite: Some(false),
+                        ignore_if_exists: Some(false),
+                    }),
+                    annotation_id: None,
+                })),
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+                    edits: vec![OneOf::Left(TextEdit {
+                        range: Range::default().into(),
+                        new_text: stub,
+                    })],
+                }),
+            ])),
+            ..WorkspaceEdit::default()
+        };
+        actions.push(CodeAction {
+            title: format!("Create function file {relative}"),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: Some(edit),
+            ..CodeAction::default()
+        });
+    }
+    Ok(actions)
+}
+
+/// The folder a file's package hierarchy starts from, which is the folder on the path.
+fn package_root(path: &str) -> Result<PathBuf> {
+    let mut folder = Path::new(path)
+        .parent()
+        .ok_or(code_loc!("File has no folder."))?;
+    while let Some(name) = folder.file_name().and_then(|n| n.to_str()) {
+        if !(name.starts_with('+') || name.starts_with('@') || name == "private") {
+            break;
+        }
+        match folder.parent() {
+            Some(parent) => folder = parent,
+            None => break,
+        }
+    }
+    Ok(folder.to_path_buf())
+}
+
+fn function_stub(file: &ParsedFile, name: &str, call: Option<Node>) -> String {
+    let mut inputs: Vec<String> = vec![];
+    let mut outputs: Vec<String> = vec![];
+    if let Some(call) = call {
+        let mut cursor = call.walk();
+        if let Some(arguments) = call
+            .named_children(&mut cursor)
+            .find(|n| n.kind() == "arguments")
+        {
+            let mut cursor = arguments.walk();
+            for (i, argument) in arguments.named_children(&mut cursor).enumerate() {
+                inputs.push(identifier_or(
+                    file,
+                    argument,
+                    format!("in{}", i + 1),
+                    &inputs,
+                ));
+            }
+        }
+        let left = call
+            .parent()
+            .filter(|p| p.kind() == "assignment")
+            .and_then(|p| p.child_by_field_name("left"));
+        if let Some(left) = left {
+            if left.kind() == "multioutput_variable" {
+                let mut cursor = left.walk();
+                for (i, output) in left.named_children(&mut cursor).enumerate() {
+                    outputs.push(identifier_or(
+                        file,
+                        output,
+                        format!("out{}", i + 1),
+                        &outputs,
+                    ));
+                }
+            } else {
+                outputs.push(identifier_or(file, left, "out1".into(), &outputs));
+            }
+        }
+    }
+    let mut stub = String::from("function ");
+    match outputs.len() {
+        0 => {}
+        1 => stub += format!("{} = ", outputs[0]).as_str(),
+        _ => stub += format!("[{}] = ", outputs.join(", ")).as_str(),
+    }
+    stub += name;
+    if !inputs.is_empty() {
+        stub += format!("({})", inputs.join(", ")).as_str();
+    }
+    stub += format!("\n%{}\n\n", name.to_uppercase()).as_str();
+    for output in &outputs {
+        stub += format!("{output} = [];\n").as_str();
+    }
+    stub += "end\n";
+    stub
+}
+
+/// The node's text if it is an identifier not yet taken, otherwise the fallback name.
+fn identifier_or(file: &ParsedFile, node: Node, fallback: String, taken: &[String]) -> String {
+    if node.kind() == "identifier" {
+        if let Ok(text) = node.utf8_text(file.contents.as_bytes()) {
+            if !taken.iter().any(|t| t == text) {
+                return text.to_string();
+            }
+        }
+    }
+    fallback
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{open_script, Fixture};
+    use tree_sitter::Point;
+
+    fn at(row: usize, column: usize) -> Range {
+        let point = Point { row, column };
+        Range {
+            start: point,
+            end: point,
+        }
+    }
+
+    /// The file the action creates and the text written to it.
+    fn created(action: &CodeAction) -> (CreateFile, String) {
+        let Some(DocumentChanges::Operations(operations)) = action
+            .edit
+            .as_ref()
+            .and_then(|e| e.document_changes.clone())
+        else {
+            panic!("Expected resource operations.");
+        };
+        let [DocumentChangeOperation::Op(ResourceOp::Create(create)), DocumentChangeOperation::Edit(edit)] =
+            operations.as_slice()
+        else {
+            panic!("Expected a file creation followed by its contents.");
+        };
+        let OneOf::Left(text) = &edit.edits[0] else {
+            panic!("Expected a plain text edit.");
+        };
+        (create.clone(), text.new_text.clone())
+    }
+
+    #[test]
+    fn creates_stub_from_call() {
+        let file = open_script(
+            "create-call",
+            "a = 1;
+[m, n] = compute(a, 2);
+",
+        );
+        let actions = create_function_actions(&file, at(1, 10)).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].title, "Create function file compute.m");
+        let (create, stub) = created(&actions[0]);
+        assert!(create.uri.path().ends_with("/ws/compute.m"));
+        assert_eq!(
+            stub,
+            "function [m, n] = compute(a, in2)\n%COMPUTE\n\nm = [];\nn = [];\nend\n"
+        );
+    }
+
+    #[test]
+    fn does_not_overwrite_existing_file() {
+        let file = open_script(
+            "create-options",
+            "compute();
+",
+        );
+        let actions = create_function_actions(&file, at(0, 0)).unwrap();
+        assert_eq!(actions.len(), 1);
+        let (create, _) = created(&actions[0]);
+        let options = create.options.unwrap();
+        assert_eq!(options.overwrite, Some(false));
+        assert_eq!(options.ignore_if_exists, Some(false));
+    }
+
+    #[test]
+    fn creates_package_function_next_to_package_folder() {
+        let fixture = Fixture::new(
+            "create-package",
+            &[(
+                "+pkg/caller.m",
+                "function caller()\nout = tools.helper(1);\nend\n",
+            )],
+        );
+        let file = fixture.open("+pkg/caller.m");
+        let actions = create_function_actions(&file, at(1, 6)).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].title, "Create function file +tools/helper.m");
+        let (create, stub) = created(&actions[0]);
+        assert_eq!(create.uri.path(), fixture.path("+tools/helper.m").as_str());
+        assert!(stub.starts_with("function out = helper(in1)\n"));
+    }
+}
@@ -0,0 +1,466 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use atomic_refcell::AtomicRefCell;
+use crossbeam_channel::{Receiver, Sender};
+use log::debug;
+use lsp_types::{Location, Url};
+use tree_sitter::Point;
+
+use crate::code_loc;
+use crate::extractors::fast::class_folder;
+use crate::extractors::symbols::parent_of_kind;
+use crate::threads::db::{
+    db_fetch_parsed_files, db_get_function_candidates, db_get_global, db_get_parsed_file,
+};
+use crate::types::{
+    FunctionDefinition, ParsedFile, Range, ReferenceTarget, SenderThread, ThreadMessage,
+    VariableDefinition,
+};
+
+/// MATLAB's function precedence order, from the first place searched to the last. Candidates on
+/// the path keep the path order among themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Nested,
+    Local,
+    Private,
+    ClassFolder,
+    Imported,
+    CurrentFolder,
+    Path,
+}
+
+impl Precedence {
+    fn kind(&self) -> &'static str {
+        match self {
+            Precedence::Nested => "nested function",
+            Precedence::Local => "local function",
+            Precedence::Private => "private function",
+            Precedence::ClassFolder => "function in the class folder",
+            Precedence::Imported => "imported function",
+            Precedence::CurrentFolder => "function in the current folder",
+            Precedence::Path => "path function",
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            Precedence::Nested => "nested functions are searched first",
+            Precedence::Local => "local functions are searched before private and path functions",
+            Precedence::Private => "private functions are searched before path functions",
+            Precedence::ClassFolder => {
+                "the class folder is searched before imported and path functions"
+            }
+            Precedence::Imported => "imported names are searched before the path",
+            Precedence::CurrentFolder => {
+                "the current folder is searched before the rest of the path"
+            }
+            Precedence::Path => "first match on the path",
+        }
+    }
+}
+
+/// Lists every definition the symbol at `loc` may refer to, each with a label describing it. For
+/// functions the candidates are sorted by precedence, the first one being the one MATLAB calls.
+pub fn definitions_for_symbol(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+    loc: Point,
+) -> Result<Vec<(Location, String)>> {
+    let file = db_get_parsed_file(&sender, &receiver, path, SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    debug!(
+        "Listing definitions in {}, {} references.",
+        file.path,
+        file.workspace.references.len()
+    );
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if !r_ref.loc.contains(loc) {
+            continue;
+        }
+        debug!("Point in range, matching.");
+        return match &r_ref.target {
+            ReferenceTarget::Function(function) => {
+                let function = function.borrow().clone();
+                function_definitions(&sender, &receiver, &file, &function)
+            }
+            ReferenceTarget::Variable(var) if var.borrow().is_global => {
+                let name = var.borrow().name.clone();
+                global_definitions(&sender, &receiver, name)
+            }
+            ReferenceTarget::Variable(var) => variable_definitions(&file, r_ref.loc, var),
+            ReferenceTarget::Script(path) => Ok(vec![(
+                Location::new(file_uri(path)?, Range::default().into()),
+                "script".into(),
+            )]),
+            ReferenceTarget::Namespace(_) => namespace_definitions(&sender, &receiver, &r_ref.name),
+            ReferenceTarget::UnknownVariable => Ok(vec![]),
+            ReferenceTarget::UnknownFunction => Ok(vec![]),
+        };
+    }
+    debug!("Point not in range.");
+    Ok(vec![])
+}
+
+fn function_definitions(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    file: &ParsedFile,
+    target: &FunctionDefinition,
+) -> Result<Vec<(Location, String)>> {
+    let mut candidates: Vec<(Precedence, Arc<FunctionDefinition>)> = vec![];
+    let qualified = if target.package.is_empty() {
+        target.name.clone()
+    } else {
+        format!("{}.{}", target.package, target.name)
+    };
+    if target.package.is_empty() {
+        for function in file.workspace.functions.values() {
+            if function.name != target.name || !function.package.is_empty() {
+                continue;
+            }
+            let precedence = if function.path != file.path {
+                Precedence::Imported
+            } else if is_nested(file, function) {
+                Precedence::Nested
+            } else {
+                Precedence::Local
+            };
+            candidates.push((precedence, Arc::clone(function)));
+        }
+        if let Some(folder) = Path::new(&file.path).parent() {
+            let file_name = target.name.clone() + ".m";
+            let mut folders = vec![(Precedence::Private, folder.join("private"))];
+            if class_folder(&file.path).is_some() {
+                folders.push((Precedence::ClassFolder, folder.to_path_buf()));
+            }
+            for (precedence, folder) in folders {
+                let path = folder.join(&file_name);
+                if !path.is_file() || path == Path::new(&file.path) {
+                    continue;
+                }
+                if let Some(path) = path.to_str() {
+                    let function = FunctionDefinition {
+                        name: target.name.clone(),
+                        path: path.into(),
+                        ..FunctionDefinition::default()
+                    };
+                    candidates.push((precedence, Arc::new(function)));
+                }
+            }
+        }
+    }
+    let folder = Path::new(&file.path).parent();
+    for function in db_get_function_candidates(sender, receiver, qualified, SenderThread::Handler) {
+        if candidates
+            .iter()
+            .any(|(_, c)| c.path == function.path && c.loc == function.loc)
+        {
+            continue;
+        }
+        let precedence = if function.package.is_empty()
+            && folder.is_some()
+            && Path::new(&function.path).parent() == folder
+        {
+            Precedence::CurrentFolder
+        } else {
+            Precedence::Path
+        };
+        candidates.push((precedence, function));
+    }
+    if candidates.is_empty() {
+        let function = Arc::new(target.clone());
+        candidates.push((Precedence::Path, function));
+    }
+    candidates.sort_by_key(|(precedence, _)| *precedence);
+    let (winner_precedence, winner) = candidates[0].clone();
+    let mut definitions = vec![];
+    for (i, (precedence, function)) in candidates.iter().enumerate() {
+        let label = if i == 0 {
+            format!("{}: wins, {}", precedence.kind(), precedence.reason())
+        } else if *precedence == winner_precedence {
+            format!("{}: shadowed by {}", precedence.kind(), winner.path)
+        } else {
+            format!(
+                "{}: shadowed by the {}",
+                precedence.kind(),
+                winner_precedence.kind()
+            )
+        };
+        let location = Location::new(file_uri(&function.path)?, function.loc.into());
+        definitions.push((location, label));
+    }
+    Ok(definitions)
+}
+
+fn is_nested(file: &ParsedFile, function: &FunctionDefinition) -> bool {
+    let range = function.signature.name_range;
+    file.tree
+        .root_node()
+        .named_descendant_for_point_range(range.start, range.end)
+        .and_then(|node| parent_of_kind("function_definition", node))
+        .and_then(|node| node.parent())
+        .and_then(|node| parent_of_kind("function_definition", node))
+        .is_some()
+}
+
+fn global_definitions(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    name: String,
+) -> Result<Vec<(Location, String)>> {
+    let mut definitions = vec![];
+    for (path, file) in db_get_global(sender, receiver, name.clone(), SenderThread::Handler) {
+        let uri = file_uri(&path)?;
+        for v in &file.workspace.variables {
+            let v_ref = v.borrow();
+            if v_ref.is_global && v_ref.name == name {
+                let label = format!("global declaration in {}", file.name);
+                definitions.push((Location::new(uri.clone(), v_ref.loc.into()), label));
+            }
+        }
+    }
+    Ok(definitions)
+}
+
+fn variable_definitions(
+    file: &ParsedFile,
+    loc: Range,
+    var: &Arc<AtomicRefCell<VariableDefinition>>,
+) -> Result<Vec<(Location, String)>> {
+    let mut definitions = vec![];
+    match file.workspace.reaching.uses.get(&loc) {
+        Some(reaching) if reaching.assignments.len() > 1 => {
+            for (a_loc, a_var) in &reaching.assignments {
+                let label = format!(
+                    "assignment on line {}, may reach this use",
+                    a_loc.start.row + 1
+                );
+                definitions.push((assignment_location(file, *a_loc, a_var)?, label));
+            }
+        }
+        _ => {
+            let v_ref = var.borrow();
+            let v_loc = v_ref.loc;
+            let label = match &v_ref.script {
+                Some((path, _)) => format!("left behind by script {path}"),
+                None => format!("assignment on line {}", v_loc.start.row + 1),
+            };
+            drop(v_ref);
+            definitions.push((assignment_location(file, v_loc, var)?, label));
+        }
+    }
+    Ok(definitions)
+}
+
+/// Variables left behind by a script are defined at the call site, but point into the script.
+fn assignment_location(
+    file: &ParsedFile,
+    loc: Range,
+    var: &Arc<AtomicRefCell<VariableDefinition>>,
+) -> Result<Location> {
+    let v_ref = var.borrow();
+    match &v_ref.script {
+        Some((path, s_loc)) if v_ref.loc == loc => {
+            Ok(Location::new(file_uri(path)?, (*s_loc).into()))
+        }
+        _ => Ok(Location::new(file_uri(&file.path)?, loc.into())),
+    }
+}
+
+/// The `Contents.m` of a package if it has one, which documents it, and otherwise the files
+/// directly in its folder.
+fn namespace_definitions(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    namespace: &str,
+) -> Result<Vec<(Location, String)>> {
+    let files = db_fetch_parsed_files(sender, receiver, SenderThread::Handler).unwrap_or_default();
+    let mut files: Vec<&Arc<ParsedFile>> =
+        files.values().filter(|f| f.package == namespace).collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    if let Some(contents) = files.iter().find(|f| f.name == "Contents") {
+        return Ok(vec![(
+            Location::new(file_uri(&contents.path)?, Range::default().into()),
+            "package contents".into(),
+        )]);
+    }
+    let mut definitions = vec![];
+    for file in files {
+        definitions.push((
+            Location::new(file_uri(&file.path)?, Range::default().into()),
+            format!("file in package {namespace}"),
+        ));
+    }
+    Ok(definitions)
+}
+
+fn file_uri(path: &str) -> Result<Url> {
+    let path = String::from("file://") + path;
+    Ok(Url::parse(path.as_str())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    /// The paths and labels of the definitions of the symbol at `loc` in `name`.
+    fn definitions(
+        fixture: &Fixture,
+        name: &str,
+        row: usize,
+        column: usize,
+    ) -> Vec<(String, String)> {
+        definitions_for_symbol(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path(name),
+            Point { row, column },
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(location, label)| (location.uri.path().to_string(), label))
+        .collect()
+    }
+
+    #[test]
+    fn local_function_shadows_path() {
+        let fixture = Fixture::new(
+            "definition-local",
+            &[
+                (
+                    "main.m",
+                    "function main\nhelper();\nend\nfunction helper\nend\n",
+                ),
+                ("helper.m", "function helper\nend\n"),
+            ],
+        );
+        let definitions = definitions(&fixture, "main.m", 1, 0);
+        assert_eq!(definitions.len(), 2);
+        assert_eq!(definitions[0].0, fixture.path("main.m"));
+        assert!(definitions[0].1.starts_with("local function: wins"));
+        assert_eq!(definitions[1].0, fixture.path("helper.m"));
+        assert!(definitions[1].1.ends_with("shadowed by the local function"));
+    }
+
+    #[test]
+    fn private_function_shadows_path() {
+        let fixture = Fixture::new(
+            "definition-private",
+            &[
+                ("main.m", "helper();\n"),
+                ("helper.m", "function helper\nend\n"),
+                ("private/helper.m", "function helper\nend\n"),
+            ],
+        );
+        let definitions = definitions(&fixture, "main.m", 0, 0);
+        assert_eq!(definitions[0].0, fixture.path("private/helper.m"));
+        assert!(definitions[0].1.starts_with("private function: wins"));
+        assert_eq!(definitions[1].0, fixture.path("helper.m"));
+    }
+
+    #[test]
+    fn class_folder_shadows_path() {
+        let fixture = Fixture::new(
+            "definition-class-folder",
+            &[
+                ("@Shape/Shape.m", "classdef Shape\nend\n"),
+                (
+                    "@Shape/area.m",
+                    "function a = area(obj)\na = scale(obj);\nend\n",
+                ),
+                ("@Shape/scale.m", "function s = scale(obj)\ns = 1;\nend\n"),
+                ("scale.m", "function s = scale(x)\ns = x;\nend\n"),
+            ],
+        );
+        let definitions = definitions(&fixture, "@Shape/area.m", 1, 4);
+        assert_eq!(definitions[0].0, fixture.path("@Shape/scale.m"));
+        assert!(definitions[0]
+            .1
+            .starts_with("function in the class folder: wins"));
+        assert_eq!(definitions[1].0, fixture.path("scale.m"));
+    }
+
+    #[test]
+    fn current_folder_shadows_library_path() {
+        let fixture = Fixture::with_library(
+            "definition-path",
+            &[
+                ("main.m", "helper();\n"),
+                ("helper.m", "function helper\nend\n"),
+            ],
+            &[("helper.m", "function helper\nend\n")],
+        );
+        let definitions = definitions(&fixture, "main.m", 0, 0);
+        assert_eq!(definitions.len(), 2);
+        assert_eq!(definitions[0].0, fixture.path("helper.m"));
+        assert!(definitions[0]
+            .1
+            .starts_with("function in the current folder: wins"));
+        assert_eq!(definitions[1].0, fixture.path("lib/helper.m"));
+        assert!(definitions[1].1.starts_with("path function: shadowed by"));
+    }
+
+    #[test]
+    fn script_variable_points_into_the_script() {
+        let fixture = Fixture::new(
+            "definition-script",
+            &[("main.m", "setup\ndisp(a)\n"), ("setup.m", "a = 1;\n")],
+        );
+        let definitions = definitions(&fixture, "main.m", 1, 5);
+        assert_eq!(definitions.len(), 1);
+        assert_eq!(definitions[0].0, fixture.path("setup.m"));
+        assert_eq!(
+            definitions[0].1,
+            format!("left behind by script {}", fixture.path("setup.m"))
+        );
+    }
+
+    #[test]
+    fn package_goes_to_its_contents() {
+        let fixture = Fixture::new(
+            "definition-package-contents",
+            &[
+                ("+pkg/Contents.m", "% Tools of the package.\n"),
+                ("+pkg/helper.m", "function helper\nend\n"),
+                ("main.m", "pkg.helper();\n"),
+            ],
+        );
+        let definitions = definitions(&fixture, "main.m", 0, 1);
+        assert_eq!(
+            definitions,
+            vec![(fixture.path("+pkg/Contents.m"), "package contents".into())]
+        );
+    }
+
+    #[test]
+    fn package_without_contents_goes_to_its_files() {
+        let fixture = Fixture::new(
+            "definition-package-files",
+            &[
+                ("+pkg/helper.m", "function helper\nend\n"),
+                ("+pkg/other.m", "function other\nend\n"),
+                ("main.m", "pkg.helper();\n"),
+            ],
+        );
+        let paths: Vec<String> = definitions(&fixture, "main.m", 0, 1)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![fixture.path("+pkg/helper.m"), fixture.path("+pkg/other.m")]
+        );
+    }
+}
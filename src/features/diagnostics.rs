@@ -0,0 +1,251 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{Receiver, Sender};
+use lsp_server::Message;
+use lsp_types::notification::{Notification, PublishDiagnostics};
+use lsp_types::{Diagnostic, DiagnosticSeverity, PublishDiagnosticsParams, Url};
+
+use crate::code_loc;
+use crate::settings::Release;
+use crate::threads::db::{db_get_global, db_get_settings, db_is_workspace_scanned};
+use crate::types::{ParsedFile, Range, SenderThread, ThreadMessage};
+
+pub fn publish_diagnostics(
+    lsp_sender: &Sender<Message>,
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    thread: SenderThread,
+    parsed_file: &ParsedFile,
+) -> Result<()> {
+    let settings = db_get_settings(sender, receiver, thread.clone());
+    let mut diagnostics = vec![];
+    if settings.features.diagnostics {
+        // Other files declaring the global may not be indexed before the workspace scan ends,
+        // which analyses the open files again and republishes their diagnostics.
+        let unshared_global = settings.diagnostics.unshared_global.to_lsp();
+        if let Some(severity) =
+            unshared_global.filter(|_| db_is_workspace_scanned(sender, receiver, thread.clone()))
+        {
+            diagnostics.extend(global_diagnostics(
+                sender,
+                receiver,
+                thread,
+                parsed_file,
+                severity,
+            ));
+        }
+        if let Some(severity) = settings.diagnostics.maybe_undefined.to_lsp() {
+            diagnostics.extend(flow_diagnostics(parsed_file, severity));
+        }
+        if let Some(release) = settings.release {
+            diagnostics.extend(release_diagnostics(parsed_file, release));
+        }
+    }
+    send_diagnostics(lsp_sender, &parsed_file.path, diagnostics)
+}
+
+pub fn clear_diagnostics(lsp_sender: &Sender<Message>, path: &str) -> Result<()> {
+    send_diagnostics(lsp_sender, path, vec![])
+}
+
+fn send_diagnostics(
+    lsp_sender: &Sender<Message>,
+    path: &str,
+    diagnostics: Vec<Diagnostic>,
+) -> Result<()> {
+    let uri = Url::parse((String::from("file://") + path).as_str())?;
+    lsp_sender
+        .send(Message::Notification(lsp_server::Notification {
+            method: PublishDiagnostics::METHOD.to_string(),
+            params: serde_json::to_value(PublishDiagnosticsParams {
+                uri,
+                diagnostics,
+                version: None,
+            })?,
+        }))
+        .context(code_loc!())
+}
+
+/// Hints about globals that are declared in this file only. A global is only useful if some other
+/// file shares it, so those are usually leftovers or typos in the name.
+fn global_diagnostics(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    thread: SenderThread,
+    parsed_file: &ParsedFile,
+    severity: DiagnosticSeverity,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut checked: Vec<String> = vec![];
+    for var in &parsed_file.workspace.variables {
+        let v_ref = var.borrow();
+        if !v_ref.is_global || checked.contains(&v_ref.name) {
+            continue;
+        }
+        checked.push(v_ref.name.clone());
+        let files = db_get_global(sender, receiver, v_ref.name.clone(), thread.clone());
+        if files.keys().all(|p| *p == parsed_file.path) {
+            diagnostics.push(Diagnostic {
+                range: v_ref.loc.into(),
+                severity: Some(severity),
+                source: Some("matlab-lsp".into()),
+                message: format!(
+                    "Global variable {} is not declared in any other file.",
+                    v_ref.name
+                ),
+                ..Diagnostic::default()
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Syntax added after R2006a, as the kind of its node, the release adding it and what it is.
+const NEW_SYNTAX: [(&str, Release, &str); 1] = [(
+    "arguments_statement",
+    Release::new(2019, 'b'),
+    "Argument validation blocks",
+)];
+
+/// Errors about syntax the release targeted by the settings does not have yet.
+fn release_diagnostics(parsed_file: &ParsedFile, release: Release) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut nodes = vec![parsed_file.tree.root_node()];
+    while let Some(node) = nodes.pop() {
+        let syntax = NEW_SYNTAX
+            .iter()
+            .find(|(kind, added, _)| node.kind() == *kind && release < *added);
+        if let Some((_, added, what)) = syntax {
+            // Only the keyword, not the whole block.
+            let range = node.child(0).unwrap_or(node).range();
+            diagnostics.push(Diagnostic {
+                range: Range::from(range).into(),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("matlab-lsp".into()),
+                message: format!("{what} need {added} or later, the settings target {release}."),
+                ..Diagnostic::default()
+            });
+            continue;
+        }
+        let mut cursor = node.walk();
+        nodes.extend(node.named_children(&mut cursor));
+    }
+    diagnostics
+}
+
+/// Warnings about variables that are assigned on some of the paths reaching a use, but not on all
+/// of them.
+fn flow_diagnostics(parsed_file: &ParsedFile, severity: DiagnosticSeverity) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut checked: Vec<Range> = vec![];
+    for reference in &parsed_file.workspace.references {
+        let r_ref = reference.borrow();
+        if checked.contains(&r_ref.loc) {
+            continue;
+        }
+        checked.push(r_ref.loc);
+        if let Some(reaching) = parsed_file.workspace.reaching.uses.get(&r_ref.loc) {
+            if reaching.maybe_undefined && !reaching.assignments.is_empty() {
+                diagnostics.push(Diagnostic {
+                    range: r_ref.loc.into(),
+                    severity: Some(severity),
+                    source: Some("matlab-lsp".into()),
+                    message: format!("Variable {} may be undefined on some paths.", r_ref.name),
+                    ..Diagnostic::default()
+                });
+            }
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+
+    use crossbeam_channel::unbounded;
+
+    use super::*;
+    use crate::testing::{open_script, Fixture};
+    use crate::threads::dispatcher::serve_db;
+    use crate::types::State;
+
+    /// The diagnostics published for `file`, with the database on `sender` and `receiver`.
+    fn published(
+        sender: &Sender<ThreadMessage>,
+        receiver: &Receiver<ThreadMessage>,
+        file: &ParsedFile,
+    ) -> Vec<Diagnostic> {
+        let (lsp_sender, client) = unbounded();
+        publish_diagnostics(&lsp_sender, sender, receiver, SenderThread::Handler, file).unwrap();
+        let Ok(Message::Notification(notification)) = client.try_recv() else {
+            panic!("Expected the diagnostics.");
+        };
+        let params: PublishDiagnosticsParams = serde_json::from_value(notification.params).unwrap();
+        params.diagnostics
+    }
+
+    /// The diagnostics of `file` against an empty index, while the workspace is being scanned or
+    /// once it was.
+    fn published_while(scanning: bool, file: &ParsedFile) -> Vec<Diagnostic> {
+        let state = State {
+            scanning_workspace: scanning,
+            ..State::default()
+        };
+        let (sender, db_receiver) = unbounded();
+        let (db_sender, receiver) = unbounded();
+        spawn(move || serve_db(state, db_receiver, db_sender));
+        published(&sender, &receiver, file)
+    }
+
+    #[test]
+    fn unshared_global_waits_for_the_workspace_scan() {
+        let file = open_script("diagnostics-scan", "global g\ng = 1;\n");
+        assert!(published_while(true, &file).is_empty());
+        let diagnostics = published_while(false, &file);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Global variable g is not declared in any other file."
+        );
+    }
+
+    #[test]
+    fn syntax_newer_than_the_release_is_reported() {
+        let file = open_script(
+            "diagnostics-release",
+            "function f(x)\narguments\n    x double\nend\ndisp(x)\nend\n",
+        );
+        let diagnostics = release_diagnostics(&file, Release::new(2019, 'a'));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Argument validation blocks need R2019b or later, the settings target R2019a."
+        );
+        assert_eq!(diagnostics[0].range.start.line, 1);
+        assert!(release_diagnostics(&file, Release::new(2019, 'b')).is_empty());
+    }
+
+    #[test]
+    fn shared_global_is_not_reported() {
+        let fixture = Fixture::new(
+            "diagnostics-shared",
+            &[
+                ("a.m", "global g h\ng = 1;\nh = 2;\n"),
+                ("b.m", "global g\ndisp(g)\n"),
+            ],
+        );
+        let file = fixture.open("a.m");
+        let diagnostics = published(&fixture.sender, &fixture.receiver, &file);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Global variable h is not declared in any other file."
+        );
+    }
+}
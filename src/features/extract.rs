@@ -0,0 +1,294 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use lsp_types::{CodeAction, CodeActionKind, TextEdit, Url, WorkspaceEdit};
+use tree_sitter::{Node, Point};
+
+use crate::extractors::flow::{body_of, write_kind};
+use crate::extractors::symbols::parent_of_kind;
+use crate::types::{ParsedFile, Range, ReferenceTarget};
+
+/// Refactoring moving the statements in `range` to a new local function at the end of the file.
+/// The inputs are the variables read in the selection before being written there, the outputs are
+/// the variables written in the selection that are read after it.
+pub fn extract_function_actions(file: &ParsedFile, range: Range) -> Result<Vec<CodeAction>> {
+    if range.start == range.end || file.contents.is_empty() {
+        return Ok(vec![]);
+    }
+    let root = file.tree.root_node();
+    let Some(mut container) = root.descendant_for_point_range(range.start, range.end) else {
+        return Ok(vec![]);
+    };
+    while container.kind() != "block" && container.id() != root.id() {
+        match container.parent() {
+            Some(parent) => container = parent,
+            None => break,
+        }
+    }
+    let mut cursor = container.walk();
+    let statements: Vec<Node> = container
+        .named_children(&mut cursor)
+        .filter(|s| s.end_position() > range.start && s.start_position() < range.end)
+        .collect();
+    if statements.iter().all(|s| s.kind() == "comment")
+        || statements.iter().any(|s| {
+            !range.fully_contains(s.range().into())
+                || s.kind() == "function_definition"
+                || s.kind() == "class_definition"
+                || escapes(*s, *s)
+        })
+    {
+        return Ok(vec![]);
+    }
+    let first = statements[0];
+    let last = statements[statements.len() - 1];
+    let mut end_byte = last.end_byte();
+    let mut end = last.end_position();
+    if file.contents[end_byte..].starts_with(';') {
+        end_byte += 1;
+        end.column += 1;
+    }
+    let selection = Range {
+        start: first.start_position(),
+        end,
+    };
+    let body = body_of(first, root).map(|b| b.id());
+
+    // Reads and writes in the selection, in order.
+    let mut events: Vec<(Point, String, bool, bool)> = vec![];
+    for variable in &file.workspace.variables {
+        let v_ref = variable.borrow();
+        if v_ref.name.contains('.') || !selection.fully_contains(v_ref.loc) {
+            continue;
+        }
+        if let Some(node) = root.named_descendant_for_point_range(v_ref.loc.start, v_ref.loc.end) {
+            let kills = write_kind(node).unwrap_or(true);
+            events.push((v_ref.loc.start, v_ref.name.clone(), !kills, true));
+        }
+    }
+    let mut outputs: Vec<String> = vec![];
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if r_ref.name.contains('.')
+            || !matches!(
+                r_ref.target,
+                ReferenceTarget::Variable(_) | ReferenceTarget::UnknownVariable
+            )
+        {
+            continue;
+        }
+        let Some(node) = root.named_descendant_for_point_range(r_ref.loc.start, r_ref.loc.end)
+        else {
+            continue;
+        };
+        if selection.fully_contains(r_ref.loc) {
+            if events.iter().any(|(p, _, _, _)| *p == r_ref.loc.start) {
+                continue;
+            }
+            match write_kind(node) {
+                None => events.push((r_ref.loc.start, r_ref.name.clone(), true, false)),
+                Some(kills) => events.push((r_ref.loc.start, r_ref.name.clone(), !kills, true)),
+            }
+        } else if body_of(node, root).map(|b| b.id()) == body
+            && file
+                .workspace
+                .reaching
+                .uses
+                .get(&r_ref.loc)
+                .is_some_and(|r| {
+                    r.assignments
+                        .iter()
+                        .any(|(loc, _)| selection.fully_contains(*loc))
+                })
+            && !outputs.contains(&r_ref.name)
+        {
+            outputs.push(r_ref.name.clone());
+        }
+    }
+    events.sort_by_key(|(p, _, _, _)| *p);
+    let mut inputs: Vec<String> = vec![];
+    let mut written: Vec<String> = vec![];
+    for (_, name, reads, writes) in events {
+        if reads && !written.contains(&name) && !inputs.contains(&name) {
+            inputs.push(name.clone());
+        }
+        if writes && !written.contains(&name) {
+            written.push(name);
+        }
+    }
+    // Outputs of the enclosing function are read by its caller.
+    if let Some(name) =
+        parent_of_kind("function_definition", first).and_then(|f| f.child_by_field_name("name"))
+    {
+        let name_range: Range = name.range().into();
+        for function in file.workspace.functions.values() {
+            if function.path == file.path && function.signature.name_range == name_range {
+                for output in &function.signature.argout_names {
+                    if written.contains(output) && !outputs.contains(output) {
+                        outputs.push(output.clone());
+                    }
+                }
+            }
+        }
+    }
+    outputs.retain(|o| written.contains(o));
+
+    let mut name = String::from("extracted");
+    let mut i = 1;
+    while file.workspace.functions.values().any(|f| f.name == name) {
+        i += 1;
+        name = format!("extracted{i}");
+    }
+    let outputs = match outputs.len() {
+        0 => String::new(),
+        1 => format!("{} = ", outputs[0]),
+        _ => format!("[{}] = ", outputs.join(", ")),
+    };
+    let inputs = inputs.join(", ");
+    let line_start = first.start_byte() - first.start_position().column;
+    let indent = &file.contents[line_start..first.start_byte()];
+    let mut code = String::new();
+    for line in file.contents[first.start_byte()..end_byte].lines() {
+        let line = line.strip_prefix(indent).unwrap_or(line);
+        if line.trim().is_empty() {
+            code += "\n";
+        } else {
+            code += format!("    {line}\n").as_str();
+        }
+    }
+    let mut function = format!("\nfunction {outputs}{name}({inputs})\n{code}");
+    if uses_end(root) {
+        function += "end\n";
+    }
+    if !file.contents.ends_with('\n') {
+        function.insert(0, '\n');
+    }
+    let eof = root.end_position();
+    let edits = vec![
+        TextEdit {
+            range: selection.into(),
+            new_text: format!("{outputs}{name}({inputs});"),
+        },
+        TextEdit {
+            range: Range {
+                start: eof,
+                end: eof,
+            }
+            .into(),
+            new_text: function,
+        },
+    ];
+    let uri = Url::parse((String::from("file://") + file.path.as_str()).as_str())?;
+    Ok(vec![CodeAction {
+        title: "Extract to local function".into(),
+        kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri, edits)])),
+            ..WorkspaceEdit::default()
+        }),
+        ..CodeAction::default()
+    }])
+}
+
+/// Whether running `node` on its own could jump out of `selection`: a `return`, or a `break` or
+/// `continue` of a loop outside of it.
+fn escapes(node: Node, selection: Node) -> bool {
+    match node.kind() {
+        "return_statement" => return true,
+        "break_statement" | "continue_statement" => {
+            if node.id() == selection.id() {
+                return true;
+            }
+            let mut parent = node.parent();
+            while let Some(p) = parent {
+                if p.kind() == "for_statement" || p.kind() == "while_statement" {
+                    return false;
+                }
+                if p.id() == selection.id() {
+                    return true;
+                }
+                parent = p.parent();
+            }
+            return true;
+        }
+        "lambda" | "function_definition" => return false,
+        _ => {}
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.named_children(&mut cursor).collect();
+    children.into_iter().any(|c| escapes(c, selection))
+}
+
+/// Whether the functions of the file are terminated with `end`. They either all are or none is.
+pub fn uses_end(root: Node) -> bool {
+    let mut cursor = root.walk();
+    let functions: Vec<Node> = root
+        .named_children(&mut cursor)
+        .filter(|n| n.kind() == "function_definition")
+        .collect();
+    functions.iter().all(|f| {
+        u32::try_from(f.child_count().saturating_sub(1))
+            .ok()
+            .and_then(|i| f.child(i))
+            .is_some_and(|c| c.kind() == "end")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::open_script;
+
+    fn range(start: (usize, usize), end: (usize, usize)) -> Range {
+        Range {
+            start: Point {
+                row: start.0,
+                column: start.1,
+            },
+            end: Point {
+                row: end.0,
+                column: end.1,
+            },
+        }
+    }
+
+    #[test]
+    fn extracts_inputs_and_outputs() {
+        let file = open_script("extract-inputs", "a = 1;\nb = a + 1;\nc = 2 * b;\n");
+        let actions = extract_function_actions(&file, range((1, 0), (1, 10))).unwrap();
+        assert_eq!(actions.len(), 1);
+        let changes = actions[0].edit.as_ref().unwrap().changes.as_ref().unwrap();
+        let edits = changes.values().next().unwrap();
+        assert_eq!(edits[0].new_text, "b = extracted(a);");
+        assert_eq!(
+            edits[1].new_text,
+            "\nfunction b = extracted(a)\n    b = a + 1;\nend\n"
+        );
+    }
+
+    #[test]
+    fn does_not_output_variables_assigned_again_before_their_use() {
+        let code = "a = 1;\nb = a;\nb = 2;\ndisp(b)\n";
+        let file = open_script("extract-reassigned", code);
+        let actions = extract_function_actions(&file, range((1, 0), (1, 6))).unwrap();
+        let changes = actions[0].edit.as_ref().unwrap().changes.as_ref().unwrap();
+        let edits = changes.values().next().unwrap();
+        assert_eq!(edits[0].new_text, "extracted(a);");
+    }
+
+    #[test]
+    fn does_not_extract_break_of_outer_loop() {
+        let file = open_script(
+            "extract-break",
+            "while c\n    if d\n        break\n    end\nend\n",
+        );
+        let actions = extract_function_actions(&file, range((1, 4), (3, 7))).unwrap();
+        assert!(actions.is_empty());
+    }
+}
@@ -9,11 +9,13 @@ use log::debug;
 use matlab_beautifier::beautify;
 use matlab_beautifier::Arguments;
 
-pub fn format(code: &str) -> Result<String> {
+use crate::settings::FormatterSettings;
+
+pub fn format(code: &str, settings: &FormatterSettings) -> Result<String> {
     let mut arguments = Arguments {
         files: vec![],
-        sparse_math: false,
-        sparse_add: true,
+        sparse_math: settings.sparse_math,
+        sparse_add: settings.sparse_add,
         inplace: true,
     };
     debug!("Calling beautifier code.");
@@ -15,7 +15,8 @@ use lsp_types::{MarkupContent, MarkupKind};
 use tree_sitter::Point;
 
 use crate::extractors::symbols::parent_of_kind;
-use crate::threads::db::db_get_parsed_file;
+use crate::features::definition::definitions_for_symbol;
+use crate::threads::db::{db_fetch_functions, db_get_package, db_get_parsed_file};
 use crate::types::{
     FunctionDefinition, ParsedFile, SenderThread, ThreadMessage, VariableDefinition,
 };
@@ -25,6 +26,29 @@ pub fn hover_for_symbol(
     receiver: Receiver<ThreadMessage>,
     file: String,
     loc: Point,
+) -> Result<Option<(MarkupContent, MarkupContent)>> {
+    let hover = hover_for_reference(sender.clone(), receiver.clone(), file.clone(), loc)?;
+    let definitions = definitions_for_symbol(sender, receiver, file, loc).unwrap_or_default();
+    match hover {
+        Some((mut md, mut plain)) if definitions.len() > 1 => {
+            md.value += "\n\n---\nDefinitions:\n";
+            plain.value += "\n\nDefinitions:\n";
+            for (location, label) in definitions {
+                let line = location.range.start.line + 1;
+                md.value += format!("- `{}:{line}` {label}\n", location.uri.path()).as_str();
+                plain.value += format!("  {}:{line} {label}\n", location.uri.path()).as_str();
+            }
+            Ok(Some((md, plain)))
+        }
+        hover => Ok(hover),
+    }
+}
+
+fn hover_for_reference(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    file: String,
+    loc: Point,
 ) -> Result<Option<(MarkupContent, MarkupContent)>> {
     if let Some(file) = db_get_parsed_file(&sender, &receiver, file.clone(), SenderThread::Handler)
     {
@@ -35,8 +59,8 @@ pub fn hover_for_symbol(
                     crate::types::ReferenceTarget::Function(function) => {
                         return hover_function(function.clone());
                     }
-                    crate::types::ReferenceTarget::Namespace(ns) => {
-                        return hover_simple_info(format!("Namespace: {}", ns));
+                    crate::types::ReferenceTarget::Namespace(_) => {
+                        return hover_namespace(&sender, &receiver, &r_ref.name);
                     }
                     crate::types::ReferenceTarget::Script(s) => {
                         return hover_simple_info(format!("Script: {}", s));
@@ -130,6 +154,60 @@ fn hover_function(
     Ok(Some((md, plain)))
 }
 
+/// Lists the functions and subpackages of a package.
+fn hover_namespace(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    namespace: &str,
+) -> Result<Option<(MarkupContent, MarkupContent)>> {
+    debug!("Hovering a namespace.");
+    let functions: Vec<String> = db_fetch_functions(sender, receiver, SenderThread::Handler)
+        .unwrap_or_default()
+        .values()
+        .filter(|f| f.package == namespace)
+        .map(|f| f.name.clone())
+        .sorted()
+        .collect();
+    let prefix = format!("{namespace}.");
+    let packages: Vec<String> =
+        db_get_package(sender, receiver, prefix.clone(), SenderThread::Handler)
+            .into_iter()
+            .filter_map(|p| {
+                let rest = p.strip_prefix(&prefix)?;
+                Some(prefix.clone() + rest.split('.').next()?)
+            })
+            .sorted()
+            .dedup()
+            .collect();
+    let mut md = format!("Namespace: `{namespace}`");
+    let mut plain = format!("Namespace: {namespace}");
+    if !functions.is_empty() {
+        md += "\n\nFunctions:\n";
+        plain += "\n\nFunctions:\n";
+        for function in &functions {
+            md += format!("- `{function}`\n").as_str();
+            plain += format!("  {function}\n").as_str();
+        }
+    }
+    if !packages.is_empty() {
+        md += "\n\nSubpackages:\n";
+        plain += "\n\nSubpackages:\n";
+        for package in &packages {
+            md += format!("- `{package}`\n").as_str();
+            plain += format!("  {package}\n").as_str();
+        }
+    }
+    let md = MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: md,
+    };
+    let plain = MarkupContent {
+        kind: MarkupKind::PlainText,
+        value: plain,
+    };
+    Ok(Some((md, plain)))
+}
+
 fn hover_simple_info(info: String) -> Result<Option<(MarkupContent, MarkupContent)>> {
     let md = MarkupContent {
         kind: MarkupKind::Markdown,
@@ -141,3 +219,33 @@ fn hover_simple_info(info: String) -> Result<Option<(MarkupContent, MarkupConten
     };
     Ok(Some((md, plain)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn namespace_lists_functions_and_subpackages() {
+        let fixture = Fixture::new(
+            "hover-namespace",
+            &[
+                ("+pkg/helper.m", "function helper\nend\n"),
+                ("+pkg/+sub/inner.m", "function inner\nend\n"),
+                ("main.m", "pkg.helper();\n"),
+            ],
+        );
+        let (_, plain) = hover_for_symbol(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("main.m"),
+            Point { row: 0, column: 1 },
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            plain.value,
+            "Namespace: pkg\n\nFunctions:\n  helper\n\n\nSubpackages:\n  pkg.sub\n"
+        );
+    }
+}
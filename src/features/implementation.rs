@@ -0,0 +1,205 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{Receiver, Sender};
+use log::debug;
+use lsp_types::{Location, Url};
+use tree_sitter::Point;
+
+use crate::code_loc;
+use crate::extractors::fast::class_folder;
+use crate::features::type_hierarchy::qualified_name;
+use crate::threads::db::db_fetch_parsed_files;
+use crate::types::{ClassDefinition, ParsedFile, Range, SenderThread, ThreadMessage};
+
+/// Lists the methods overriding the method at `loc`, in every subclass of its class.
+pub fn find_implementations(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+    loc: Point,
+) -> Result<Vec<Location>> {
+    let files = db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler)
+        .ok_or(code_loc!("Could not fetch files."))?;
+    let classes: Vec<&ClassDefinition> = files.values().filter_map(|f| f.class.as_ref()).collect();
+    let (class, method) = method_at(&files, &classes, &path, loc)?;
+    debug!("Listing implementations of {}.{method}", class.name);
+    let mut locations = vec![];
+    let mut queue = vec![qualified_name(class)];
+    let mut seen = queue.clone();
+    while let Some(name) = queue.pop() {
+        for subclass in classes.iter().filter(|c| c.superclasses.contains(&name)) {
+            let subclass_name = qualified_name(subclass);
+            if seen.contains(&subclass_name) {
+                continue;
+            }
+            seen.push(subclass_name.clone());
+            queue.push(subclass_name);
+            for (path, range) in class_methods(&files, subclass)
+                .into_iter()
+                .filter(|(m, _, _)| *m == method)
+                .map(|(_, p, r)| (p, r))
+            {
+                locations.push(location(&path, range)?);
+            }
+        }
+    }
+    Ok(locations)
+}
+
+/// Finds the methods overridden by the method at `loc`: for each superclass, the closest class up
+/// its hierarchy defining or declaring a method with the same name.
+pub fn find_super_methods(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+    loc: Point,
+) -> Result<Vec<Location>> {
+    let files = db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler)
+        .ok_or(code_loc!("Could not fetch files."))?;
+    let classes: Vec<&ClassDefinition> = files.values().filter_map(|f| f.class.as_ref()).collect();
+    let (class, method) = method_at(&files, &classes, &path, loc)?;
+    debug!("Listing super methods of {}.{method}", class.name);
+    let mut locations = vec![];
+    let mut queue: Vec<String> = class.superclasses.clone();
+    let mut seen = vec![qualified_name(class)];
+    while let Some(name) = queue.pop() {
+        if seen.contains(&name) {
+            continue;
+        }
+        seen.push(name.clone());
+        if let Some(superclass) = classes.iter().find(|c| qualified_name(c) == name) {
+            let methods: Vec<(String, Range)> = class_methods(&files, superclass)
+                .into_iter()
+                .filter(|(m, _, _)| *m == method)
+                .map(|(_, p, r)| (p, r))
+                .collect();
+            if methods.is_empty() {
+                queue.extend(superclass.superclasses.clone());
+            }
+            for (path, range) in methods {
+                locations.push(location(&path, range)?);
+            }
+        }
+    }
+    Ok(locations)
+}
+
+/// The class and method name of the method whose name is at `loc`, either in a classdef file or in
+/// a method file of a class folder.
+fn method_at<'a>(
+    files: &HashMap<String, Arc<ParsedFile>>,
+    classes: &[&'a ClassDefinition],
+    path: &str,
+    loc: Point,
+) -> Result<(&'a ClassDefinition, String)> {
+    let file = files.get(path).ok_or(code_loc!("No such file."))?;
+    for class in classes {
+        if class.path != path {
+            continue;
+        }
+        if let Some((method, _)) = class.methods.iter().find(|(_, r)| r.contains(loc)) {
+            return Ok((class, method.clone()));
+        }
+    }
+    if class_folder(path).is_some() && file.method_range.is_some_and(|r| r.contains(loc)) {
+        let folder = Path::new(path).parent();
+        if let Some(class) = classes
+            .iter()
+            .find(|c| Path::new(&c.path).parent() == folder)
+        {
+            return Ok((class, file.name.clone()));
+        }
+    }
+    Err(code_loc!("Not a method."))
+}
+
+/// Methods of `class` with the file and range of their names, including the method files in its
+/// class folder.
+fn class_methods(
+    files: &HashMap<String, Arc<ParsedFile>>,
+    class: &ClassDefinition,
+) -> Vec<(String, String, Range)> {
+    let mut methods: Vec<(String, String, Range)> = class
+        .methods
+        .iter()
+        .map(|(m, r)| (m.clone(), class.path.clone(), *r))
+        .collect();
+    if class_folder(&class.path).is_some() {
+        let folder = Path::new(&class.path).parent();
+        for (path, file) in files {
+            if *path != class.path && Path::new(path).parent() == folder {
+                if let Some(range) = file.method_range {
+                    methods.push((file.name.clone(), path.clone(), range));
+                }
+            }
+        }
+    }
+    methods
+}
+
+fn location(path: &str, range: Range) -> Result<Location> {
+    let path = String::from("file://") + path;
+    Ok(Location::new(Url::parse(path.as_str())?, range.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    fn hierarchy() -> Fixture {
+        Fixture::new(
+            "implementation",
+            &[
+                (
+                    "Base.m",
+                    "classdef Base < handle\nmethods\nfunction draw(obj)\nend\nend\nend\n",
+                ),
+                ("@Child/Child.m", "classdef Child < Base\nend\n"),
+                ("@Child/draw.m", "function draw(obj)\nend\n"),
+            ],
+        )
+    }
+
+    fn paths(locations: Vec<Location>) -> Vec<String> {
+        locations
+            .into_iter()
+            .map(|l| l.uri.path().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn method_file_implements_superclass_method() {
+        let fixture = hierarchy();
+        let implementations = find_implementations(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("Base.m"),
+            Point { row: 2, column: 10 },
+        )
+        .unwrap();
+        assert_eq!(paths(implementations), vec![fixture.path("@Child/draw.m")]);
+    }
+
+    #[test]
+    fn method_file_overrides_superclass_method() {
+        let fixture = hierarchy();
+        let super_methods = find_super_methods(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("@Child/draw.m"),
+            Point { row: 0, column: 10 },
+        )
+        .unwrap();
+        assert_eq!(paths(super_methods), vec![fixture.path("Base.m")]);
+    }
+}
@@ -0,0 +1,425 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use atomic_refcell::AtomicRefCell;
+use lsp_types::{CodeAction, CodeActionKind, TextEdit, Url, WorkspaceEdit};
+use tree_sitter::{Node, Point};
+
+use crate::extractors::flow::{body_of, write_kind};
+use crate::extractors::symbols::parent_of_kind;
+use crate::types::{FunctionDefinition, ParsedFile, Range, ReferenceTarget, VariableDefinition};
+
+/// Refactorings inlining the variable or local function at `range`.
+pub fn inline_actions(file: &ParsedFile, range: Range) -> Result<Vec<CodeAction>> {
+    if file.contents.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut actions = vec![];
+    let mut variable = file
+        .workspace
+        .variables
+        .iter()
+        .find(|v| v.borrow().loc.contains(range.start))
+        .map(Arc::clone);
+    let mut function = None;
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if r_ref.loc.contains(range.start) {
+            match &r_ref.target {
+                ReferenceTarget::Variable(v) => variable = Some(Arc::clone(v)),
+                ReferenceTarget::Function(f) => function = Some(f.borrow().clone()),
+                _ => {}
+            }
+        }
+    }
+    if let Some(variable) = variable {
+        actions.extend(inline_variable(file, &variable)?);
+    }
+    if let Some(function) = function {
+        actions.extend(inline_function(file, &function)?);
+    }
+    Ok(actions)
+}
+
+fn inline_variable(
+    file: &ParsedFile,
+    variable: &Arc<AtomicRefCell<VariableDefinition>>,
+) -> Result<Option<CodeAction>> {
+    let root = file.tree.root_node();
+    let v_ref = variable.borrow();
+    if v_ref.is_global || v_ref.is_parameter || v_ref.script.is_some() {
+        return Ok(None);
+    }
+    let Some(node) = root.named_descendant_for_point_range(v_ref.loc.start, v_ref.loc.end) else {
+        return Ok(None);
+    };
+    let Some(assignment) = node.parent().filter(|p| p.kind() == "assignment") else {
+        return Ok(None);
+    };
+    let (Some(left), Some(right)) = (
+        assignment.child_by_field_name("left"),
+        assignment.child_by_field_name("right"),
+    ) else {
+        return Ok(None);
+    };
+    if left.id() != node.id() {
+        return Ok(None);
+    }
+    let body = body_of(node, root).map(|b| b.id());
+    let assignments = file
+        .workspace
+        .variables
+        .iter()
+        .map(|v| v.borrow())
+        .filter(|v| v.name == v_ref.name)
+        .filter_map(|v| root.named_descendant_for_point_range(v.loc.start, v.loc.end))
+        .filter(|n| body_of(*n, root).map(|b| b.id()) == body)
+        .count();
+    if assignments != 1 {
+        return Ok(None);
+    }
+    let mut uses: Vec<Node> = vec![];
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        let is_field = r_ref.name.starts_with(&format!("{}.", v_ref.name));
+        if (r_ref.name != v_ref.name && !is_field) || r_ref.loc == v_ref.loc {
+            continue;
+        }
+        let Some(n) = root.named_descendant_for_point_range(r_ref.loc.start, r_ref.loc.end) else {
+            continue;
+        };
+        let is_use =
+            matches!(&r_ref.target, ReferenceTarget::Variable(v) if Arc::ptr_eq(v, variable));
+        if body_of(n, root).map(|b| b.id()) != body {
+            // Uses in lambdas and nested functions would lose the variable.
+            if is_use {
+                return Ok(None);
+            }
+            continue;
+        }
+        // Assignments inside blocks and to parts of the variable, like `x(2) = 1`, are references.
+        if write_kind(n).is_some() {
+            return Ok(None);
+        }
+        if !is_use {
+            continue;
+        }
+        // The assignment must be the only one reaching the use, on every path.
+        let reaching = file.workspace.reaching.uses.get(&r_ref.loc);
+        if !reaching.is_some_and(|r| {
+            !r.maybe_undefined && r.assignments.len() == 1 && r.assignments[0].0 == v_ref.loc
+        }) {
+            return Ok(None);
+        }
+        uses.push(n);
+    }
+    let Some(last_use) = uses.iter().map(|u| u.end_position()).max() else {
+        return Ok(None);
+    };
+    // Indexing into the inlined expression is only valid if it is a plain name.
+    if right.kind() != "identifier" && uses.iter().any(|u| is_indexed(*u)) {
+        return Ok(None);
+    }
+    // The variables the expression reads must keep their value until the last use.
+    let mut names = vec![];
+    identifiers(file, right, &mut names);
+    if file.workspace.variables.iter().any(|v| {
+        let v = v.borrow();
+        names.iter().any(|(_, n)| *n == v.name)
+            && v.loc.start > assignment.end_position()
+            && v.loc.start < last_use
+    }) {
+        return Ok(None);
+    }
+    let text = file.contents[right.byte_range()].to_string();
+    let text = parenthesize(right, text);
+    let mut edits: Vec<TextEdit> = uses
+        .iter()
+        .map(|u| TextEdit {
+            range: Range::from(u.range()).into(),
+            new_text: text.clone(),
+        })
+        .collect();
+    edits.push(TextEdit {
+        range: statement_range(file, assignment).into(),
+        new_text: String::new(),
+    });
+    Ok(Some(code_action(
+        file,
+        format!("Inline variable {}", v_ref.name),
+        edits,
+    )?))
+}
+
+/// Inlines a local function whose body is a single `out = expression` statement, substituting the
+/// arguments of each call for the parameters, and removes it.
+fn inline_function(file: &ParsedFile, function: &FunctionDefinition) -> Result<Option<CodeAction>> {
+    let root = file.tree.root_node();
+    if function.path != file.path || function.signature.argout_names.len() != 1 {
+        return Ok(None);
+    }
+    let range = function.signature.name_range;
+    let Some(definition) = root
+        .named_descendant_for_point_range(range.start, range.end)
+        .and_then(|n| parent_of_kind("function_definition", n))
+    else {
+        return Ok(None);
+    };
+    if definition.parent().is_none_or(|p| p.id() != root.id()) || is_public(root, definition) {
+        return Ok(None);
+    }
+    let mut cursor = definition.walk();
+    let Some(block) = definition
+        .named_children(&mut cursor)
+        .find(|n| n.kind() == "block")
+    else {
+        return Ok(None);
+    };
+    let mut cursor = block.walk();
+    let statements: Vec<Node> = block
+        .named_children(&mut cursor)
+        .filter(|n| n.kind() != "comment")
+        .collect();
+    let [statement] = statements[..] else {
+        return Ok(None);
+    };
+    let (Some(left), Some(right)) = (
+        statement.child_by_field_name("left"),
+        statement.child_by_field_name("right"),
+    ) else {
+        return Ok(None);
+    };
+    if statement.kind() != "assignment"
+        || left.utf8_text(file.contents.as_bytes())? != function.signature.argout_names[0]
+    {
+        return Ok(None);
+    }
+    let parameters = &function.signature.argin_names;
+    let mut names = vec![];
+    identifiers(file, right, &mut names);
+    if names
+        .iter()
+        .any(|(_, n)| n == "nargin" || n == "nargout" || n == "varargin")
+    {
+        return Ok(None);
+    }
+    let mut edits = vec![];
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        let ReferenceTarget::Function(target) = &r_ref.target else {
+            continue;
+        };
+        let target = target.borrow();
+        if target.path != function.path || target.name != function.name {
+            continue;
+        }
+        let Some(node) = root.named_descendant_for_point_range(r_ref.loc.start, r_ref.loc.end)
+        else {
+            continue;
+        };
+        let call = node.parent().filter(|p| p.kind() == "function_call");
+        let mut arguments: Vec<Node> = vec![];
+        if let Some(call) = call {
+            let mut cursor = call.walk();
+            let args = call
+                .named_children(&mut cursor)
+                .find(|n| n.kind() == "arguments");
+            if let Some(args) = args {
+                let mut cursor = args.walk();
+                arguments = args.named_children(&mut cursor).collect();
+            }
+        } else if node.parent().is_some_and(|p| p.kind() != "block") {
+            // Handles and command syntax calls cannot be inlined.
+            return Ok(None);
+        }
+        if arguments.len() != parameters.len() {
+            return Ok(None);
+        }
+        let mut text = String::new();
+        let mut byte = right.start_byte();
+        for (start, name) in &names {
+            if let Some(i) = parameters.iter().position(|p| p == name) {
+                text += &file.contents[byte..*start];
+                let argument = file.contents[arguments[i].byte_range()].to_string();
+                text += parenthesize(arguments[i], argument).as_str();
+                byte = start + name.len();
+            }
+        }
+        text += &file.contents[byte..right.end_byte()];
+        let call = call.unwrap_or(node);
+        edits.push(TextEdit {
+            range: Range::from(call.range()).into(),
+            new_text: parenthesize(right, text),
+        });
+    }
+    if edits.is_empty() {
+        return Ok(None);
+    }
+    edits.push(TextEdit {
+        range: statement_range(file, definition).into(),
+        new_text: String::new(),
+    });
+    Ok(Some(code_action(
+        file,
+        format!("Inline function {}", function.name),
+        edits,
+    )?))
+}
+
+/// Whether the node is the first function of a function file, which can be called from other
+/// files.
+fn is_public(root: Node, definition: Node) -> bool {
+    let mut cursor = root.walk();
+    let first = root
+        .named_children(&mut cursor)
+        .find(|n| n.kind() != "comment");
+    first.is_some_and(|f| f.id() == definition.id())
+}
+
+/// Whether the node is indexed into, as in `x(2)`, `x{2}` or `x.a`.
+fn is_indexed(node: Node) -> bool {
+    node.parent().is_some_and(|p| {
+        (p.kind() == "function_call" && p.child_by_field_name("name") == Some(node))
+            || (p.kind() == "cell_indexing" || p.kind() == "field_expression")
+                && p.named_child(0) == Some(node)
+    })
+}
+
+/// Identifiers in the node, with their start bytes. Field names are left out.
+fn identifiers(file: &ParsedFile, node: Node, names: &mut Vec<(usize, String)>) {
+    if node.kind() == "identifier" {
+        if node.prev_sibling().is_some_and(|s| s.kind() == ".") {
+            return;
+        }
+        if let Ok(text) = node.utf8_text(file.contents.as_bytes()) {
+            names.push((node.start_byte(), text.to_string()));
+        }
+        return;
+    }
+    if node.kind() == "lambda" {
+        return;
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.named_children(&mut cursor).collect();
+    for child in children {
+        identifiers(file, child, names);
+    }
+}
+
+fn parenthesize(node: Node, text: String) -> String {
+    match node.kind() {
+        "identifier" | "number" | "string" | "boolean" | "function_call" | "field_expression"
+        | "parenthesis" | "matrix" | "cell" => text,
+        _ => format!("({text})"),
+    }
+}
+
+/// Range of a statement with its trailing semicolon, extended to whole lines if nothing else is
+/// on them.
+fn statement_range(file: &ParsedFile, node: Node) -> Range {
+    let mut end_byte = node.end_byte();
+    let mut end = node.end_position();
+    if file.contents[end_byte..].starts_with(';') {
+        end_byte += 1;
+        end.column += 1;
+    }
+    let start = node.start_position();
+    let line_start = node.start_byte() - start.column;
+    let before = &file.contents[line_start..node.start_byte()];
+    let after = file.contents[end_byte..].split('\n').next().unwrap_or("");
+    if before.trim().is_empty() && after.trim().is_empty() {
+        Range {
+            start: Point {
+                row: start.row,
+                column: 0,
+            },
+            end: Point {
+                row: end.row + 1,
+                column: 0,
+            },
+        }
+    } else {
+        Range { start, end }
+    }
+}
+
+fn code_action(file: &ParsedFile, title: String, edits: Vec<TextEdit>) -> Result<CodeAction> {
+    let uri = Url::parse((String::from("file://") + file.path.as_str()).as_str())?;
+    Ok(CodeAction {
+        title,
+        kind: Some(CodeActionKind::REFACTOR_INLINE),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri, edits)])),
+            ..WorkspaceEdit::default()
+        }),
+        ..CodeAction::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::open_script;
+
+    fn at(row: usize, column: usize) -> Range {
+        let point = Point { row, column };
+        Range {
+            start: point,
+            end: point,
+        }
+    }
+
+    fn edits(action: &CodeAction) -> Vec<TextEdit> {
+        let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+        changes.values().next().unwrap().clone()
+    }
+
+    #[test]
+    fn inlines_variable_assigned_once() {
+        let file = open_script("inline-once", "x = a + 1;\ny = 2 * x;\n");
+        let actions = inline_actions(&file, at(0, 0)).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].title, "Inline variable x");
+        let edits = edits(&actions[0]);
+        assert_eq!(edits.len(), 2);
+        assert_eq!(edits[0].new_text, "(a + 1)");
+        assert_eq!(edits[1].new_text, "");
+    }
+
+    #[test]
+    fn does_not_inline_variable_assigned_in_a_block() {
+        let file = open_script("inline-block", "x = 1;\nif c\n    x = 2;\nend\ndisp(x)\n");
+        assert!(inline_actions(&file, at(0, 0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn does_not_inline_variable_assigned_on_some_paths() {
+        let file = open_script("inline-branch", "if c\n    x = 1;\nend\ndisp(x)\n");
+        assert!(inline_actions(&file, at(1, 4)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn does_not_inline_variable_used_in_a_lambda() {
+        let file = open_script("inline-lambda", "x = 1;\nf = @() x;\ndisp(x)\n");
+        assert!(inline_actions(&file, at(0, 0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn does_not_inline_partially_assigned_variable() {
+        let file = open_script("inline-partial", "x = [1 2];\nx(2) = 3;\ndisp(x)\n");
+        assert!(inline_actions(&file, at(0, 0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn does_not_inline_unused_variable() {
+        let file = open_script("inline-unused", "x = 1;\n");
+        assert!(inline_actions(&file, at(0, 0)).unwrap().is_empty());
+    }
+}
@@ -4,8 +4,20 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub mod call_hierarchy;
+pub mod code_actions;
+pub mod code_lens;
 pub mod completion;
+pub mod create_function;
+pub mod definition;
+pub mod diagnostics;
+pub mod extract;
 pub mod formatter;
 pub mod hover;
+pub mod implementation;
+pub mod inline;
 pub mod references;
+pub mod rename;
+pub mod script_to_function;
 pub mod semantic;
+pub mod type_hierarchy;
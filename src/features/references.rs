@@ -4,7 +4,6 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
@@ -16,7 +15,7 @@ use tree_sitter::Point;
 
 use crate::code_loc;
 use crate::extractors::symbols::parent_of_kind;
-use crate::threads::db::{db_fetch_parsed_files, db_get_parsed_file};
+use crate::threads::db::{db_fetch_parsed_files, db_get_global, db_get_parsed_file};
 use crate::types::{
     FunctionDefinition, ParsedFile, ReferenceTarget, SenderThread, ThreadMessage,
     VariableDefinition,
@@ -58,6 +57,11 @@ pub fn find_references_to_symbol(
                 }
                 ReferenceTarget::Variable(v) => {
                     drop(r_ref);
+                    if v.borrow().is_global {
+                        let name = v.borrow().name.clone();
+                        drop(file);
+                        return find_references_to_global(sender, receiver, name, inc_dec);
+                    }
                     return find_references_to_variable(&file, v.clone(), inc_dec);
                 }
                 ReferenceTarget::UnknownVariable => {
@@ -68,9 +72,11 @@ pub fn find_references_to_symbol(
                     }
                     return Ok(vec![]);
                 }
-                ReferenceTarget::Namespace(ns) => {
+                ReferenceTarget::Namespace(_) => {
+                    let name = r_ref.name.clone();
                     drop(r_ref);
-                    return find_references_to_namespace(&file, ns.clone());
+                    drop(file);
+                    return find_references_to_namespace(sender, receiver, name);
                 }
                 _ => return Ok(vec![]),
             }
@@ -78,6 +84,10 @@ pub fn find_references_to_symbol(
     }
     for v in &file.workspace.variables {
         if v.borrow().loc.contains(loc) {
+            if v.borrow().is_global {
+                let name = v.borrow().name.clone();
+                return find_references_to_global(sender, receiver, name, inc_dec);
+            }
             return find_references_to_variable(&file, v.clone(), inc_dec);
         }
     }
@@ -95,7 +105,7 @@ pub fn find_references_to_symbol(
     Ok(vec![])
 }
 
-fn find_references_to_function(
+pub fn find_references_to_function(
     sender: Sender<ThreadMessage>,
     receiver: Receiver<ThreadMessage>,
     function: Arc<AtomicRefCell<FunctionDefinition>>,
@@ -103,13 +113,14 @@ fn find_references_to_function(
 ) -> Result<Vec<(Location, DocumentHighlightKind)>> {
     let mut refs = vec![];
     for (path, file) in
-        db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler).unwrap_or(HashMap::new())
+        db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler).unwrap_or_default()
     {
         let f_refs = file.workspace.references.iter().map(|r| (path.clone(), r));
         for (r_path, reference) in f_refs {
             let r_ref = reference.borrow();
             if let ReferenceTarget::Function(target) = &r_ref.target {
-                if function.borrow().path == target.borrow().path {
+                let (f_ref, t_ref) = (function.borrow(), target.borrow());
+                if f_ref.path == t_ref.path && f_ref.name == t_ref.name {
                     let path = String::from("file://") + r_path.as_str();
                     let uri = Url::parse(path.as_str())?;
                     let location = Location::new(uri.clone(), r_ref.loc.into());
@@ -130,14 +141,14 @@ fn find_references_to_function(
     Ok(refs)
 }
 
-fn find_references_to_script(
+pub fn find_references_to_script(
     sender: Sender<ThreadMessage>,
     receiver: Receiver<ThreadMessage>,
     script: String,
 ) -> Result<Vec<(Location, DocumentHighlightKind)>> {
     let mut refs = vec![];
     for (path, file) in
-        db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler).unwrap_or(HashMap::new())
+        db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler).unwrap_or_default()
     {
         let f_refs = file.workspace.references.iter().map(|r| (path.clone(), r));
         for (r_path, reference) in f_refs {
@@ -180,17 +191,57 @@ fn find_references_to_variable(
     Ok(refs)
 }
 
+/// Globals are shared by every file that declares them, so their references are collected from all
+/// the files in the global variable index instead of only from the current file.
+fn find_references_to_global(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    name: String,
+    inc_dec: bool,
+) -> Result<Vec<(Location, DocumentHighlightKind)>> {
+    let mut refs = vec![];
+    for (path, file) in db_get_global(&sender, &receiver, name.clone(), SenderThread::Handler) {
+        let path = String::from("file://") + path.as_str();
+        let uri = Url::parse(path.as_str())?;
+        for r in &file.workspace.references {
+            let r_ref = r.borrow();
+            if let ReferenceTarget::Variable(v) = &r_ref.target {
+                let v_ref = v.borrow();
+                if v_ref.is_global && v_ref.name == name {
+                    let location = Location::new(uri.clone(), r_ref.loc.into());
+                    refs.push((location, DocumentHighlightKind::READ));
+                }
+            }
+        }
+        if inc_dec {
+            for v in &file.workspace.variables {
+                let v_ref = v.borrow();
+                if v_ref.is_global && v_ref.name == name {
+                    let location = Location::new(uri.clone(), v_ref.loc.into());
+                    refs.push((location, DocumentHighlightKind::WRITE));
+                }
+            }
+        }
+    }
+    Ok(refs)
+}
+
+/// Packages are visible from every file, so references to them are collected across the
+/// workspace. A reference to a package is one of the segments of a qualified name.
 fn find_references_to_namespace(
-    parsed_file: &ParsedFile,
-    ns: String,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    name: String,
 ) -> Result<Vec<(Location, DocumentHighlightKind)>> {
-    let path = String::from("file://") + parsed_file.path.as_str();
-    let uri = Url::parse(path.as_str())?;
     let mut refs = vec![];
-    for r in &parsed_file.workspace.references {
-        let r_ref = r.borrow();
-        if let ReferenceTarget::Namespace(v) = &r_ref.target {
-            if ns == *v {
+    for (path, file) in
+        db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler).unwrap_or_default()
+    {
+        let path = String::from("file://") + path.as_str();
+        let uri = Url::parse(path.as_str())?;
+        for r in &file.workspace.references {
+            let r_ref = r.borrow();
+            if r_ref.name == name && matches!(r_ref.target, ReferenceTarget::Namespace(_)) {
                 let location = Location::new(uri.clone(), r_ref.loc.into());
                 refs.push((location, DocumentHighlightKind::TEXT));
             }
@@ -252,3 +303,34 @@ fn base_definition(
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn namespace_references_span_the_workspace() {
+        let fixture = Fixture::new(
+            "references-namespace",
+            &[
+                ("+pkg/helper.m", "function helper\nend\n"),
+                ("main.m", "pkg.helper();\n"),
+                ("other.m", "x = 1;\npkg.helper();\n"),
+            ],
+        );
+        let mut paths: Vec<String> = find_references_to_symbol(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("main.m"),
+            Point { row: 0, column: 1 },
+            false,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(location, _)| location.uri.path().to_string())
+        .collect();
+        paths.sort();
+        assert_eq!(paths, vec![fixture.path("main.m"), fixture.path("other.m")]);
+    }
+}
@@ -0,0 +1,900 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use atomic_refcell::AtomicRefCell;
+use crossbeam_channel::{Receiver, Sender};
+use itertools::Itertools;
+use lsp_types::{
+    DocumentChangeOperation, DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier,
+    RenameFile, RenameFileOptions, ResourceOp, TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+};
+use regex::Regex;
+use tree_sitter::{Node, Point};
+
+use crate::code_loc;
+use crate::extractors::fast::{class_folder, folder_package};
+use crate::extractors::flow::body_of;
+use crate::features::references::{
+    find_references_to_function, find_references_to_script, find_references_to_symbol,
+};
+use crate::threads::db::{
+    db_fetch_parsed_files, db_fetch_workspace_paths, db_get_function, db_get_package,
+    db_get_parsed_file,
+};
+use crate::types::{
+    ClassDefinition, FunctionDefinition, ParsedFile, Range, ReferenceTarget, SenderThread,
+    ThreadMessage, VariableDefinition,
+};
+
+/// Keywords of the language, as listed by `iskeyword`.
+const KEYWORDS: [&str; 20] = [
+    "break",
+    "case",
+    "catch",
+    "classdef",
+    "continue",
+    "else",
+    "elseif",
+    "end",
+    "for",
+    "function",
+    "global",
+    "if",
+    "otherwise",
+    "parfor",
+    "persistent",
+    "return",
+    "spmd",
+    "switch",
+    "try",
+    "while",
+];
+
+/// Value of `namelengthmax`. Longer names are silently truncated by MATLAB.
+const NAME_LENGTH_MAX: usize = 63;
+
+/// The range of the identifier at `loc`, if it can be renamed. Builtins and unknown symbols cannot,
+/// and neither can anything defined in a library file outside the workspace.
+pub fn prepare_rename(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+    loc: Point,
+) -> Result<Range> {
+    let workspace = db_fetch_workspace_paths(&sender, &receiver, SenderThread::Handler);
+    let in_workspace = |p: &str| workspace.iter().any(|w| Path::new(p).starts_with(w));
+    if !in_workspace(&path) {
+        return Err(anyhow!("This file is outside the workspace."));
+    }
+    let file = db_get_parsed_file(&sender, &receiver, path.clone(), SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if !r_ref.loc.contains(loc) {
+            continue;
+        }
+        let definition = match &r_ref.target {
+            ReferenceTarget::Function(f) => f.borrow().path.clone(),
+            ReferenceTarget::Script(p) => p.clone(),
+            ReferenceTarget::Namespace(_) => {
+                let files = db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler)
+                    .unwrap_or_default();
+                files
+                    .values()
+                    .find(|f| f.package == r_ref.name)
+                    .map(|f| f.path.clone())
+                    .unwrap_or_default()
+            }
+            ReferenceTarget::Variable(_) => return Ok(r_ref.loc),
+            ReferenceTarget::UnknownVariable if r_ref.name.contains('.') => return Ok(r_ref.loc),
+            ReferenceTarget::UnknownVariable => {
+                return Err(anyhow!("{} is not defined.", r_ref.name))
+            }
+            ReferenceTarget::UnknownFunction => {
+                return Err(anyhow!("{} is a builtin or unknown function.", r_ref.name))
+            }
+        };
+        if !in_workspace(&definition) {
+            return Err(anyhow!("{} is defined outside the workspace.", r_ref.name));
+        }
+        return Ok(r_ref.loc);
+    }
+    if let Some(v) = file
+        .workspace
+        .variables
+        .iter()
+        .find(|v| v.borrow().loc.contains(loc))
+    {
+        return Ok(v.borrow().loc);
+    }
+    if let Some(f) = file
+        .workspace
+        .functions
+        .values()
+        .find(|f| f.path == path && f.signature.name_range.contains(loc))
+    {
+        return Ok(f.signature.name_range);
+    }
+    Err(anyhow!("There is no symbol to rename here."))
+}
+
+/// Renames the symbol at `loc`. Renaming the public function of a file also renames the file, and
+/// renaming a package segment moves its `+` folder, as MATLAB resolves both through the path.
+pub fn rename(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+    loc: Point,
+    new_name: String,
+) -> Result<WorkspaceEdit> {
+    validate_name(&new_name)?;
+    let file = db_get_parsed_file(&sender, &receiver, path.clone(), SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    let mut function = None;
+    let mut package = None;
+    let mut variable = file
+        .workspace
+        .variables
+        .iter()
+        .find(|v| v.borrow().loc.contains(loc))
+        .map(Arc::clone);
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if r_ref.loc.contains(loc) {
+            match &r_ref.target {
+                ReferenceTarget::Function(f) => function = Some(Arc::clone(f)),
+                ReferenceTarget::Namespace(_) => package = Some(r_ref.name.clone()),
+                ReferenceTarget::Variable(v) => variable = Some(Arc::clone(v)),
+                _ => {}
+            }
+        }
+    }
+    if function.is_none() && package.is_none() {
+        function = file
+            .workspace
+            .functions
+            .values()
+            .find(|f| f.path == path && f.signature.name_range.contains(loc))
+            .map(|f| Arc::new(AtomicRefCell::new(f.as_ref().clone())));
+    }
+    if let Some(variable) = &variable {
+        variable_collision(&sender, &receiver, &file, variable, &new_name)?;
+    }
+    drop(file);
+    if let Some(package) = package {
+        return rename_package(sender, receiver, package, new_name);
+    }
+    if let Some(function) = function {
+        function_collision(&sender, &receiver, &function.borrow(), &new_name)?;
+        let file = public_function_file(&sender, &receiver, &function.borrow());
+        if let Some(file) = file {
+            return rename_public_function(sender, receiver, function, file, new_name);
+        }
+    }
+    let references = find_references_to_symbol(sender, receiver, path, loc, true)?;
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for (reference, _) in references {
+        changes.entry(reference.uri).or_default().push(TextEdit {
+            range: reference.range,
+            new_text: new_name.clone(),
+        });
+    }
+    Ok(WorkspaceEdit::new(changes))
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let regex = Regex::new(r"^[a-zA-Z][a-zA-Z_0-9]*$")?;
+    if !regex.is_match(name) {
+        return Err(anyhow!("The name is not a valid identifier."));
+    }
+    if KEYWORDS.contains(&name) {
+        return Err(anyhow!("{name} is a keyword."));
+    }
+    if name.len() > NAME_LENGTH_MAX {
+        return Err(anyhow!(
+            "The name is longer than namelengthmax ({NAME_LENGTH_MAX})."
+        ));
+    }
+    Ok(())
+}
+
+/// Fails if a variable of the same body, or a function visible from the file, already has the name.
+fn variable_collision(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    file: &ParsedFile,
+    variable: &Arc<AtomicRefCell<VariableDefinition>>,
+    new_name: &str,
+) -> Result<()> {
+    if variable.borrow().name == new_name {
+        return Ok(());
+    }
+    let root = file.tree.root_node();
+    let body = |range: Range| {
+        root.named_descendant_for_point_range(range.start, range.end)
+            .and_then(|n| body_of(n, root))
+            .map(|b| b.id())
+    };
+    let scope = body(variable.borrow().loc);
+    if file
+        .workspace
+        .variables
+        .iter()
+        .map(|v| v.borrow())
+        .any(|v| v.name == new_name && body(v.loc) == scope)
+    {
+        return Err(anyhow!("A variable named {new_name} already exists."));
+    }
+    if file.workspace.functions.contains_key(new_name)
+        || db_get_function(sender, receiver, new_name.into(), SenderThread::Handler).is_some()
+    {
+        return Err(anyhow!("A function named {new_name} already exists."));
+    }
+    Ok(())
+}
+
+/// Fails if a function of the same package, or a local function of the same file, already has the
+/// name.
+fn function_collision(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    function: &FunctionDefinition,
+    new_name: &str,
+) -> Result<()> {
+    if function.name == new_name {
+        return Ok(());
+    }
+    let qualified = if function.package.is_empty() {
+        new_name.to_string()
+    } else {
+        format!("{}.{new_name}", function.package)
+    };
+    let local = db_get_parsed_file(
+        sender,
+        receiver,
+        function.path.clone(),
+        SenderThread::Handler,
+    )
+    .is_some_and(|f| {
+        f.workspace
+            .functions
+            .values()
+            .any(|f| f.path == function.path && f.name == new_name)
+    });
+    if local
+        || db_get_function(sender, receiver, qualified.clone(), SenderThread::Handler).is_some()
+    {
+        return Err(anyhow!("A function named {qualified} already exists."));
+    }
+    Ok(())
+}
+
+/// Renames the function and its file, and the imports naming it.
+fn rename_public_function(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    function: Arc<AtomicRefCell<FunctionDefinition>>,
+    file: Arc<ParsedFile>,
+    new_name: String,
+) -> Result<WorkspaceEdit> {
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    function_changes(&sender, &receiver, function, &new_name, &mut changes)?;
+    let old_path = Path::new(&file.path);
+    let new_path = old_path.with_file_name(format!("{new_name}.m"));
+    let rename = RenameFile {
+        old_uri: file_uri(&file.path)?,
+        new_uri: file_uri(&new_path.to_string_lossy())?,
+        options: Some(RenameFileOptions {
+            overwrite: Some(false),
+            ignore_if_exists: None,
+        }),
+        annotation_id: None,
+    };
+    Ok(document_changes(changes, rename))
+}
+
+/// Renames the last segment of `package`, moving its folder and rewriting the qualified names and
+/// imports going through it.
+fn rename_package(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    package: String,
+    new_name: String,
+) -> Result<WorkspaceEdit> {
+    let new_package = match package.rsplit_once('.') {
+        Some((parent, _)) => format!("{parent}.{new_name}"),
+        None => new_name.clone(),
+    };
+    if db_get_package(
+        &sender,
+        &receiver,
+        new_package.clone(),
+        SenderThread::Handler,
+    )
+    .contains(&new_package)
+    {
+        return Err(anyhow!("A package named {new_package} already exists."));
+    }
+    let files = workspace_files(&sender, &receiver);
+    let folder_name = package.split('.').map(|s| format!("/+{s}")).join("");
+    let folder = files
+        .values()
+        .filter(|f| f.package == package || f.package.starts_with(&format!("{package}.")))
+        .find_map(|f| {
+            f.path
+                .find(&(folder_name.clone() + "/"))
+                .map(|i| f.path[..i + folder_name.len()].to_string())
+        })
+        .ok_or(code_loc!("Could not find the package folder."))?;
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    package_changes(&files, &package, &new_name, &mut changes)?;
+    let new_folder = Path::new(&folder).with_file_name(format!("+{new_name}"));
+    let rename = RenameFile {
+        old_uri: file_uri(&folder)?,
+        new_uri: file_uri(&new_folder.to_string_lossy())?,
+        options: Some(RenameFileOptions {
+            overwrite: Some(false),
+            ignore_if_exists: None,
+        }),
+        annotation_id: None,
+    };
+    Ok(document_changes(changes, rename))
+}
+
+/// The edits following files and folders renamed by the client, which renames them itself. A
+/// function file renames its public function, a class file or `@` folder its class, and a `+`
+/// folder its package. Moves to another folder are left alone.
+pub fn file_rename_changes(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    renames: Vec<(String, String)>,
+) -> Result<WorkspaceEdit> {
+    let files = workspace_files(&sender, &receiver);
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for (old_path, new_path) in renames {
+        let (old, new) = (Path::new(&old_path), Path::new(&new_path));
+        if old.parent() != new.parent() {
+            continue;
+        }
+        let (Some(old_name), Some(new_name)) = (
+            old.file_name().map(|n| n.to_string_lossy().to_string()),
+            new.file_name().map(|n| n.to_string_lossy().to_string()),
+        ) else {
+            continue;
+        };
+        if let (Some(_), Some(new_name)) = (old_name.strip_prefix('+'), new_name.strip_prefix('+'))
+        {
+            package_changes(&files, &folder_package(old), new_name, &mut changes)?;
+        } else if let (Some(_), Some(new_name)) =
+            (old_name.strip_prefix('@'), new_name.strip_prefix('@'))
+        {
+            let class = files
+                .values()
+                .filter(|f| f.path.starts_with(&format!("{old_path}/")))
+                .find_map(|f| f.class.clone());
+            if let Some(class) = class {
+                class_changes(&files, &class, new_name, &mut changes)?;
+            }
+        } else if let (Some(_), Some(new_name)) =
+            (old_name.strip_suffix(".m"), new_name.strip_suffix(".m"))
+        {
+            let Some(file) = files.get(&old_path) else {
+                continue;
+            };
+            if let Some(class) = &file.class {
+                if class_folder(&old_path).is_none() {
+                    class_changes(&files, class, new_name, &mut changes)?;
+                }
+            } else if file.is_script {
+                let references =
+                    find_references_to_script(sender.clone(), receiver.clone(), old_path.clone())?;
+                for (reference, _) in references {
+                    changes.entry(reference.uri).or_default().push(TextEdit {
+                        range: reference.range,
+                        new_text: new_name.to_string(),
+                    });
+                }
+            } else if let Some(function) = file.workspace.functions.values().find(|f| {
+                f.path == old_path && public_function_file(&sender, &receiver, f).is_some()
+            }) {
+                let function = Arc::new(AtomicRefCell::new(function.as_ref().clone()));
+                function_changes(&sender, &receiver, function, new_name, &mut changes)?;
+            }
+        }
+    }
+    Ok(WorkspaceEdit::new(changes))
+}
+
+/// Edits renaming a function at its declaration and call sites, and in the imports naming it.
+fn function_changes(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    function: Arc<AtomicRefCell<FunctionDefinition>>,
+    new_name: &str,
+    changes: &mut HashMap<Url, Vec<TextEdit>>,
+) -> Result<()> {
+    let references =
+        find_references_to_function(sender.clone(), receiver.clone(), function.clone(), true)?;
+    for (reference, _) in references {
+        changes.entry(reference.uri).or_default().push(TextEdit {
+            range: reference.range,
+            new_text: new_name.to_string(),
+        });
+    }
+    let f_ref = function.borrow();
+    let qualify = |name: &str| {
+        if f_ref.package.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{name}", f_ref.package)
+        }
+    };
+    let files = workspace_files(sender, receiver);
+    import_edits(&files, &qualify(&f_ref.name), &qualify(new_name), changes)
+}
+
+/// Edits renaming the last segment of `package` in the qualified names and imports going through
+/// it.
+fn package_changes(
+    files: &HashMap<String, Arc<ParsedFile>>,
+    package: &str,
+    new_name: &str,
+    changes: &mut HashMap<Url, Vec<TextEdit>>,
+) -> Result<()> {
+    let new_package = match package.rsplit_once('.') {
+        Some((parent, _)) => format!("{parent}.{new_name}"),
+        None => new_name.to_string(),
+    };
+    for (path, file) in files {
+        for reference in &file.workspace.references {
+            let r_ref = reference.borrow();
+            if r_ref.name == package && matches!(r_ref.target, ReferenceTarget::Namespace(_)) {
+                changes.entry(file_uri(path)?).or_default().push(TextEdit {
+                    range: r_ref.loc.into(),
+                    new_text: new_name.to_string(),
+                });
+            }
+        }
+    }
+    import_edits(files, package, &new_package, changes)
+}
+
+/// Edits renaming a class in its `classdef` line, at the calls to its constructor, and in the
+/// imports naming it.
+fn class_changes(
+    files: &HashMap<String, Arc<ParsedFile>>,
+    class: &ClassDefinition,
+    new_name: &str,
+    changes: &mut HashMap<Url, Vec<TextEdit>>,
+) -> Result<()> {
+    let qualify = |name: &str| {
+        if class.package.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{name}", class.package)
+        }
+    };
+    let qualified = qualify(&class.name);
+    changes
+        .entry(file_uri(&class.path)?)
+        .or_default()
+        .push(TextEdit {
+            range: class.name_range.into(),
+            new_text: new_name.to_string(),
+        });
+    for (path, file) in files {
+        for reference in &file.workspace.references {
+            let r_ref = reference.borrow();
+            if r_ref.name == qualified
+                && matches!(
+                    r_ref.target,
+                    ReferenceTarget::Function(_) | ReferenceTarget::UnknownFunction
+                )
+            {
+                changes.entry(file_uri(path)?).or_default().push(TextEdit {
+                    range: r_ref.loc.into(),
+                    new_text: new_name.to_string(),
+                });
+            }
+        }
+    }
+    import_edits(files, &qualified, &qualify(new_name), changes)
+}
+
+/// The file `function` is the public function of, if it is one.
+fn public_function_file(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    function: &FunctionDefinition,
+) -> Option<Arc<ParsedFile>> {
+    if class_folder(&function.path).is_some() {
+        return None;
+    }
+    let file = db_get_parsed_file(
+        sender,
+        receiver,
+        function.path.clone(),
+        SenderThread::Handler,
+    )?;
+    let tree = file.syntax_tree().ok()?;
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let first = root
+        .named_children(&mut cursor)
+        .find(|n| n.kind() != "comment");
+    let is_public = first.is_some_and(|n| {
+        n.kind() == "function_definition"
+            && n.child_by_field_name("name")
+                .is_some_and(|name| Range::from(name.range()) == function.signature.name_range)
+    });
+    drop(cursor);
+    is_public.then_some(file)
+}
+
+/// The files under the workspace folders, the only ones a rename edits. Library files are left
+/// alone even when they import what is renamed.
+fn workspace_files(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+) -> HashMap<String, Arc<ParsedFile>> {
+    let workspace = db_fetch_workspace_paths(sender, receiver, SenderThread::Handler);
+    let mut files =
+        db_fetch_parsed_files(sender, receiver, SenderThread::Handler).unwrap_or_default();
+    files.retain(|path, _| workspace.iter().any(|w| Path::new(path).starts_with(w)));
+    files
+}
+
+/// Rewrites the `import` arguments naming `old`, or something in it, to `new`.
+/// `files` are the workspace files, see [`workspace_files`].
+fn import_edits(
+    files: &HashMap<String, Arc<ParsedFile>>,
+    old: &str,
+    new: &str,
+    changes: &mut HashMap<Url, Vec<TextEdit>>,
+) -> Result<()> {
+    for (path, file) in files {
+        let tree = file.syntax_tree()?;
+        let mut commands = vec![];
+        commands_of(tree.root_node(), &mut commands);
+        if commands.is_empty() {
+            continue;
+        }
+        let mut file = file.as_ref().clone();
+        file.load_contents()?;
+        let contents = file.contents.as_bytes();
+        for command in commands {
+            let mut cursor = command.walk();
+            let children: Vec<Node> = command.named_children(&mut cursor).collect();
+            if children.first().and_then(|c| c.utf8_text(contents).ok()) != Some("import") {
+                continue;
+            }
+            for argument in children.iter().filter(|c| c.kind() == "command_argument") {
+                let text = argument.utf8_text(contents)?;
+                let rest = match text.strip_prefix(old) {
+                    Some(rest) if rest.is_empty() || rest.starts_with('.') => rest,
+                    _ => continue,
+                };
+                changes.entry(file_uri(path)?).or_default().push(TextEdit {
+                    range: Range::from(argument.range()).into(),
+                    new_text: format!("{new}{rest}"),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn commands_of<'a>(node: Node<'a>, commands: &mut Vec<Node<'a>>) {
+    if node.kind() == "command" {
+        commands.push(node);
+        return;
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.named_children(&mut cursor).collect();
+    for child in children {
+        commands_of(child, commands);
+    }
+}
+
+/// The text edits followed by the rename, so that the edits still apply to the old paths.
+fn document_changes(changes: HashMap<Url, Vec<TextEdit>>, rename: RenameFile) -> WorkspaceEdit {
+    let mut operations: Vec<DocumentChangeOperation> = changes
+        .into_iter()
+        .map(|(uri, edits)| {
+            DocumentChangeOperation::Edit(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+                edits: edits.into_iter().map(OneOf::Left).collect(),
+            })
+        })
+        .collect();
+    operations.push(DocumentChangeOperation::Op(ResourceOp::Rename(rename)));
+    WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Operations(operations)),
+        ..WorkspaceEdit::default()
+    }
+}
+
+fn file_uri(path: &str) -> Result<Url> {
+    Ok(Url::parse((String::from("file://") + path).as_str())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn accepts_identifiers() {
+        assert!(validate_name("x").is_ok());
+        assert!(validate_name("new_name2").is_ok());
+        assert!(validate_name(&"a".repeat(NAME_LENGTH_MAX)).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_identifiers() {
+        assert!(validate_name("").is_err());
+        assert!(validate_name("2x").is_err());
+        assert!(validate_name("_x").is_err());
+        assert!(validate_name("x.y").is_err());
+        assert!(validate_name("new name").is_err());
+    }
+
+    #[test]
+    fn rejects_keywords() {
+        assert!(validate_name("end").is_err());
+        assert!(validate_name("function").is_err());
+        assert!(validate_name("End").is_ok());
+    }
+
+    #[test]
+    fn rejects_long_names() {
+        assert!(validate_name(&"a".repeat(NAME_LENGTH_MAX + 1)).is_err());
+    }
+
+    /// The text edits of a workspace edit by file, and its file operation.
+    fn operations(edit: WorkspaceEdit) -> (HashMap<String, Vec<TextEdit>>, Option<RenameFile>) {
+        let Some(DocumentChanges::Operations(operations)) = edit.document_changes else {
+            panic!("Expected resource operations.");
+        };
+        let mut edits = HashMap::new();
+        let mut rename = None;
+        for operation in operations {
+            match operation {
+                DocumentChangeOperation::Edit(edit) => {
+                    let texts = edit.edits.into_iter().map(|e| match e {
+                        OneOf::Left(e) => e,
+                        OneOf::Right(e) => e.text_edit,
+                    });
+                    edits
+                        .entry(edit.text_document.uri.path().to_string())
+                        .or_insert_with(Vec::new)
+                        .extend(texts);
+                }
+                DocumentChangeOperation::Op(ResourceOp::Rename(r)) => rename = Some(r),
+                DocumentChangeOperation::Op(_) => panic!("Unexpected operation."),
+            }
+        }
+        (edits, rename)
+    }
+
+    #[test]
+    fn renames_public_function_file_without_overwriting() {
+        let fixture = Fixture::new(
+            "rename-file",
+            &[
+                ("helper.m", "function helper()\nend\n"),
+                ("main.m", "helper();\n"),
+            ],
+        );
+        let edit = rename(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("helper.m"),
+            Point { row: 0, column: 10 },
+            "assist".into(),
+        )
+        .unwrap();
+        let (edits, rename) = operations(edit);
+        let rename = rename.unwrap();
+        assert_eq!(rename.old_uri.path(), fixture.path("helper.m"));
+        assert_eq!(rename.new_uri.path(), fixture.path("assist.m"));
+        assert_eq!(rename.options.unwrap().overwrite, Some(false));
+        assert_eq!(edits[&fixture.path("main.m")][0].new_text, "assist");
+    }
+
+    #[test]
+    fn leaves_library_imports_alone() {
+        let fixture = Fixture::with_library(
+            "rename-imports",
+            &[
+                ("+pkg/helper.m", "function helper()\nend\n"),
+                ("main.m", "import pkg.helper\nhelper();\n"),
+            ],
+            &[("user.m", "import pkg.helper\nhelper();\n")],
+        );
+        let edit = rename(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("+pkg/helper.m"),
+            Point { row: 0, column: 10 },
+            "assist".into(),
+        )
+        .unwrap();
+        let (edits, _) = operations(edit);
+        let imports = &edits[&fixture.path("main.m")];
+        assert!(imports.iter().any(|e| e.new_text == "pkg.assist"));
+        assert!(!edits.contains_key(&fixture.path("lib/user.m")));
+    }
+
+    #[test]
+    fn renames_package_folder_without_overwriting() {
+        let fixture = Fixture::new(
+            "rename-package",
+            &[
+                ("+pkg/helper.m", "function helper()\nend\n"),
+                ("main.m", "pkg.helper();\n"),
+            ],
+        );
+        let edit = rename(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("main.m"),
+            Point { row: 0, column: 1 },
+            "tools".into(),
+        )
+        .unwrap();
+        let (edits, rename) = operations(edit);
+        let rename = rename.unwrap();
+        assert_eq!(rename.old_uri.path(), fixture.path("+pkg"));
+        assert_eq!(rename.new_uri.path(), fixture.path("+tools"));
+        assert_eq!(rename.options.unwrap().overwrite, Some(false));
+        assert_eq!(edits[&fixture.path("main.m")][0].new_text, "tools");
+    }
+
+    #[test]
+    fn prepare_refuses_library_and_unknown_symbols() {
+        let fixture = Fixture::with_library(
+            "rename-prepare",
+            &[("main.m", "x = 1;\nhelper();\nunknown();\ndisp(x)\n")],
+            &[("helper.m", "function helper()\nend\n")],
+        );
+        let prepare = |path: String, row, column| {
+            prepare_rename(
+                fixture.sender.clone(),
+                fixture.receiver.clone(),
+                path,
+                Point { row, column },
+            )
+        };
+        let main = fixture.path("main.m");
+        assert!(prepare(main.clone(), 0, 0).is_ok());
+        assert!(prepare(main.clone(), 3, 5).is_ok());
+        let library = prepare(main.clone(), 1, 0).unwrap_err();
+        assert_eq!(
+            library.to_string(),
+            "helper is defined outside the workspace."
+        );
+        let unknown = prepare(main, 2, 0).unwrap_err();
+        assert_eq!(
+            unknown.to_string(),
+            "unknown is a builtin or unknown function."
+        );
+        assert!(prepare(fixture.path("lib/helper.m"), 0, 10).is_err());
+    }
+
+    #[test]
+    fn refuses_names_already_taken() {
+        let fixture = Fixture::new(
+            "rename-collision",
+            &[
+                ("main.m", "x = 1;\ny = 2;\nhelper();\n"),
+                ("helper.m", "function helper()\nend\n"),
+                ("other.m", "function other()\nend\n"),
+            ],
+        );
+        let rename_to = |row, new_name: &str| {
+            rename(
+                fixture.sender.clone(),
+                fixture.receiver.clone(),
+                fixture.path("main.m"),
+                Point { row, column: 0 },
+                new_name.into(),
+            )
+        };
+        let variable = rename_to(0, "y").unwrap_err();
+        assert_eq!(variable.to_string(), "A variable named y already exists.");
+        let shadowing = rename_to(0, "other").unwrap_err();
+        assert_eq!(
+            shadowing.to_string(),
+            "A function named other already exists."
+        );
+        let function = rename_to(2, "other").unwrap_err();
+        assert_eq!(
+            function.to_string(),
+            "A function named other already exists."
+        );
+    }
+
+    /// The new texts of a workspace edit by file, following renames of the given files.
+    fn file_renames(fixture: &Fixture, renames: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+        let renames = renames
+            .iter()
+            .map(|(old, new)| (fixture.path(old), fixture.path(new)))
+            .collect();
+        let edit =
+            file_rename_changes(fixture.sender.clone(), fixture.receiver.clone(), renames).unwrap();
+        edit.changes
+            .unwrap()
+            .into_iter()
+            .map(|(uri, edits)| {
+                let mut texts: Vec<String> = edits.into_iter().map(|e| e.new_text).collect();
+                texts.sort();
+                (uri.path().to_string(), texts)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn renamed_function_file_renames_its_function() {
+        let fixture = Fixture::new(
+            "rename-function-file",
+            &[
+                ("helper.m", "function helper()\nend\n"),
+                ("main.m", "helper();\n"),
+            ],
+        );
+        let changes = file_renames(&fixture, &[("helper.m", "assist.m")]);
+        assert_eq!(changes[&fixture.path("helper.m")], vec!["assist"]);
+        assert_eq!(changes[&fixture.path("main.m")], vec!["assist"]);
+    }
+
+    #[test]
+    fn renamed_package_folder_renames_its_package() {
+        let fixture = Fixture::new(
+            "rename-package-folder",
+            &[
+                ("+pkg/helper.m", "function helper()\nend\n"),
+                ("main.m", "import pkg.helper\npkg.helper();\n"),
+            ],
+        );
+        let changes = file_renames(&fixture, &[("+pkg", "+tools")]);
+        assert_eq!(
+            changes[&fixture.path("main.m")],
+            vec!["tools", "tools.helper"]
+        );
+    }
+
+    #[test]
+    fn renamed_class_folder_renames_its_class() {
+        let fixture = Fixture::new(
+            "rename-class-folder",
+            &[
+                ("@Shape/Shape.m", "classdef Shape\nend\n"),
+                ("main.m", "s = Shape();\n"),
+            ],
+        );
+        let changes = file_renames(&fixture, &[("@Shape", "@Form")]);
+        assert_eq!(changes[&fixture.path("@Shape/Shape.m")], vec!["Form"]);
+        assert_eq!(changes[&fixture.path("main.m")], vec!["Form"]);
+    }
+
+    #[test]
+    fn moved_files_are_left_alone() {
+        let fixture = Fixture::new(
+            "rename-moved-file",
+            &[
+                ("helper.m", "function helper()\nend\n"),
+                ("main.m", "helper();\n"),
+            ],
+        );
+        assert!(file_renames(&fixture, &[("helper.m", "sub/helper.m")]).is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{Receiver, Sender};
+use lsp_types::{CodeAction, CodeActionKind, TextEdit, Url, WorkspaceEdit};
+use serde_json::json;
+use tree_sitter::{Node, Point};
+
+use crate::code_loc;
+use crate::extractors::flow::body_of;
+use crate::features::extract::uses_end;
+use crate::threads::db::{db_fetch_parsed_files, db_get_parsed_file};
+use crate::types::{ParsedFile, Range, ReferenceTarget, SenderThread, ThreadMessage};
+
+/// Offers to turn a script into a function. The edit is only computed when resolving the action,
+/// as it goes through every file calling the script.
+pub fn script_to_function_actions(file: &ParsedFile) -> Vec<CodeAction> {
+    if !file.is_script || file.class.is_some() {
+        return vec![];
+    }
+    vec![CodeAction {
+        title: format!("Convert script {} to function", file.name),
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        data: Some(json!({ "action": "script_to_function", "path": file.path })),
+        ..CodeAction::default()
+    }]
+}
+
+/// Wraps the script code in a function and rewrites the calls to the script. The inputs are the
+/// variables the script reads without defining them, the outputs are the variables it leaves behind
+/// that its callers use.
+pub fn script_to_function(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+) -> Result<WorkspaceEdit> {
+    let file = db_get_parsed_file(&sender, &receiver, path.clone(), SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    let mut file = file.as_ref().clone();
+    file.load_contents()?;
+    let root = file.tree.root_node();
+    let mut inputs: Vec<String> = vec![];
+    for reference in &file.workspace.references {
+        let r_ref = reference.borrow();
+        if !matches!(r_ref.target, ReferenceTarget::UnknownVariable)
+            || r_ref.name.contains('.')
+            || inputs.contains(&r_ref.name)
+        {
+            continue;
+        }
+        // Only the script code reads its workspace, not the local functions or lambda bodies.
+        let in_script = root
+            .named_descendant_for_point_range(r_ref.loc.start, r_ref.loc.end)
+            .and_then(|n| body_of(n, root))
+            .is_some_and(|b| b.id() == root.id());
+        if in_script {
+            inputs.push(r_ref.name.clone());
+        }
+    }
+    let mut callers = vec![];
+    let mut outputs: Vec<(Range, String)> = vec![];
+    for (c_path, caller) in
+        db_fetch_parsed_files(&sender, &receiver, SenderThread::Handler).unwrap_or_default()
+    {
+        let calls: Vec<Range> = caller
+            .workspace
+            .references
+            .iter()
+            .map(|r| r.borrow())
+            .filter(|r| matches!(&r.target, ReferenceTarget::Script(p) if *p == path))
+            .map(|r| r.loc)
+            .collect();
+        if calls.is_empty() || c_path == path {
+            continue;
+        }
+        for variable in &caller.workspace.variables {
+            let v_ref = variable.borrow();
+            let Some((_, loc)) = v_ref.script.as_ref().filter(|(p, _)| *p == path) else {
+                continue;
+            };
+            let used = caller.workspace.references.iter().any(|r| {
+                let r_ref = r.borrow();
+                r_ref.loc != v_ref.loc
+                    && matches!(&r_ref.target, ReferenceTarget::Variable(v) if Arc::ptr_eq(v, variable))
+            });
+            if used && !outputs.iter().any(|(_, n)| *n == v_ref.name) {
+                outputs.push((*loc, v_ref.name.clone()));
+            }
+        }
+        callers.push((c_path, caller, calls));
+    }
+    outputs.sort_by_key(|(loc, _)| loc.start);
+    let outputs: Vec<String> = outputs.into_iter().map(|(_, n)| n).collect();
+    let call = match outputs.len() {
+        0 => String::new(),
+        1 => format!("{} = ", outputs[0]),
+        _ => format!("[{}] = ", outputs.join(", ")),
+    } + format!("{}({})", file.name, inputs.join(", ")).as_str();
+
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    let mut cursor = root.walk();
+    let end = root
+        .named_children(&mut cursor)
+        .find(|n| n.kind() == "function_definition")
+        .map(|n| n.start_position())
+        .unwrap_or(root.end_position());
+    let mut function = format!("function {call}\n");
+    for (i, line) in file.contents.lines().enumerate() {
+        if i >= end.row {
+            break;
+        }
+        if line.trim().is_empty() {
+            function += "\n";
+        } else {
+            function += format!("    {line}\n").as_str();
+        }
+    }
+    if uses_end(root) {
+        function += "end\n";
+    }
+    if end.row < root.end_position().row {
+        function += "\n";
+    }
+    let range = Range {
+        start: Point { row: 0, column: 0 },
+        end: Point {
+            row: end.row,
+            column: 0,
+        },
+    };
+    changes.insert(
+        file_uri(&path)?,
+        vec![TextEdit {
+            range: range.into(),
+            new_text: function,
+        }],
+    );
+    for (c_path, caller, calls) in callers {
+        let mut caller = caller.as_ref().clone();
+        caller.load_contents()?;
+        let root = caller.tree.root_node();
+        let mut edits = vec![];
+        for loc in calls {
+            let Some(statement) = root
+                .named_descendant_for_point_range(loc.start, loc.end)
+                .and_then(statement_of)
+            else {
+                continue;
+            };
+            let semicolon = caller.contents[statement.end_byte()..].starts_with(';');
+            edits.push(TextEdit {
+                range: Range::from(statement.range()).into(),
+                new_text: if semicolon {
+                    call.clone()
+                } else {
+                    call.clone() + ";"
+                },
+            });
+        }
+        changes.entry(file_uri(&c_path)?).or_default().extend(edits);
+    }
+    Ok(WorkspaceEdit {
+        changes: Some(changes),
+        ..WorkspaceEdit::default()
+    })
+}
+
+/// The statement the node is part of, if the node is the whole statement.
+fn statement_of(node: Node) -> Option<Node> {
+    let mut statement = node;
+    while let Some(parent) = statement.parent() {
+        if parent.kind() == "block" || parent.parent().is_none() {
+            break;
+        }
+        statement = parent;
+    }
+    (statement.kind() == "command" || statement.byte_range() == node.byte_range())
+        .then_some(statement)
+}
+
+fn file_uri(path: &str) -> Result<Url> {
+    Ok(Url::parse((String::from("file://") + path).as_str())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn reads_become_inputs_and_used_variables_outputs() {
+        let fixture = Fixture::new(
+            "script-to-function",
+            &[
+                ("compute.m", "y = x * 2;\nz = 3;\n"),
+                ("main.m", "x = 1;\ncompute\ndisp(y)\n"),
+            ],
+        );
+        let edit = script_to_function(
+            fixture.sender.clone(),
+            fixture.receiver.clone(),
+            fixture.path("compute.m"),
+        )
+        .unwrap();
+        let changes = edit.changes.unwrap();
+        let script = &changes[&file_uri(&fixture.path("compute.m")).unwrap()];
+        assert!(script[0]
+            .new_text
+            .starts_with("function y = compute(x)\n    y = x * 2;\n    z = 3;\n"));
+        let caller = &changes[&file_uri(&fixture.path("main.m")).unwrap()];
+        assert_eq!(caller.len(), 1);
+        assert_eq!(caller[0].range.start.line, 1);
+        assert_eq!(caller[0].new_text, "y = compute(x);");
+    }
+
+    #[test]
+    fn only_scripts_can_be_converted() {
+        let fixture = Fixture::new(
+            "script-to-function-actions",
+            &[
+                ("script.m", "x = 1;\n"),
+                ("helper.m", "function helper\nend\n"),
+            ],
+        );
+        assert_eq!(
+            script_to_function_actions(&fixture.file("script.m")).len(),
+            1
+        );
+        assert!(script_to_function_actions(&fixture.file("helper.m")).is_empty());
+    }
+}
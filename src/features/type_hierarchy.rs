@@ -0,0 +1,192 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{Receiver, Sender};
+use log::debug;
+use lsp_types::{SymbolKind, TypeHierarchyItem, Url};
+use serde_json::json;
+use tree_sitter::Point;
+
+use crate::code_loc;
+use crate::threads::db::{db_fetch_parsed_files, db_get_parsed_file};
+use crate::types::{ClassDefinition, SenderThread, ThreadMessage};
+
+/// Finds the class at `loc`: the class defined there, one of its superclasses, or a class named by
+/// a reference.
+pub fn prepare_type_hierarchy(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    path: String,
+    loc: Point,
+) -> Result<Vec<TypeHierarchyItem>> {
+    let file = db_get_parsed_file(&sender, &receiver, path, SenderThread::Handler)
+        .ok_or(code_loc!("No such file."))?;
+    let mut name = None;
+    if let Some(class) = &file.class {
+        if class.name_range.contains(loc) {
+            return Ok(vec![class_item(class)?]);
+        }
+        let node = file
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(loc, loc);
+        let mut node = node;
+        while let Some(n) = node {
+            if n.parent().is_some_and(|p| p.kind() == "superclasses") {
+                let text = n.utf8_text(file.contents.as_bytes())?;
+                name = Some(text.split_whitespace().collect::<String>());
+                break;
+            }
+            node = n.parent();
+        }
+    }
+    if name.is_none() {
+        name = file
+            .workspace
+            .references
+            .iter()
+            .map(|r| r.borrow())
+            .find(|r| r.loc.contains(loc))
+            .map(|r| r.name.clone());
+    }
+    drop(file);
+    if let Some(name) = name {
+        debug!("Preparing type hierarchy for {name}.");
+        let classes = fetch_classes(&sender, &receiver);
+        if let Some(class) = classes.iter().find(|c| qualified_name(c) == name) {
+            return Ok(vec![class_item(class)?]);
+        }
+    }
+    Ok(vec![])
+}
+
+/// Lists the direct superclasses of `item` found in the workspace or the library path.
+pub fn supertypes(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    item: TypeHierarchyItem,
+) -> Result<Vec<TypeHierarchyItem>> {
+    let name = item_class(&item);
+    let classes = fetch_classes(&sender, &receiver);
+    let mut items = vec![];
+    if let Some(class) = classes.iter().find(|c| qualified_name(c) == name) {
+        for superclass in &class.superclasses {
+            match classes.iter().find(|c| qualified_name(c) == *superclass) {
+                Some(superclass) => items.push(class_item(superclass)?),
+                None => debug!("Superclass {superclass} is not indexed."),
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Lists the classes directly inheriting from `item`.
+pub fn subtypes(
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    item: TypeHierarchyItem,
+) -> Result<Vec<TypeHierarchyItem>> {
+    let name = item_class(&item);
+    let mut items = vec![];
+    for class in fetch_classes(&sender, &receiver) {
+        if class.superclasses.contains(&name) {
+            items.push(class_item(&class)?);
+        }
+    }
+    Ok(items)
+}
+
+pub fn fetch_classes(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+) -> Vec<ClassDefinition> {
+    db_fetch_parsed_files(sender, receiver, SenderThread::Handler)
+        .unwrap_or_default()
+        .values()
+        .filter_map(|f| f.class.clone())
+        .collect()
+}
+
+pub fn qualified_name(class: &ClassDefinition) -> String {
+    if class.package.is_empty() {
+        class.name.clone()
+    } else {
+        format!("{}.{}", class.package, class.name)
+    }
+}
+
+fn class_item(class: &ClassDefinition) -> Result<TypeHierarchyItem> {
+    let path = String::from("file://") + class.path.as_str();
+    Ok(TypeHierarchyItem {
+        name: qualified_name(class),
+        kind: SymbolKind::CLASS,
+        tags: None,
+        detail: Some(class.path.clone()),
+        uri: Url::parse(path.as_str())?,
+        range: class.loc.into(),
+        selection_range: class.name_range.into(),
+        data: Some(json!({ "name": qualified_name(class) })),
+    })
+}
+
+fn item_class(item: &TypeHierarchyItem) -> String {
+    item.data
+        .as_ref()
+        .and_then(|d| d.get("name"))
+        .and_then(|v| v.as_str())
+        .map(String::from)
+        .unwrap_or(item.name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    fn hierarchy() -> Fixture {
+        Fixture::with_library(
+            "type-hierarchy",
+            &[
+                ("Base.m", "classdef Base < handle\nend\n"),
+                ("Child.m", "classdef Child < Base\nend\n"),
+            ],
+            &[("Other.m", "classdef Other < Base\nend\n")],
+        )
+    }
+
+    fn names(items: Vec<TypeHierarchyItem>) -> Vec<String> {
+        let mut names: Vec<String> = items.into_iter().map(|i| i.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn prepares_on_class_name_and_superclass() {
+        let fixture = hierarchy();
+        fixture.open("Child.m");
+        let prepare = |column| {
+            let path = fixture.path("Child.m");
+            let loc = Point { row: 0, column };
+            prepare_type_hierarchy(fixture.sender.clone(), fixture.receiver.clone(), path, loc)
+                .unwrap()
+        };
+        assert_eq!(names(prepare(10)), vec!["Child"]);
+        assert_eq!(names(prepare(18)), vec!["Base"]);
+    }
+
+    #[test]
+    fn lists_supertypes_and_subtypes() {
+        let fixture = hierarchy();
+        let child = class_item(fixture.file("Child.m").class.as_ref().unwrap()).unwrap();
+        let base = class_item(fixture.file("Base.m").class.as_ref().unwrap()).unwrap();
+        let supertypes =
+            supertypes(fixture.sender.clone(), fixture.receiver.clone(), child).unwrap();
+        assert_eq!(names(supertypes), vec!["Base"]);
+        let subtypes = subtypes(fixture.sender.clone(), fixture.receiver.clone(), base).unwrap();
+        assert_eq!(names(subtypes), vec!["Child", "Other"]);
+    }
+}
@@ -6,9 +6,14 @@
 
 use std::sync::Arc;
 
+use crate::extractors::matlab_path::PATH_FILES;
+use crate::extractors::matlab_project::PROJECT_METADATA;
 use crate::extractors::symbols::extract_symbols;
+use crate::features::diagnostics::{clear_diagnostics, publish_diagnostics};
+use crate::settings::PROJECT_FILES;
 use crate::threads::db::{
-    db_delete_file_function, db_delete_parsed_file, db_get_parsed_file, db_set_parsed_file,
+    db_delete_file_function, db_delete_parsed_file, db_delete_workspace_path, db_get_parsed_file,
+    db_reload_settings, db_set_parsed_file, db_set_settings, db_set_workspace_paths,
 };
 use crate::types::{MessagePayload, ParsedFile, Range, SenderThread, ThreadMessage};
 use crate::utils::{read_to_string, request_semantic_tokens_refresh};
@@ -17,11 +22,14 @@ use anyhow::{anyhow, Result};
 use crossbeam_channel::{Receiver, Sender};
 use lsp_server::{ExtractError, Message, Notification};
 use lsp_types::notification::{
-    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
+    DidChangeWorkspaceFolders, DidCloseTextDocument, DidOpenTextDocument, DidRenameFiles,
+    DidSaveTextDocument,
 };
 use lsp_types::{
-    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-    DidSaveTextDocumentParams,
+    DidChangeConfigurationParams, DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
+    DidChangeWorkspaceFoldersParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams, FileChangeType, RenameFilesParams, Url,
 };
 
 pub fn handle_notification(
@@ -36,6 +44,10 @@ pub fn handle_notification(
         .handle::<DidCloseTextDocument>(handle_text_document_did_close)
         .handle::<DidChangeTextDocument>(handle_text_document_did_change)
         .handle::<DidSaveTextDocument>(handle_text_document_did_save)
+        .handle::<DidChangeConfiguration>(handle_workspace_did_change_configuration)
+        .handle::<DidChangeWatchedFiles>(handle_workspace_did_change_watched_files)
+        .handle::<DidChangeWorkspaceFolders>(handle_workspace_did_change_workspace_folders)
+        .handle::<DidRenameFiles>(handle_workspace_did_rename_files)
         .finish()?;
     Ok(())
 }
@@ -117,13 +129,20 @@ fn handle_text_document_did_open(
         SenderThread::Handler,
         Arc::new(file),
     )?;
-    db_set_parsed_file(&sender, file, SenderThread::Handler)?;
+    db_set_parsed_file(&sender, Arc::clone(&file), SenderThread::Handler)?;
+    publish_diagnostics(
+        &lsp_sender,
+        &sender,
+        &receiver,
+        SenderThread::Handler,
+        &file,
+    )?;
     request_semantic_tokens_refresh(&lsp_sender, &sender, &receiver, SenderThread::Handler)?;
     Ok(())
 }
 
 fn handle_text_document_did_close(
-    _lsp_sender: Sender<Message>,
+    lsp_sender: Sender<Message>,
     sender: Sender<ThreadMessage>,
     receiver: Receiver<ThreadMessage>,
     params: DidCloseTextDocumentParams,
@@ -142,8 +161,9 @@ fn handle_text_document_did_close(
         db_set_parsed_file(&sender, Arc::new(file), SenderThread::Handler)?;
     } else {
         db_delete_parsed_file(&sender, path.clone(), SenderThread::Handler)?;
-        db_delete_file_function(&sender, path, SenderThread::Handler)?;
+        db_delete_file_function(&sender, path.clone(), SenderThread::Handler)?;
     }
+    clear_diagnostics(&lsp_sender, &path)?;
     sender.send(ThreadMessage {
         sender: SenderThread::Handler,
         payload: MessagePayload::ScanWorkspace(vec![]),
@@ -188,7 +208,14 @@ fn handle_text_document_did_change(
         SenderThread::Handler,
         Arc::new(file),
     )?;
-    db_set_parsed_file(&sender, file, SenderThread::Handler)?;
+    db_set_parsed_file(&sender, Arc::clone(&file), SenderThread::Handler)?;
+    publish_diagnostics(
+        &lsp_sender,
+        &sender,
+        &receiver,
+        SenderThread::Handler,
+        &file,
+    )?;
     sender.send(ThreadMessage {
         sender: SenderThread::Handler,
         payload: crate::types::MessagePayload::ScanOpen,
@@ -220,7 +247,14 @@ fn handle_text_document_did_save(
         SenderThread::Handler,
         Arc::new(file),
     )?;
-    db_set_parsed_file(&sender, file, SenderThread::Handler)?;
+    db_set_parsed_file(&sender, Arc::clone(&file), SenderThread::Handler)?;
+    publish_diagnostics(
+        &lsp_sender,
+        &sender,
+        &receiver,
+        SenderThread::Handler,
+        &file,
+    )?;
     sender.send(ThreadMessage {
         sender: SenderThread::Handler,
         payload: MessagePayload::ScanWorkspace(vec![]),
@@ -228,3 +262,101 @@ fn handle_text_document_did_save(
     request_semantic_tokens_refresh(&lsp_sender, &sender, &receiver, SenderThread::Handler)?;
     Ok(())
 }
+
+fn handle_workspace_did_change_watched_files(
+    _lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    _receiver: Receiver<ThreadMessage>,
+    params: DidChangeWatchedFilesParams,
+) -> Result<()> {
+    let mut changed = vec![];
+    let mut deleted = vec![];
+    let mut reload = false;
+    for event in params.changes {
+        let path = event.uri.path().to_string();
+        let name = path.rsplit('/').next().unwrap_or_default();
+        if PATH_FILES.contains(&name) {
+            // Still indexed as any other file, but may change the library path too.
+            reload = true;
+        }
+        if PROJECT_FILES.contains(&name) || path.contains(&format!("/{PROJECT_METADATA}/")) {
+            reload = true;
+        } else if event.typ == FileChangeType::DELETED {
+            deleted.push(path);
+        } else if event.typ == FileChangeType::CREATED || path.ends_with(".m") {
+            // A changed folder only means its entries changed, which are reported on their own.
+            changed.push(path);
+        }
+    }
+    if !changed.is_empty() || !deleted.is_empty() {
+        sender.send(ThreadMessage {
+            sender: SenderThread::Handler,
+            payload: MessagePayload::RescanFiles((changed, deleted)),
+        })?;
+    }
+    if reload {
+        db_reload_settings(&sender, SenderThread::Handler)?;
+    }
+    Ok(())
+}
+
+fn handle_workspace_did_change_configuration(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    params: DidChangeConfigurationParams,
+) -> Result<()> {
+    // Clients that only support pulling the configuration send no settings.
+    if params.settings.is_null() {
+        return Ok(());
+    }
+    db_set_settings(&sender, params.settings, SenderThread::Handler)?;
+    request_semantic_tokens_refresh(&lsp_sender, &sender, &receiver, SenderThread::Handler)?;
+    Ok(())
+}
+
+fn handle_workspace_did_change_workspace_folders(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    params: DidChangeWorkspaceFoldersParams,
+) -> Result<()> {
+    for folder in params.event.removed {
+        let path = folder.uri.path().to_string();
+        db_delete_workspace_path(&sender, path, SenderThread::Handler)?;
+    }
+    let added: Vec<String> = params
+        .event
+        .added
+        .iter()
+        .map(|f| f.uri.path().to_string())
+        .collect();
+    if !added.is_empty() {
+        db_set_workspace_paths(&sender, added.clone(), SenderThread::Handler)?;
+        sender.send(ThreadMessage {
+            sender: SenderThread::Handler,
+            payload: MessagePayload::ScanWorkspace(added),
+        })?;
+    }
+    request_semantic_tokens_refresh(&lsp_sender, &sender, &receiver, SenderThread::Handler)?;
+    Ok(())
+}
+
+fn handle_workspace_did_rename_files(
+    _lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    _receiver: Receiver<ThreadMessage>,
+    params: RenameFilesParams,
+) -> Result<()> {
+    let mut changed = vec![];
+    let mut deleted = vec![];
+    for file in params.files {
+        deleted.push(Url::parse(&file.old_uri)?.path().to_string());
+        changed.push(Url::parse(&file.new_uri)?.path().to_string());
+    }
+    sender.send(ThreadMessage {
+        sender: SenderThread::Handler,
+        payload: MessagePayload::RescanFiles((changed, deleted)),
+    })?;
+    Ok(())
+}
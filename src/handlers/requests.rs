@@ -4,31 +4,44 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::HashMap;
-
+use crate::features::call_hierarchy::{incoming_calls, outgoing_calls, prepare_call_hierarchy};
+use crate::features::code_actions::{code_actions, resolve_code_action};
+use crate::features::code_lens::{code_lenses, resolve_code_lens};
 use crate::features::completion::complete;
+use crate::features::definition::definitions_for_symbol;
 use crate::features::hover::hover_for_symbol;
+use crate::features::implementation::{find_implementations, find_super_methods};
 use crate::features::references::find_references_to_symbol;
+use crate::features::rename::{file_rename_changes, prepare_rename, rename};
 use crate::features::semantic::semantic_tokens;
+use crate::features::type_hierarchy::{prepare_type_hierarchy, subtypes, supertypes};
 use crate::impls::range::{PointToPos, PosToPoint};
-use crate::threads::db::db_get_parsed_file;
-use crate::types::{Range, SenderThread, ThreadMessage};
+use crate::threads::db::{db_get_link_support, db_get_parsed_file, db_get_settings};
+use crate::types::{SenderThread, ThreadMessage};
 
 use anyhow::{anyhow, Result};
 use crossbeam_channel::{Receiver, Sender};
-use log::{debug, info};
+use log::info;
 use lsp_server::{ExtractError, Message, Request, RequestId, Response};
 use lsp_types::request::{
-    Completion, DocumentHighlightRequest, FoldingRangeRequest, Formatting, GotoDefinition,
-    HoverRequest, References, Rename, SemanticTokensFullRequest,
+    CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare,
+    CodeActionRequest, CodeActionResolveRequest, CodeLensRequest, CodeLensResolve, Completion,
+    DocumentHighlightRequest, FoldingRangeRequest, Formatting, GotoDeclaration,
+    GotoDeclarationParams, GotoDefinition, GotoImplementation, GotoImplementationParams,
+    HoverRequest, PrepareRenameRequest, References, Rename, SemanticTokensFullRequest,
+    TypeHierarchyPrepare, TypeHierarchySubtypes, TypeHierarchySupertypes, WillRenameFiles,
 };
 use lsp_types::{
-    CompletionParams, DocumentFormattingParams, DocumentHighlight, DocumentHighlightParams,
-    FoldingRange, FoldingRangeKind, FoldingRangeParams, GotoDefinitionParams,
-    GotoDefinitionResponse, Hover, HoverContents, HoverParams, Location, Position, ReferenceParams,
-    RenameParams, SemanticTokens, SemanticTokensParams, TextEdit, Url, WorkspaceEdit,
+    CallHierarchyIncomingCallsParams, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+    CodeAction, CodeActionParams, CodeLens, CodeLensParams, CompletionParams,
+    DocumentFormattingParams, DocumentHighlight, DocumentHighlightParams, FoldingRange,
+    FoldingRangeKind, FoldingRangeParams, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, Location, LocationLink, Position, PrepareRenameResponse,
+    ReferenceParams, RenameFilesParams, RenameParams, SemanticTokens, SemanticTokensParams,
+    TextDocumentPositionParams, TextEdit, TypeHierarchyPrepareParams, TypeHierarchySubtypesParams,
+    TypeHierarchySupertypesParams, Url,
 };
-use regex::Regex;
+use serde_json::Value;
 use tree_sitter::{Node, Point, Query, QueryCursor, StreamingIterator};
 
 pub fn handle_request(
@@ -41,13 +54,27 @@ pub fn handle_request(
     dispatcher
         .handle::<Formatting>(handle_formatting)
         .handle::<GotoDefinition>(handle_goto_definition)
+        .handle::<GotoImplementation>(handle_goto_implementation)
+        .handle::<GotoDeclaration>(handle_goto_declaration)
         .handle::<References>(handle_references)
         .handle::<Rename>(handle_rename)
+        .handle::<PrepareRenameRequest>(handle_prepare_rename)
+        .handle::<WillRenameFiles>(handle_will_rename_files)
         .handle::<HoverRequest>(handle_hover)
         .handle::<DocumentHighlightRequest>(handle_highlight)
         .handle::<FoldingRangeRequest>(handle_folding)
         .handle::<SemanticTokensFullRequest>(handle_semantic)
         .handle::<Completion>(handle_completion)
+        .handle::<CodeActionRequest>(handle_code_action)
+        .handle::<CodeActionResolveRequest>(handle_code_action_resolve)
+        .handle::<CodeLensRequest>(handle_code_lens)
+        .handle::<CodeLensResolve>(handle_code_lens_resolve)
+        .handle::<CallHierarchyPrepare>(handle_prepare_call_hierarchy)
+        .handle::<CallHierarchyIncomingCalls>(handle_incoming_calls)
+        .handle::<CallHierarchyOutgoingCalls>(handle_outgoing_calls)
+        .handle::<TypeHierarchyPrepare>(handle_prepare_type_hierarchy)
+        .handle::<TypeHierarchySupertypes>(handle_supertypes)
+        .handle::<TypeHierarchySubtypes>(handle_subtypes)
         .finish()
 }
 
@@ -129,8 +156,13 @@ fn handle_formatting(
     } else {
         return Ok(());
     };
+    let settings = db_get_settings(&sender, &receiver, SenderThread::Handler);
+    if !settings.features.formatting {
+        lsp_sender.send(Message::Response(Response::new_ok(id, Value::Null)))?;
+        return Ok(());
+    }
     let pos = file.tree.root_node().end_position();
-    if let Some(code) = file.format() {
+    if let Some(code) = file.format(&settings.formatter) {
         let result = vec![TextEdit {
             range: lsp_types::Range {
                 start: Position::new(0, 0),
@@ -165,65 +197,273 @@ fn handle_goto_definition(
         row: loc.line.try_into()?,
         column: loc.character.try_into()?,
     };
-    if let Some(file) = db_get_parsed_file(&sender, &receiver, path, SenderThread::Handler) {
-        debug!("Goto Definition for file {}", file.path);
-        debug!(
-            "File contains {} references",
-            file.workspace.references.len()
-        );
-        let refs = file.workspace.references.clone();
-        drop(file);
-        for refs in &refs {
-            let r = refs.borrow();
-            if r.loc.contains(loc) {
-                debug!("Point in range, matching.");
-                let resp = match &r.target {
-                    crate::types::ReferenceTarget::Function(fun) => {
-                        let path = fun.borrow().path.clone();
-                        let path = String::from("file://") + path.as_str();
-                        Some(GotoDefinitionResponse::from(Location::new(
-                            Url::parse(path.as_str())?,
-                            fun.borrow_mut().loc.into(),
-                        )))
-                    }
-                    crate::types::ReferenceTarget::Variable(var) => {
-                        Some(GotoDefinitionResponse::from(Location::new(
-                            uri,
-                            var.borrow_mut().loc.into(),
-                        )))
-                    }
-                    crate::types::ReferenceTarget::Script(path) => {
-                        let path = String::from("file://") + path.as_str();
-                        Some(GotoDefinitionResponse::from(Location::new(
-                            Url::parse(path.as_str())?,
-                            Range::default().into(),
-                        )))
-                    }
-                    crate::types::ReferenceTarget::Namespace(_) => None,
-                    crate::types::ReferenceTarget::UnknownVariable => None,
-                    crate::types::ReferenceTarget::UnknownFunction => None,
-                };
-                if let Some(resp) = resp {
-                    let resp = Response::new_ok(id, resp);
-                    lsp_sender.send(Message::Response(resp))?;
-                } else {
-                    debug!("Matched, got None");
-                    let resp = Response::new_ok(id, ());
-                    let _ = lsp_sender.send(resp.into());
+    match definitions_for_symbol(sender.clone(), receiver.clone(), path, loc) {
+        Ok(definitions) => {
+            // Sorted by precedence, the first one is the one MATLAB calls. The labels saying why
+            // are listed in the hover.
+            let link_support = db_get_link_support(&sender, &receiver, SenderThread::Handler);
+            let mut locations: Vec<Location> = definitions.into_iter().map(|(l, _)| l).collect();
+            let resp = match locations.len() {
+                0 => Response::new_ok(id, ()),
+                _ if link_support => {
+                    let links: Vec<LocationLink> = locations
+                        .into_iter()
+                        .map(|l| LocationLink {
+                            origin_selection_range: None,
+                            target_uri: l.uri,
+                            target_range: l.range,
+                            target_selection_range: l.range,
+                        })
+                        .collect();
+                    Response::new_ok(id, GotoDefinitionResponse::Link(links))
                 }
-                return Ok(());
-            }
+                1 => Response::new_ok(id, GotoDefinitionResponse::from(locations.remove(0))),
+                _ => Response::new_ok(id, GotoDefinitionResponse::Array(locations)),
+            };
+            let _ = lsp_sender.send(resp.into());
+        }
+        Err(_) => {
+            let resp = Response::new_err(id, 0, "Could not find file.".into());
+            let _ = lsp_sender.send(resp.into());
         }
-        debug!("Point not in range.");
-        let resp = Response::new_ok(id, ());
-        let _ = lsp_sender.send(resp.into());
-    } else {
-        let resp = Response::new_err(id, 0, "Could not find file.".into());
-        let _ = lsp_sender.send(resp.into());
     }
     Ok(())
 }
 
+fn handle_goto_implementation(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: GotoImplementationParams,
+) -> Result<()> {
+    info!("Received textDocument/implementation.");
+    let path = params
+        .text_document_position_params
+        .text_document
+        .uri
+        .path()
+        .to_string();
+    let loc = params.text_document_position_params.position.to_point();
+    let resp = match find_implementations(sender, receiver, path, loc) {
+        Ok(locations) => Response::new_ok(id, GotoDefinitionResponse::Array(locations)),
+        Err(_) => Response::new_ok(id, ()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+/// The declaration of a method is the method it overrides, so this is "goto super method".
+fn handle_goto_declaration(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: GotoDeclarationParams,
+) -> Result<()> {
+    info!("Received textDocument/declaration.");
+    let path = params
+        .text_document_position_params
+        .text_document
+        .uri
+        .path()
+        .to_string();
+    let loc = params.text_document_position_params.position.to_point();
+    let resp = match find_super_methods(sender, receiver, path, loc) {
+        Ok(locations) => Response::new_ok(id, GotoDefinitionResponse::Array(locations)),
+        Err(_) => Response::new_ok(id, ()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_code_action(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: CodeActionParams,
+) -> Result<()> {
+    info!("Received textDocument/codeAction.");
+    let settings = db_get_settings(&sender, &receiver, SenderThread::Handler);
+    let resp = if !settings.features.code_actions {
+        Response::new_ok(id, Value::Null)
+    } else {
+        match code_actions(sender, receiver, params) {
+            Ok(actions) => Response::new_ok(id, actions),
+            Err(_) => Response::new_err(id, 0, "Could not find file.".into()),
+        }
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_code_action_resolve(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: CodeAction,
+) -> Result<()> {
+    info!("Received codeAction/resolve.");
+    let resp = match resolve_code_action(sender, receiver, params) {
+        Ok(action) => Response::new_ok(id, action),
+        Err(err) => Response::new_err(id, 0, err.to_string()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_code_lens(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: CodeLensParams,
+) -> Result<()> {
+    info!("Received textDocument/codeLens.");
+    let path = params.text_document.uri.path().to_string();
+    let settings = db_get_settings(&sender, &receiver, SenderThread::Handler);
+    let resp = if !settings.features.code_lens {
+        Response::new_ok(id, Value::Null)
+    } else {
+        match code_lenses(sender, receiver, path) {
+            Ok(lenses) => Response::new_ok(id, lenses),
+            Err(_) => Response::new_err(id, 0, "Could not find file.".into()),
+        }
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_code_lens_resolve(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: CodeLens,
+) -> Result<()> {
+    info!("Received codeLens/resolve.");
+    let resp = match resolve_code_lens(sender, receiver, params.clone()) {
+        Ok(lens) => Response::new_ok(id, lens),
+        Err(_) => Response::new_ok(id, params),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_prepare_call_hierarchy(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: CallHierarchyPrepareParams,
+) -> Result<()> {
+    info!("Received textDocument/prepareCallHierarchy.");
+    let path = params
+        .text_document_position_params
+        .text_document
+        .uri
+        .path()
+        .to_string();
+    let loc = params.text_document_position_params.position.to_point();
+    let resp = match prepare_call_hierarchy(sender, receiver, path, loc) {
+        Ok(items) if items.is_empty() => Response::new_ok(id, ()),
+        Ok(items) => Response::new_ok(id, items),
+        Err(err) => Response::new_err(id, 0, err.to_string()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_incoming_calls(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: CallHierarchyIncomingCallsParams,
+) -> Result<()> {
+    info!("Received callHierarchy/incomingCalls.");
+    let resp = match incoming_calls(sender, receiver, params.item) {
+        Ok(calls) => Response::new_ok(id, calls),
+        Err(err) => Response::new_err(id, 0, err.to_string()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_outgoing_calls(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: CallHierarchyOutgoingCallsParams,
+) -> Result<()> {
+    info!("Received callHierarchy/outgoingCalls.");
+    let resp = match outgoing_calls(sender, receiver, params.item) {
+        Ok(calls) => Response::new_ok(id, calls),
+        Err(err) => Response::new_err(id, 0, err.to_string()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_prepare_type_hierarchy(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: TypeHierarchyPrepareParams,
+) -> Result<()> {
+    info!("Received textDocument/prepareTypeHierarchy.");
+    let path = params
+        .text_document_position_params
+        .text_document
+        .uri
+        .path()
+        .to_string();
+    let loc = params.text_document_position_params.position.to_point();
+    let resp = match prepare_type_hierarchy(sender, receiver, path, loc) {
+        Ok(items) if items.is_empty() => Response::new_ok(id, ()),
+        Ok(items) => Response::new_ok(id, items),
+        Err(_) => Response::new_err(id, 0, "Could not find file.".into()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_supertypes(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: TypeHierarchySupertypesParams,
+) -> Result<()> {
+    info!("Received typeHierarchy/supertypes.");
+    let resp = match supertypes(sender, receiver, params.item) {
+        Ok(items) => Response::new_ok(id, items),
+        Err(err) => Response::new_err(id, 0, err.to_string()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_subtypes(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: TypeHierarchySubtypesParams,
+) -> Result<()> {
+    info!("Received typeHierarchy/subtypes.");
+    let resp = match subtypes(sender, receiver, params.item) {
+        Ok(items) => Response::new_ok(id, items),
+        Err(err) => Response::new_err(id, 0, err.to_string()),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
 fn handle_references(
     lsp_sender: Sender<Message>,
     sender: Sender<ThreadMessage>,
@@ -265,7 +505,7 @@ fn handle_rename(
     id: RequestId,
     params: RenameParams,
 ) -> Result<()> {
-    info!("Received textDocument/references.");
+    info!("Received textDocument/rename.");
     let path = params
         .text_document_position
         .text_document
@@ -274,31 +514,58 @@ fn handle_rename(
         .to_string();
     let loc = params.text_document_position.position.to_point();
     let new_name = params.new_name;
-    let regex = Regex::new(r"^[a-zA-Z_][a-zA-Z_0-9]*$")?;
-    if !regex.is_match(&new_name) {
-        let resp = Response::new_err(
+    let resp = match rename(sender, receiver, path, loc, new_name) {
+        Ok(ws_edit) => Response::new_ok(id, ws_edit),
+        Err(err) => Response::new_err(
             id,
             lsp_server::ErrorCode::InvalidParams as i32,
-            "The name is not a valid identifier.".to_owned(),
-        );
-        lsp_sender.send(Message::Response(resp))?;
-        return Ok(());
-    }
-    let references = find_references_to_symbol(sender.clone(), receiver.clone(), path, loc, true)?;
-    let mut ws_edit: HashMap<Url, Vec<TextEdit>> = HashMap::new();
-    for (reference, _) in references {
-        let uri = reference.uri;
-        let text_edit = TextEdit {
-            range: reference.range,
-            new_text: new_name.clone(),
-        };
-        ws_edit
-            .entry(uri)
-            .and_modify(|v| v.push(text_edit.clone()))
-            .or_insert(vec![text_edit]);
+            err.to_string(),
+        ),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_prepare_rename(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: TextDocumentPositionParams,
+) -> Result<()> {
+    info!("Received textDocument/prepareRename.");
+    let path = params.text_document.uri.path().to_string();
+    let loc = params.position.to_point();
+    let resp = match prepare_rename(sender, receiver, path, loc) {
+        Ok(range) => Response::new_ok(id, PrepareRenameResponse::Range(range.into())),
+        Err(err) => Response::new_err(
+            id,
+            lsp_server::ErrorCode::InvalidRequest as i32,
+            err.to_string(),
+        ),
+    };
+    lsp_sender.send(Message::Response(resp))?;
+    Ok(())
+}
+
+fn handle_will_rename_files(
+    lsp_sender: Sender<Message>,
+    sender: Sender<ThreadMessage>,
+    receiver: Receiver<ThreadMessage>,
+    id: RequestId,
+    params: RenameFilesParams,
+) -> Result<()> {
+    info!("Received workspace/willRenameFiles.");
+    let mut renames = vec![];
+    for file in params.files {
+        let old = Url::parse(&file.old_uri)?.path().to_string();
+        let new = Url::parse(&file.new_uri)?.path().to_string();
+        renames.push((old, new));
     }
-    let ws_edit = WorkspaceEdit::new(ws_edit);
-    let resp = Response::new_ok(id, ws_edit);
+    let resp = match file_rename_changes(sender, receiver, renames) {
+        Ok(ws_edit) => Response::new_ok(id, ws_edit),
+        Err(err) => Response::new_err(id, 0, err.to_string()),
+    };
     lsp_sender.send(Message::Response(resp))?;
     Ok(())
 }
@@ -421,6 +688,13 @@ fn handle_semantic(
 ) -> Result<()> {
     info!("Received textDocument/semanticTokens/full.");
     let path = params.text_document.uri.path().to_string();
+    if !db_get_settings(&sender, &receiver, SenderThread::Handler)
+        .features
+        .semantic_tokens
+    {
+        lsp_sender.send(Message::Response(Response::new_ok(id, Value::Null)))?;
+        return Ok(());
+    }
     if let Some(file) = db_get_parsed_file(&sender, &receiver, path, SenderThread::Handler) {
         let response = semantic_tokens(&file)?;
         let sts = SemanticTokens {
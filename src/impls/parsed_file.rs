@@ -7,6 +7,7 @@
 use std::time::Instant;
 
 use crate::features::formatter::format;
+use crate::settings::FormatterSettings;
 use crate::types::{ParsedFile, Workspace};
 use crate::utils::read_to_string;
 
@@ -27,7 +28,7 @@ impl ParsedFile {
             contents,
             name: path
                 .split('/')
-                .last()
+                .next_back()
                 .unwrap_or("")
                 .strip_suffix(".m")
                 .unwrap_or("")
@@ -37,6 +38,8 @@ impl ParsedFile {
             timestamp: Instant::now(),
             package: String::new(),
             is_script: true,
+            class: None,
+            method_range: None,
             workspace: Workspace::default(),
         })
     }
@@ -55,17 +58,31 @@ impl ParsedFile {
         if !self.open {
             let mut file = std::fs::File::open(self.path.clone())?;
             self.contents = read_to_string(&mut file, None)?.0;
+            // Files restored from the index cache are only parsed when needed.
+            if self.tree.root_node().end_byte() == 0 && !self.contents.is_empty() {
+                self.tree = ParsedFile::ts_parse(&self.contents)?;
+            }
         }
         Ok(())
     }
 
+    /// The syntax tree, parsed from disk for files restored from the index cache.
+    pub fn syntax_tree(&self) -> Result<Tree> {
+        if self.open || self.tree.root_node().end_byte() > 0 {
+            return Ok(self.tree.clone());
+        }
+        let mut file = std::fs::File::open(self.path.clone())?;
+        let contents = read_to_string(&mut file, None)?.0;
+        ParsedFile::ts_parse(&contents)
+    }
+
     pub fn dump_contents(&mut self) {
         if !self.open {
             self.contents = "".into();
         }
     }
 
-    pub fn format(&mut self) -> Option<String> {
+    pub fn format(&mut self, settings: &FormatterSettings) -> Option<String> {
         let tree = self.tree.clone();
         if tree.root_node().has_error() {
             error!("Cannot format, has errors.");
@@ -75,7 +92,7 @@ impl ParsedFile {
             error!("Error loading contents: {err}");
             return None;
         }
-        let result = format((self.contents.clone() + "\n").as_str()).ok();
+        let result = format((self.contents.clone() + "\n").as_str(), settings).ok();
         self.dump_contents();
         result
     }
@@ -10,6 +10,7 @@ use crate::code_loc;
 use crate::types::{ParsedFile, Range};
 use anyhow::{anyhow, Context};
 use lsp_types::Position;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tree_sitter::Point;
 
 impl From<tree_sitter::Range> for Range {
@@ -50,6 +51,30 @@ impl From<Range> for lsp_types::Range {
     }
 }
 
+/// Ranges are stored as `[start row, start column, end row, end column]`.
+impl Serialize for Range {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        [
+            self.start.row,
+            self.start.column,
+            self.end.row,
+            self.end.column,
+        ]
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Range {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let [start_row, start_column, end_row, end_column] =
+            <[usize; 4]>::deserialize(deserializer)?;
+        Ok(Range {
+            start: Point::new(start_row, start_column),
+            end: Point::new(end_row, end_column),
+        })
+    }
+}
+
 impl Display for Range {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
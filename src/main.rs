@@ -9,6 +9,9 @@ mod extractors;
 mod features;
 mod handlers;
 mod impls;
+mod settings;
+#[cfg(test)]
+mod testing;
 mod threads;
 mod types;
 mod utils;
@@ -20,6 +23,7 @@ use std::thread::{spawn, JoinHandle};
 use args::{Arguments, Parser};
 use threads::{background_worker, dispatcher, handler};
 use types::{MessagePayload, SenderThread, ThreadMessage};
+use utils::register_file_watchers;
 
 use anyhow::Result;
 use crossbeam_channel::{Receiver, Sender};
@@ -27,11 +31,16 @@ use log::{debug, error, info};
 use lsp_server::{Connection, Message};
 use lsp_types::notification::{Exit, Notification};
 use lsp_types::{
-    CompletionOptions, FoldingRangeProviderCapability, HoverProviderCapability, InitializeParams,
-    OneOf, PositionEncodingKind, SaveOptions, SemanticTokenType, SemanticTokensFullOptions,
-    SemanticTokensLegend, SemanticTokensOptions, SemanticTokensServerCapabilities,
-    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
-    WorkDoneProgressOptions,
+    CallHierarchyServerCapability, CodeActionKind, CodeActionOptions, CodeActionProviderCapability,
+    CodeLensOptions, CompletionOptions, DeclarationCapability, FileOperationFilter,
+    FileOperationPattern, FileOperationPatternKind, FileOperationRegistrationOptions,
+    FoldingRangeProviderCapability, HoverProviderCapability, ImplementationProviderCapability,
+    InitializeParams, OneOf, PositionEncodingKind, RenameOptions, SaveOptions, SemanticTokenType,
+    SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions,
+    SemanticTokensServerCapabilities, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, WorkDoneProgressOptions,
+    WorkspaceFileOperationsServerCapabilities, WorkspaceFoldersServerCapabilities,
+    WorkspaceServerCapabilities,
 };
 use process_alive::Pid;
 use simplelog::{CombinedLogger, Config, WriteLogger};
@@ -79,10 +88,22 @@ fn configure_logger() -> Result<()> {
 
 fn start_server(arguments: Arguments) -> Result<ExitCode> {
     let (connection, _io_threads) = Connection::stdio();
-    let server_capabilities = serde_json::to_value(server_capabilities())?;
+    let mut server_capabilities = serde_json::to_value(server_capabilities())?;
+    // lsp-types does not have a field for this capability yet.
+    server_capabilities["typeHierarchyProvider"] = true.into();
     let initialization_params = connection.initialize(server_capabilities)?;
     let initialization_params: InitializeParams = serde_json::from_value(initialization_params)?;
     let pid = initialization_params.process_id;
+    let watch_files = initialization_params
+        .capabilities
+        .workspace
+        .as_ref()
+        .and_then(|w| w.did_change_watched_files)
+        .and_then(|w| w.dynamic_registration)
+        .unwrap_or(false);
+    if watch_files {
+        register_file_watchers(&connection.sender)?;
+    }
     let (threads, sender) =
         start_threads(arguments, initialization_params, connection.sender.clone());
     let result = main_loop(sender, connection.receiver.clone(), pid);
@@ -153,10 +174,31 @@ fn server_capabilities() -> ServerCapabilities {
             completion_item: None,
         }),
         definition_provider: Some(OneOf::Left(true)),
+        declaration_provider: Some(DeclarationCapability::Simple(true)),
+        implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
         references_provider: Some(OneOf::Left(true)),
         document_highlight_provider: Some(OneOf::Left(true)),
         document_formatting_provider: Some(lsp_types::OneOf::Left(true)),
-        rename_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        })),
+        code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+            code_action_kinds: Some(vec![
+                CodeActionKind::QUICKFIX,
+                CodeActionKind::REFACTOR_EXTRACT,
+                CodeActionKind::REFACTOR_INLINE,
+                CodeActionKind::REFACTOR_REWRITE,
+            ]),
+            work_done_progress_options: WorkDoneProgressOptions {
+                work_done_progress: Some(false),
+            },
+            resolve_provider: Some(true),
+        })),
+        code_lens_provider: Some(CodeLensOptions {
+            resolve_provider: Some(true),
+        }),
+        call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
         folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
         semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
             SemanticTokensOptions {
@@ -171,10 +213,40 @@ fn server_capabilities() -> ServerCapabilities {
                 full: Some(SemanticTokensFullOptions::Bool(true)),
             },
         )),
+        workspace: Some(WorkspaceServerCapabilities {
+            workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                supported: Some(true),
+                change_notifications: Some(OneOf::Left(true)),
+            }),
+            file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                will_rename: Some(file_operation_filters()),
+                did_rename: Some(file_operation_filters()),
+                ..Default::default()
+            }),
+        }),
         ..Default::default()
     }
 }
 
+/// Files and the folders whose name matters to MATLAB: packages and class folders.
+fn file_operation_filters() -> FileOperationRegistrationOptions {
+    let filter = |glob: &str, matches| FileOperationFilter {
+        scheme: Some("file".into()),
+        pattern: FileOperationPattern {
+            glob: glob.into(),
+            matches: Some(matches),
+            options: None,
+        },
+    };
+    FileOperationRegistrationOptions {
+        filters: vec![
+            filter("**/*.m", FileOperationPatternKind::File),
+            filter("**/+*", FileOperationPatternKind::Folder),
+            filter("**/@*", FileOperationPatternKind::Folder),
+        ],
+    }
+}
+
 fn start_threads(
     arguments: Arguments,
     init: InitializeParams,
@@ -0,0 +1,430 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::path::Path;
+
+use log::error;
+use lsp_types::DiagnosticSeverity;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Names of the project configuration files looked for in each workspace root.
+pub const PROJECT_FILES: [&str; 2] = [".matlab-lsp.toml", ".matlab-lsp.json"];
+
+/// Settings of the server. They come from three places, merged in this order:
+///
+/// 1. The `--path` argument (or `MLSP_PATH`), which only gives library folders.
+/// 2. The client, in `initializationOptions` and `workspace/didChangeConfiguration`.
+/// 3. A `.matlab-lsp.toml` or `.matlab-lsp.json` file in each workspace root, in the order of the
+///    roots. Only the first of the two files found in a root is read.
+///
+/// Lists, like `libPath` and `exclude`, are concatenated. Any other value set in a project file
+/// overrides the client's, since it describes the code rather than the editor, and a later root
+/// overrides an earlier one. Every field is optional, missing ones take their default value.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    /// Library folders, added to the ones given in the command line. In project files, relative
+    /// paths are relative to the workspace root.
+    pub lib_path: Vec<String>,
+    /// Glob patterns of files and folders left out of the scans.
+    pub exclude: Vec<String>,
+    /// MATLAB release the code targets. Syntax added in a later release is reported.
+    pub release: Option<Release>,
+    /// How the workspace folders are scanned.
+    pub workspace_scan: ScanMode,
+    pub formatter: FormatterSettings,
+    pub diagnostics: DiagnosticSettings,
+    pub features: FeatureSettings,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScanMode {
+    /// Only the root of each workspace folder and the packages and classes in it, like a folder
+    /// added with `addpath`.
+    #[default]
+    Packages,
+    /// Every subfolder, like a folder added with `addpath(genpath(...))`. Dot folders and the ones
+    /// ignored by `.gitignore` files are skipped as well.
+    Genpath,
+}
+
+/// A MATLAB release, like `R2023b`. Releases compare in the order they came out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Release {
+    pub year: u16,
+    /// `'a'` for the spring release, `'b'` for the fall one.
+    pub half: char,
+}
+
+impl Release {
+    pub const fn new(year: u16, half: char) -> Release {
+        Release { year, half }
+    }
+}
+
+impl TryFrom<String> for Release {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Release, String> {
+        let error = || format!("{name} is not a release name like R2023b");
+        let rest = name.strip_prefix('R').ok_or_else(error)?;
+        let (year, half) = rest.split_at_checked(4).ok_or_else(error)?;
+        let year: u16 = year.parse().map_err(|_| error())?;
+        // The first release named after its year is R2006a.
+        match half {
+            "a" if year >= 2006 => Ok(Release::new(year, 'a')),
+            "b" if year >= 2006 => Ok(Release::new(year, 'b')),
+            _ => Err(error()),
+        }
+    }
+}
+
+impl From<Release> for String {
+    fn from(release: Release) -> String {
+        release.to_string()
+    }
+}
+
+impl std::fmt::Display for Release {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "R{}{}", self.year, self.half)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FormatterSettings {
+    /// Whether to put spaces around `*`, `/` and `^`.
+    pub sparse_math: bool,
+    /// Whether to put spaces around `+` and `-`.
+    pub sparse_add: bool,
+}
+
+impl Default for FormatterSettings {
+    fn default() -> Self {
+        FormatterSettings {
+            sparse_math: false,
+            sparse_add: true,
+        }
+    }
+}
+
+/// Severity of each kind of diagnostic.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DiagnosticSettings {
+    /// Globals declared in a single file.
+    pub unshared_global: Severity,
+    /// Variables only assigned on some of the paths reaching a use.
+    pub maybe_undefined: Severity,
+}
+
+impl Default for DiagnosticSettings {
+    fn default() -> Self {
+        DiagnosticSettings {
+            unshared_global: Severity::Hint,
+            maybe_undefined: Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    Off,
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    /// The LSP severity, or `None` if the diagnostic is turned off.
+    pub fn to_lsp(self) -> Option<DiagnosticSeverity> {
+        match self {
+            Severity::Off => None,
+            Severity::Error => Some(DiagnosticSeverity::ERROR),
+            Severity::Warning => Some(DiagnosticSeverity::WARNING),
+            Severity::Information => Some(DiagnosticSeverity::INFORMATION),
+            Severity::Hint => Some(DiagnosticSeverity::HINT),
+        }
+    }
+}
+
+/// Features that can be turned off. They are all on by default.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FeatureSettings {
+    pub diagnostics: bool,
+    pub formatting: bool,
+    pub code_actions: bool,
+    pub code_lens: bool,
+    pub semantic_tokens: bool,
+}
+
+impl Default for FeatureSettings {
+    fn default() -> Self {
+        FeatureSettings {
+            diagnostics: true,
+            formatting: true,
+            code_actions: true,
+            code_lens: true,
+            semantic_tokens: true,
+        }
+    }
+}
+
+impl Settings {
+    /// Merges the settings sent by the client with the project files of the workspace `roots`.
+    /// Clients usually send every setting they know of, so the server's are looked for under a
+    /// `matlab` section first. Invalid values are left at their default and reported in the
+    /// returned messages, the valid ones are still used.
+    pub fn resolve(client: &Value, roots: &[String]) -> (Settings, Vec<String>) {
+        let mut errors = vec![];
+        let mut value = match client.get("matlab").unwrap_or(client) {
+            Value::Object(object) => Value::Object(object.clone()),
+            _ => Value::Object(Map::new()),
+        };
+        for root in roots {
+            if let Some(project) = project_settings(root, &mut errors) {
+                merge(&mut value, project);
+            }
+        }
+        let mut valid = serde_json::to_value(Settings::default()).unwrap_or_default();
+        keep_valid(&mut valid, &mut vec![], value, &mut errors);
+        let settings = serde_json::from_value(valid).unwrap_or_default();
+        for err in &errors {
+            error!("{err}");
+        }
+        (settings, errors)
+    }
+
+    /// Whether the file or folder at `path` matches one of the exclude patterns.
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.exclude.iter().any(|pattern| glob_match(pattern, path))
+    }
+}
+
+/// Reads the project configuration file of a workspace root, if there is one.
+fn project_settings(root: &str, errors: &mut Vec<String>) -> Option<Value> {
+    for name in PROJECT_FILES {
+        let path = Path::new(root).join(name);
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let parsed = if name.ends_with(".toml") {
+            toml::from_str::<Value>(&contents).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str::<Value>(&contents).map_err(|e| e.to_string())
+        };
+        let mut value = match parsed {
+            Ok(value @ Value::Object(_)) => value,
+            Ok(_) => {
+                errors.push(format!("Ignoring {}, it is not a table.", path.display()));
+                return None;
+            }
+            Err(err) => {
+                errors.push(format!("Ignoring {}: {err}", path.display()));
+                return None;
+            }
+        };
+        if let Some(Value::Array(paths)) = value.get_mut("libPath") {
+            for path in paths.iter_mut() {
+                if let Value::String(p) = path {
+                    *p = Path::new(root).join(&*p).to_string_lossy().to_string();
+                }
+            }
+        }
+        return Some(value);
+    }
+    None
+}
+
+/// Sets the fields of `value` in `valid`, the settings so far, one at a time. A field that would
+/// make them invalid is skipped and reported, so one bad value does not reset the others.
+fn keep_valid(valid: &mut Value, path: &mut Vec<String>, value: Value, errors: &mut Vec<String>) {
+    let pointer: String = path.iter().map(|k| format!("/{k}")).collect();
+    let is_table = valid.pointer(&pointer).is_some_and(Value::is_object);
+    match value {
+        Value::Object(object) if is_table => {
+            for (key, value) in object {
+                path.push(key);
+                keep_valid(valid, path, value, errors);
+                path.pop();
+            }
+        }
+        value => {
+            let Some((key, parent)) = path.split_last() else {
+                errors.push(String::from("Invalid settings, they are not a table."));
+                return;
+            };
+            let parent: String = parent.iter().map(|k| format!("/{k}")).collect();
+            let mut candidate = valid.clone();
+            if let Some(Value::Object(object)) = candidate.pointer_mut(&parent) {
+                object.insert(key.clone(), value);
+            }
+            match serde_json::from_value::<Settings>(candidate.clone()) {
+                Ok(_) => *valid = candidate,
+                Err(err) => errors.push(format!("Invalid setting {}: {err}", path.join("."))),
+            }
+        }
+    }
+}
+
+/// Merges `over` into `base`. Tables are merged key by key, lists are concatenated and anything
+/// else in `over` replaces what is in `base`.
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(entry) => merge(entry, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(over)) => base.extend(over),
+        (base, over) => *base = over,
+    }
+}
+
+/// Matches `path` against a glob pattern, where `**` matches any number of folders, `*` anything
+/// but a `/` and `?` a single character. Patterns without a `/` match any component of the path,
+/// and relative patterns match anywhere in it. A pattern matching a folder matches everything in
+/// it too.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    if !pattern.contains('/') {
+        return path
+            .split('/')
+            .any(|component| matches(pattern.as_bytes(), component.as_bytes()));
+    }
+    let pattern = if pattern.starts_with('/') || pattern.starts_with("**") {
+        pattern.to_string()
+    } else {
+        format!("**/{pattern}")
+    };
+    matches(pattern.as_bytes(), path.as_bytes())
+        || matches(format!("{pattern}/**").as_bytes(), path.as_bytes())
+}
+
+fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            matches(rest, text)
+                || text
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| **c == b'/')
+                    .any(|(i, _)| matches(rest, &text[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let end = text.iter().position(|c| *c == b'/').unwrap_or(text.len());
+            (0..=end).any(|i| matches(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => !text.is_empty() && text[0] != b'/' && matches(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && matches(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn invalid_values_keep_their_default() {
+        let client = json!({
+            "matlab": {
+                "exclude": ["*.asv"],
+                "formatter": 3,
+                "diagnostics": { "maybeUndefined": "loud", "unsharedGlobal": "off" },
+            }
+        });
+        let (settings, errors) = Settings::resolve(&client, &[]);
+        assert_eq!(settings.exclude, vec!["*.asv"]);
+        assert_eq!(settings.formatter, FormatterSettings::default());
+        assert_eq!(settings.diagnostics.maybe_undefined, Severity::Warning);
+        assert_eq!(settings.diagnostics.unshared_global, Severity::Off);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn project_files_override_the_client() {
+        let root = std::env::temp_dir().join(format!("matlab-lsp-settings-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join(".matlab-lsp.toml"),
+            "libPath = [\"lib\"]\nworkspaceScan = \"genpath\"\n[formatter]\nsparseMath = true\n",
+        )
+        .unwrap();
+        let client = json!({ "libPath": ["/opt/lib"], "formatter": { "sparseAdd": false } });
+        let root_path = root.to_string_lossy().to_string();
+        let (settings, errors) = Settings::resolve(&client, std::slice::from_ref(&root_path));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(
+            settings.lib_path,
+            vec![String::from("/opt/lib"), root_path + "/lib"]
+        );
+        assert_eq!(settings.workspace_scan, ScanMode::Genpath);
+        assert!(settings.formatter.sparse_math);
+        assert!(!settings.formatter.sparse_add);
+    }
+
+    #[test]
+    fn release_is_validated() {
+        let (settings, errors) = Settings::resolve(&json!({ "release": "R2019b" }), &[]);
+        assert!(errors.is_empty());
+        assert_eq!(settings.release, Some(Release::new(2019, 'b')));
+        for invalid in ["2019b", "R2019c", "R19b", "R1999a", "R+201a", "R2019bb"] {
+            let (settings, errors) = Settings::resolve(&json!({ "release": invalid }), &[]);
+            assert_eq!(settings.release, None);
+            assert_eq!(errors.len(), 1, "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn releases_compare_in_order() {
+        assert!(Release::new(2019, 'a') < Release::new(2019, 'b'));
+        assert!(Release::new(2019, 'b') < Release::new(2020, 'a'));
+        assert_eq!(Release::new(2023, 'b').to_string(), "R2023b");
+    }
+
+    #[test]
+    fn glob_without_slash_matches_any_component() {
+        assert!(glob_match("*.asv", "/home/user/code/test.asv"));
+        assert!(glob_match("build", "/home/user/code/build/a.m"));
+        assert!(glob_match("test?", "/code/test1/a.m"));
+        assert!(!glob_match("test?", "/code/test12/a.m"));
+        assert!(!glob_match("*.asv", "/code/test.m"));
+    }
+
+    #[test]
+    fn glob_with_slash_matches_anywhere_in_the_path() {
+        assert!(glob_match("tests/data", "/code/tests/data/a.m"));
+        assert!(glob_match("tests/data/", "/code/tests/data"));
+        assert!(!glob_match("tests/data", "/code/tests/other/a.m"));
+        assert!(glob_match("tests/*.m", "/code/tests/a.m"));
+        assert!(!glob_match("tests/*.m", "/code/tests/sub/a.m"));
+    }
+
+    #[test]
+    fn absolute_glob_matches_from_the_root() {
+        assert!(glob_match("/code/**/*.m", "/code/a.m"));
+        assert!(glob_match("/code/**/*.m", "/code/a/b/c.m"));
+        assert!(!glob_match("/code/**/*.m", "/other/code/a.m"));
+        assert!(glob_match("**/private/**", "/code/private/a.m"));
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::spawn;
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use lsp_server::Message;
+
+use crate::extractors::fast::fast_scan;
+use crate::extractors::full::full_scan;
+use crate::settings::Settings;
+use crate::threads::db::{db_get_parsed_file, db_set_parsed_file};
+use crate::threads::dispatcher::serve_db;
+use crate::types::{ParsedFile, SenderThread, State, ThreadMessage};
+
+/// Files written to a new folder and analysed by the same scans as the server's, with the
+/// database served as the dispatcher does.
+pub struct Fixture {
+    pub root: PathBuf,
+    pub sender: Sender<ThreadMessage>,
+    pub receiver: Receiver<ThreadMessage>,
+    /// Kept so the scans can report their progress.
+    _client: Receiver<Message>,
+    lsp_sender: Sender<Message>,
+}
+
+impl Fixture {
+    /// Scans `files` as the workspace, given as paths relative to the folder and their contents.
+    pub fn new(test: &str, files: &[(&str, &str)]) -> Fixture {
+        Fixture::with_library(test, files, &[])
+    }
+
+    /// Scans `files` as the workspace and `library` as the library path, in the `lib` folder.
+    pub fn with_library(test: &str, files: &[(&str, &str)], library: &[(&str, &str)]) -> Fixture {
+        Fixture::with_settings(test, files, library, Settings::default())
+    }
+
+    pub fn with_settings(
+        test: &str,
+        files: &[(&str, &str)],
+        library: &[(&str, &str)],
+        settings: Settings,
+    ) -> Fixture {
+        let root = std::env::temp_dir().join(format!("matlab-lsp-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let lib = root.join("lib");
+        let workspace = root.join("ws");
+        for (folder, files) in [(&workspace, files), (&lib, library)] {
+            std::fs::create_dir_all(folder).unwrap();
+            for (name, contents) in files {
+                let path = folder.join(name);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            }
+        }
+        let workspace = workspace.to_string_lossy().to_string();
+        let lib = lib.to_string_lossy().to_string();
+        let state = State {
+            ws_path: vec![workspace.clone()],
+            lib_path: vec![lib.clone()],
+            settings: settings.clone(),
+            ..State::default()
+        };
+        let (sender, db_receiver) = unbounded();
+        let (db_sender, receiver) = unbounded();
+        spawn(move || serve_db(state, db_receiver, db_sender));
+        let (lsp_sender, client) = unbounded();
+        let fixture = Fixture {
+            root,
+            sender,
+            receiver,
+            _client: client,
+            lsp_sender,
+        };
+        if !library.is_empty() {
+            fast_scan(
+                fixture.lsp_sender.clone(),
+                fixture.sender.clone(),
+                vec![lib],
+                &settings,
+                0,
+            )
+            .unwrap();
+        }
+        fixture.scan(vec![workspace]);
+        fixture
+    }
+
+    /// Analyses the folders as the workspace scan does.
+    pub fn scan(&self, folders: Vec<String>) {
+        full_scan(
+            self.lsp_sender.clone(),
+            self.sender.clone(),
+            self.receiver.clone(),
+            folders,
+            0,
+        )
+        .unwrap();
+    }
+
+    /// Path of a file of the workspace, or of the library path for `lib/` paths.
+    pub fn path(&self, name: &str) -> String {
+        match name.strip_prefix("lib/") {
+            Some(name) => self.root.join("lib").join(name),
+            None => self.root.join("ws").join(name),
+        }
+        .to_string_lossy()
+        .to_string()
+    }
+
+    pub fn file(&self, name: &str) -> Arc<ParsedFile> {
+        db_get_parsed_file(
+            &self.sender,
+            &self.receiver,
+            self.path(name),
+            SenderThread::Handler,
+        )
+        .unwrap()
+    }
+
+    /// Opens a file in the editor, so its contents are kept.
+    pub fn open(&self, name: &str) -> Arc<ParsedFile> {
+        let mut file = self.file(name).as_ref().clone();
+        file.load_contents().unwrap();
+        file.open = true;
+        let file = Arc::new(file);
+        db_set_parsed_file(&self.sender, Arc::clone(&file), SenderThread::Handler).unwrap();
+        file
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Analyses `code` as the only file of a workspace, open in the editor.
+pub fn open_script(test: &str, code: &str) -> Arc<ParsedFile> {
+    Fixture::new(test, &[("test.m", code)]).open("test.m")
+}
@@ -5,14 +5,20 @@
  */
 
 use crate::extractors::fast::fast_scan;
-use crate::extractors::full::full_scan;
-use crate::threads::db::db_get_request_id;
+use crate::extractors::full::{analyse_dependents, full_scan, rescan_files};
+use crate::extractors::matlab_path::startup_path;
+use crate::extractors::matlab_project::{find_project, MatlabProject};
+use crate::settings::Settings;
+use crate::threads::db::{db_get_request_id, db_get_settings};
 use crate::types::{MessagePayload, SenderThread, ThreadMessage};
-use crate::utils::request_semantic_tokens_refresh;
+use crate::utils::{request_semantic_tokens_refresh, show_message};
 use anyhow::Result;
 use crossbeam_channel::{Receiver, Sender};
+use itertools::Itertools;
 use log::{error, info};
 use lsp_server::Message;
+use lsp_types::MessageType;
+use serde_json::Value;
 
 pub fn start(
     lsp_sender: Sender<Message>,
@@ -28,9 +34,18 @@ pub fn start(
                     &dispatcher_receiver,
                     SenderThread::BackgroundWorker,
                 ) {
-                    if let Err(err) =
-                        fast_scan(lsp_sender.clone(), dispatcher_sender.clone(), path, id)
-                    {
+                    let settings = db_get_settings(
+                        &dispatcher_sender,
+                        &dispatcher_receiver,
+                        SenderThread::BackgroundWorker,
+                    );
+                    if let Err(err) = fast_scan(
+                        lsp_sender.clone(),
+                        dispatcher_sender.clone(),
+                        path,
+                        &settings,
+                        id,
+                    ) {
                         error!("Error scanning folders: {err}");
                     }
                 }
@@ -52,6 +67,36 @@ pub fn start(
                     }
                 }
             }
+            MessagePayload::RescanFiles((changed, deleted)) => {
+                if let Err(err) = rescan_files(
+                    lsp_sender.clone(),
+                    dispatcher_sender.clone(),
+                    dispatcher_receiver.clone(),
+                    changed,
+                    deleted,
+                ) {
+                    error!("Error rescanning files: {err}");
+                }
+            }
+            MessagePayload::AnalyseDependents((names, paths)) => {
+                if let Err(err) = analyse_dependents(
+                    lsp_sender.clone(),
+                    dispatcher_sender.clone(),
+                    dispatcher_receiver.clone(),
+                    names,
+                    paths,
+                ) {
+                    error!("Error analysing dependent files: {err}");
+                }
+            }
+            MessagePayload::ResolveSettings((client, roots, cli_lib_path)) => {
+                let (settings, lib_path) =
+                    resolve_settings(&lsp_sender, &client, &roots, &cli_lib_path)?;
+                dispatcher_sender.send(ThreadMessage {
+                    sender: SenderThread::BackgroundWorker,
+                    payload: MessagePayload::SettingsResolved((settings, lib_path)),
+                })?;
+            }
             _ => {}
         }
         request_semantic_tokens_refresh(
@@ -68,3 +113,107 @@ pub fn start(
     info!("Background Worker exited.");
     Ok(())
 }
+
+/// The settings and the library path. The user is told about invalid settings, and about the
+/// MATLAB Projects whose path is used.
+fn resolve_settings(
+    lsp_sender: &Sender<Message>,
+    client: &Value,
+    roots: &[String],
+    cli_lib_path: &[String],
+) -> Result<(Settings, Vec<String>)> {
+    let (settings, errors) = Settings::resolve(client, roots);
+    if !errors.is_empty() {
+        show_message(lsp_sender, MessageType::WARNING, errors.join("\n"))?;
+    }
+    let projects: Vec<MatlabProject> = roots
+        .iter()
+        .flat_map(|r| find_project(r))
+        .unique_by(|p| p.name.clone())
+        .collect();
+    for project in &projects {
+        show_message(
+            lsp_sender,
+            MessageType::INFO,
+            format!("Using the path of MATLAB project {}.", project.name),
+        )?;
+    }
+    let lib_path = lib_path(cli_lib_path, &settings, roots, &projects);
+    Ok((settings, lib_path))
+}
+
+/// The library path: the one given in the command line or environment, followed by the folders
+/// of the MATLAB Projects containing the workspace roots, the ones added by their `startup.m` and
+/// `pathdef.m`, and the ones in the settings.
+fn lib_path(
+    cli_lib_path: &[String],
+    settings: &Settings,
+    ws_path: &[String],
+    projects: &[MatlabProject],
+) -> Vec<String> {
+    let project: Vec<String> = projects.iter().flat_map(|p| p.path.clone()).collect();
+    let startup = startup_path(ws_path);
+    let mut paths: Vec<String> = vec![];
+    for path in cli_lib_path
+        .iter()
+        .chain(&project)
+        .chain(&startup)
+        .chain(&settings.lib_path)
+    {
+        if !path.is_empty() && !paths.contains(path) {
+            paths.push(path.clone());
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::unbounded;
+    use lsp_types::notification::{Notification, ShowMessage};
+    use lsp_types::ShowMessageParams;
+
+    use super::*;
+
+    #[test]
+    fn project_path_is_used_and_shown() {
+        let root = std::env::temp_dir().join(format!("matlab-lsp-project-{}", std::process::id()));
+        let metadata = root.join("resources/project");
+        std::fs::create_dir_all(metadata.join("ProjectPath")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("Demo.prj"), "").unwrap();
+        std::fs::write(
+            metadata.join("ProjectData.type.Info.xml"),
+            "<Info Name=\"Demo project\"/>",
+        )
+        .unwrap();
+        std::fs::write(
+            metadata.join("ProjectPath/1.type.File.xml"),
+            "<Info Location=\"src\"/>",
+        )
+        .unwrap();
+        let (lsp_sender, client) = unbounded();
+        let roots = vec![root.to_string_lossy().to_string()];
+        let (_, lib_path) = resolve_settings(&lsp_sender, &Value::Null, &roots, &[]).unwrap();
+        let messages: Vec<ShowMessageParams> = client
+            .try_iter()
+            .filter_map(|m| match m {
+                Message::Notification(n) if n.method == ShowMessage::METHOD => {
+                    serde_json::from_value(n.params).ok()
+                }
+                _ => None,
+            })
+            .collect();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            lib_path,
+            vec![root.join("src").to_string_lossy().to_string()]
+        );
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].typ, MessageType::INFO);
+        assert_eq!(
+            messages[0].message,
+            "Using the path of MATLAB project Demo project."
+        );
+    }
+}
@@ -10,7 +10,9 @@ use std::sync::Arc;
 use anyhow::Result;
 use crossbeam_channel::{Receiver, Sender};
 use log::debug;
+use serde_json::Value;
 
+use crate::settings::Settings;
 use crate::types::{
     DBArgument, DBOperation, DBRequest, DBTarget, FunctionDefinition, MessagePayload, ParsedFile,
     SenderThread, ThreadMessage,
@@ -103,6 +105,67 @@ pub fn db_fetch_parsed_files(
     None
 }
 
+/// The folders of the workspace, as opposed to the library path.
+pub fn db_fetch_workspace_paths(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    sender_thread: SenderThread,
+) -> Vec<String> {
+    if sender
+        .send(ThreadMessage {
+            sender: sender_thread,
+            payload: MessagePayload::DB(DBRequest {
+                operation: DBOperation::Fetch,
+                target: DBTarget::WorkspacePath,
+                argument: DBArgument::NotFound,
+            }),
+        })
+        .is_ok()
+    {
+        if let Ok(response) = receiver.recv() {
+            if let MessagePayload::DB(response) = response.payload {
+                if let DBArgument::Paths(paths) = response.argument {
+                    return paths;
+                }
+            }
+        }
+    }
+    vec![]
+}
+
+pub fn db_set_workspace_paths(
+    sender: &Sender<ThreadMessage>,
+    paths: Vec<String>,
+    sender_thread: SenderThread,
+) -> Result<()> {
+    sender.send(ThreadMessage {
+        sender: sender_thread,
+        payload: MessagePayload::DB(DBRequest {
+            operation: DBOperation::Set,
+            target: DBTarget::WorkspacePath,
+            argument: DBArgument::Paths(paths),
+        }),
+    })?;
+    Ok(())
+}
+
+/// Removes a folder from the workspace, along with everything indexed from it.
+pub fn db_delete_workspace_path(
+    sender: &Sender<ThreadMessage>,
+    path: String,
+    sender_thread: SenderThread,
+) -> Result<()> {
+    sender.send(ThreadMessage {
+        sender: sender_thread,
+        payload: MessagePayload::DB(DBRequest {
+            operation: DBOperation::Delete,
+            target: DBTarget::WorkspacePath,
+            argument: DBArgument::String(path),
+        }),
+    })?;
+    Ok(())
+}
+
 pub fn db_get_script(
     sender: &Sender<ThreadMessage>,
     receiver: &Receiver<ThreadMessage>,
@@ -187,6 +250,34 @@ pub fn db_get_function(
     None
 }
 
+pub fn db_get_function_candidates(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    name: String,
+    sender_thread: SenderThread,
+) -> Vec<Arc<FunctionDefinition>> {
+    if sender
+        .send(ThreadMessage {
+            sender: sender_thread,
+            payload: MessagePayload::DB(DBRequest {
+                operation: DBOperation::Get,
+                target: DBTarget::FunctionCandidates,
+                argument: DBArgument::String(name),
+            }),
+        })
+        .is_ok()
+    {
+        if let Ok(response) = receiver.recv() {
+            if let MessagePayload::DB(response) = response.payload {
+                if let DBArgument::FunctionDefinitionList(fs) = response.argument {
+                    return fs;
+                }
+            }
+        }
+    }
+    vec![]
+}
+
 pub fn db_set_function(
     sender: &Sender<ThreadMessage>,
     function: Arc<FunctionDefinition>,
@@ -246,6 +337,34 @@ pub fn db_fetch_functions(
     None
 }
 
+pub fn db_get_global(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    name: String,
+    sender_thread: SenderThread,
+) -> HashMap<String, Arc<ParsedFile>> {
+    if sender
+        .send(ThreadMessage {
+            sender: sender_thread,
+            payload: MessagePayload::DB(DBRequest {
+                operation: DBOperation::Get,
+                target: DBTarget::Global,
+                argument: DBArgument::String(name),
+            }),
+        })
+        .is_ok()
+    {
+        if let Ok(response) = receiver.recv() {
+            if let MessagePayload::DB(response) = response.payload {
+                if let DBArgument::ParsedFiles(fs) = response.argument {
+                    return fs;
+                }
+            }
+        }
+    }
+    HashMap::new()
+}
+
 pub fn db_get_package(
     sender: &Sender<ThreadMessage>,
     receiver: &Receiver<ThreadMessage>,
@@ -290,6 +409,23 @@ pub fn db_set_packages(
     Ok(())
 }
 
+/// Removes a package and its subpackages.
+pub fn db_delete_package(
+    sender: &Sender<ThreadMessage>,
+    package: String,
+    sender_thread: SenderThread,
+) -> Result<()> {
+    sender.send(ThreadMessage {
+        sender: sender_thread,
+        payload: MessagePayload::DB(DBRequest {
+            operation: DBOperation::Delete,
+            target: DBTarget::Package,
+            argument: DBArgument::String(package),
+        }),
+    })?;
+    Ok(())
+}
+
 pub fn db_get_request_id(
     sender: &Sender<ThreadMessage>,
     receiver: &Receiver<ThreadMessage>,
@@ -316,3 +452,121 @@ pub fn db_get_request_id(
     }
     None
 }
+
+/// Whether the client accepts location links as the result of goto definition.
+pub fn db_get_link_support(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    sender_thread: SenderThread,
+) -> bool {
+    if sender
+        .send(ThreadMessage {
+            sender: sender_thread,
+            payload: MessagePayload::DB(DBRequest {
+                operation: DBOperation::Get,
+                target: DBTarget::LinkSupport,
+                argument: DBArgument::NotFound,
+            }),
+        })
+        .is_ok()
+    {
+        if let Ok(response) = receiver.recv() {
+            if let MessagePayload::DB(response) = response.payload {
+                if let DBArgument::Bool(link_support) = response.argument {
+                    return link_support;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Whether the workspace was scanned, with no other scan of it waiting or running. Until then some
+/// files are not indexed yet.
+pub fn db_is_workspace_scanned(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    sender_thread: SenderThread,
+) -> bool {
+    if sender
+        .send(ThreadMessage {
+            sender: sender_thread,
+            payload: MessagePayload::DB(DBRequest {
+                operation: DBOperation::Get,
+                target: DBTarget::WorkspaceScan,
+                argument: DBArgument::NotFound,
+            }),
+        })
+        .is_ok()
+    {
+        if let Ok(response) = receiver.recv() {
+            if let MessagePayload::DB(response) = response.payload {
+                if let DBArgument::Bool(scanned) = response.argument {
+                    return scanned;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// The settings given by the client, or the defaults if they can not be fetched.
+pub fn db_get_settings(
+    sender: &Sender<ThreadMessage>,
+    receiver: &Receiver<ThreadMessage>,
+    sender_thread: SenderThread,
+) -> Settings {
+    if sender
+        .send(ThreadMessage {
+            sender: sender_thread,
+            payload: MessagePayload::DB(DBRequest {
+                operation: DBOperation::Get,
+                target: DBTarget::Settings,
+                argument: DBArgument::NotFound,
+            }),
+        })
+        .is_ok()
+    {
+        if let Ok(response) = receiver.recv() {
+            if let MessagePayload::DB(response) = response.payload {
+                if let DBArgument::Settings(settings) = response.argument {
+                    return settings;
+                }
+            }
+        }
+    }
+    Settings::default()
+}
+
+/// Replaces the settings sent by the client, rescanning and republishing diagnostics as needed.
+pub fn db_set_settings(
+    sender: &Sender<ThreadMessage>,
+    client_settings: Value,
+    sender_thread: SenderThread,
+) -> Result<()> {
+    sender.send(ThreadMessage {
+        sender: sender_thread,
+        payload: MessagePayload::DB(DBRequest {
+            operation: DBOperation::Set,
+            target: DBTarget::Settings,
+            argument: DBArgument::Json(client_settings),
+        }),
+    })?;
+    Ok(())
+}
+
+/// Reads the project configuration files again, keeping the settings sent by the client.
+pub fn db_reload_settings(
+    sender: &Sender<ThreadMessage>,
+    sender_thread: SenderThread,
+) -> Result<()> {
+    sender.send(ThreadMessage {
+        sender: sender_thread,
+        payload: MessagePayload::DB(DBRequest {
+            operation: DBOperation::Set,
+            target: DBTarget::Settings,
+            argument: DBArgument::NotFound,
+        }),
+    })?;
+    Ok(())
+}
@@ -5,12 +5,17 @@
  */
 
 use std::collections::{HashMap, VecDeque};
+use std::path::Path;
 use std::sync::Arc;
 
 use crate::args::Arguments;
+use crate::extractors::fast::reached_by_traversal;
+use crate::extractors::full::qualified_name;
+use crate::extractors::matlab_path::env_path;
+use crate::settings::{ScanMode, Settings};
 use crate::types::{
-    DBArgument, DBOperation, DBRequest, DBTarget, MessagePayload, SenderThread, State,
-    ThreadMessage, Workspace,
+    DBArgument, DBOperation, DBRequest, DBTarget, FunctionDefinition, MessagePayload, ParsedFile,
+    SenderThread, State, ThreadMessage, Workspace,
 };
 
 use anyhow::Result;
@@ -27,43 +32,60 @@ pub fn start(
     handler_sender: Sender<ThreadMessage>,
     bw_sender: Sender<ThreadMessage>,
 ) -> Result<()> {
+    let mut cli_lib_path: Vec<String> = arguments
+        .path
+        .unwrap_or("".into())
+        .split(':')
+        .map(String::from)
+        .collect();
+    cli_lib_path.extend(env_path());
+    let ws_path: Vec<String> = if let Some(ws) = init.workspace_folders {
+        ws.iter().map(|w| w.uri.path().to_string()).collect()
+    } else if let Some(path) = init.root_uri {
+        vec![path.path().to_string()]
+    } else {
+        vec![]
+    };
+    let client_settings = init.initialization_options.unwrap_or_default();
+    let link_support = init
+        .capabilities
+        .text_document
+        .as_ref()
+        .and_then(|t| t.definition.as_ref())
+        .and_then(|d| d.link_support)
+        .unwrap_or(false);
     let mut state = State {
-        lib_path: arguments
-            .path
-            .unwrap_or("".into())
-            .split(':')
-            .map(String::from)
-            .collect(),
-        ws_path: if let Some(ws) = init.workspace_folders {
-            ws.iter().map(|w| w.uri.path().to_string()).collect()
-        } else if let Some(path) = init.root_uri {
-            vec![path.path().to_string()]
-        } else {
-            vec![]
-        },
+        lib_path: vec![],
+        cli_lib_path,
+        client_settings,
+        settings: Settings::default(),
+        link_support,
+        ws_path,
         requests_queue: VecDeque::new(),
         notifications_queue: VecDeque::new(),
         responses_queue: VecDeque::new(),
         handler_idle: true,
         bw_idle: false,
+        scanning_workspace: true,
         parsed_files: HashMap::new(),
+        function_candidates: HashMap::new(),
+        globals: HashMap::new(),
         workspace: Workspace::default(),
         request_id: 0,
         bw_queue: VecDeque::new(),
         handler_queue: VecDeque::new(),
     };
+    // The settings read files of the workspace, so they are resolved on the background worker,
+    // and the scans wait for them.
     bw_sender.send(ThreadMessage {
         sender: SenderThread::Dispatcher,
-        payload: MessagePayload::ScanPath(state.lib_path.clone()),
+        payload: MessagePayload::ResolveSettings((
+            state.client_settings.clone(),
+            state.ws_path.clone(),
+            state.cli_lib_path.clone(),
+        )),
     })?;
-    state.bw_queue.push_back(ThreadMessage {
-        sender: SenderThread::Dispatcher,
-        payload: MessagePayload::ScanWorkspace(state.ws_path.clone()),
-    });
-    state.bw_queue.push_back(ThreadMessage {
-        sender: SenderThread::Dispatcher,
-        payload: MessagePayload::ScanWorkspace(state.ws_path.clone()),
-    });
+    let mut resolved = false;
     loop {
         if state.handler_idle {
             state.handler_idle = false;
@@ -91,6 +113,9 @@ pub fn start(
         if state.bw_idle {
             if let Some(msg) = state.bw_queue.pop_front() {
                 state.bw_idle = false;
+                if let MessagePayload::ScanWorkspace(_) = msg.payload {
+                    state.scanning_workspace = true;
+                }
                 bw_sender.send(msg)?;
             }
         }
@@ -126,6 +151,7 @@ pub fn start(
                         state.handler_idle = true;
                     } else if let SenderThread::BackgroundWorker = msg.sender {
                         state.bw_idle = true;
+                        state.scanning_workspace = false;
                     }
                 }
                 MessagePayload::DB(req) => match msg.sender {
@@ -138,23 +164,52 @@ pub fn start(
                     _ => {}
                 },
                 MessagePayload::InitPath((files, functions)) => {
-                    for file in files {
-                        state.parsed_files.insert(file.path.clone(), file);
-                    }
-                    for function in functions {
-                        let key = function.package.clone() + "." + &function.name;
-                        let key = key.strip_prefix('.').map(String::from).unwrap_or(key);
-                        state.workspace.functions.insert(key, function);
-                    }
+                    init_path(&mut state, files, functions)
                 }
-                MessagePayload::ScanWorkspace(_) => state.bw_queue.push_back(ThreadMessage {
+                // An empty list stands for the whole workspace.
+                MessagePayload::ScanWorkspace(folders) => state.bw_queue.push_back(ThreadMessage {
                     sender: SenderThread::Dispatcher,
-                    payload: MessagePayload::ScanWorkspace(state.ws_path.clone()),
+                    payload: MessagePayload::ScanWorkspace(if folders.is_empty() {
+                        state.ws_path.clone()
+                    } else {
+                        folders
+                    }),
                 }),
-                MessagePayload::ScanOpen => state.handler_queue.push_back(ThreadMessage {
+                MessagePayload::SettingsResolved((settings, lib_path)) if resolved => {
+                    apply_settings(&mut state, settings, lib_path)
+                }
+                MessagePayload::SettingsResolved((settings, lib_path)) => {
+                    resolved = true;
+                    state.lib_path = lib_path;
+                    state.settings = settings;
+                    state.bw_queue.push_back(ThreadMessage {
+                        sender: SenderThread::Dispatcher,
+                        payload: MessagePayload::ScanPath(state.lib_path.clone()),
+                    });
+                    state.bw_queue.push_back(ThreadMessage {
+                        sender: SenderThread::Dispatcher,
+                        payload: MessagePayload::ScanWorkspace(state.ws_path.clone()),
+                    });
+                    state.bw_queue.push_back(ThreadMessage {
+                        sender: SenderThread::Dispatcher,
+                        payload: MessagePayload::ScanWorkspace(state.ws_path.clone()),
+                    });
+                }
+                MessagePayload::RescanFiles(files) => state.bw_queue.push_back(ThreadMessage {
                     sender: SenderThread::Dispatcher,
-                    payload: MessagePayload::ScanOpen,
+                    payload: MessagePayload::RescanFiles(files),
                 }),
+                // Sent by the background worker at the end of a workspace scan, so the open files
+                // are analysed again seeing every file.
+                MessagePayload::ScanOpen => {
+                    if let SenderThread::BackgroundWorker = msg.sender {
+                        state.scanning_workspace = false;
+                    }
+                    state.handler_queue.push_back(ThreadMessage {
+                        sender: SenderThread::Dispatcher,
+                        payload: MessagePayload::ScanOpen,
+                    })
+                }
                 _ => {}
             }
         }
@@ -182,6 +237,20 @@ fn handle_db_transaction(
                 },
                 _ => DBArgument::NotFound,
             },
+            DBTarget::Global => match req.argument {
+                DBArgument::String(name) => DBArgument::ParsedFiles(
+                    state
+                        .globals
+                        .get(&name)
+                        .into_iter()
+                        .flatten()
+                        .flat_map(|p| state.parsed_files.get_key_value(p))
+                        .map(|(k, v)| (k.clone(), Arc::clone(v)))
+                        .collect(),
+                ),
+                _ => DBArgument::NotFound,
+            },
+            DBTarget::LinkSupport => DBArgument::Bool(state.link_support),
             DBTarget::Package => match req.argument {
                 DBArgument::String(pkg) => DBArgument::Packages(
                     state
@@ -194,6 +263,16 @@ fn handle_db_transaction(
                 ),
                 _ => DBArgument::NotFound,
             },
+            DBTarget::FunctionCandidates => match req.argument {
+                DBArgument::String(name) => DBArgument::FunctionDefinitionList(
+                    state
+                        .function_candidates
+                        .get(&name)
+                        .cloned()
+                        .unwrap_or_default(),
+                ),
+                _ => DBArgument::NotFound,
+            },
             DBTarget::FunctionDefinition => match req.argument {
                 DBArgument::String(path) => match state.workspace.functions.get(&path) {
                     Some(file) => DBArgument::FunctionDefinition(Arc::clone(file)),
@@ -221,6 +300,15 @@ fn handle_db_transaction(
                 }
                 _ => DBArgument::NotFound,
             },
+            DBTarget::Settings => DBArgument::Settings(state.settings.clone()),
+            DBTarget::WorkspacePath => DBArgument::NotFound,
+            DBTarget::WorkspaceScan => DBArgument::Bool(
+                !state.scanning_workspace
+                    && !state
+                        .bw_queue
+                        .iter()
+                        .any(|m| matches!(m.payload, MessagePayload::ScanWorkspace(_))),
+            ),
         },
         //////////////////////////////////////////////////////////////////////////////
         //                                                                          //
@@ -236,6 +324,7 @@ fn handle_db_transaction(
                         }
                     }
                     debug!("Setting file {file:?}");
+                    index_globals(state, &file.path, Some(&file));
                     state.parsed_files.insert(file.path.clone(), file);
                     return Ok(());
                 }
@@ -250,15 +339,40 @@ fn handle_db_transaction(
             },
             DBTarget::FunctionDefinition => match req.argument {
                 DBArgument::FunctionDefinition(func) => {
-                    let name = format!("{}.{}", func.package, func.name);
-                    let name = name.strip_prefix('.').map(String::from).unwrap_or(name);
-                    state.workspace.functions.insert(name, func);
+                    insert_function(state, func);
                     return Ok(());
                 }
                 _ => DBArgument::NotFound,
             },
+            DBTarget::FunctionCandidates => DBArgument::NotFound,
+            DBTarget::Global => DBArgument::NotFound,
+            DBTarget::LinkSupport => DBArgument::NotFound,
             DBTarget::RequestID => DBArgument::NotFound,
             DBTarget::Script => DBArgument::NotFound,
+            DBTarget::Settings => match req.argument {
+                DBArgument::Json(client_settings) => {
+                    state.client_settings = client_settings;
+                    reload_settings(state);
+                    return Ok(());
+                }
+                // Keeps the client settings, only the project files changed.
+                DBArgument::NotFound => {
+                    reload_settings(state);
+                    return Ok(());
+                }
+                _ => DBArgument::NotFound,
+            },
+            DBTarget::WorkspacePath => match req.argument {
+                DBArgument::Paths(paths) => {
+                    state.ws_path.extend(paths);
+                    state.ws_path.sort();
+                    state.ws_path.dedup();
+                    reload_settings(state);
+                    return Ok(());
+                }
+                _ => DBArgument::NotFound,
+            },
+            DBTarget::WorkspaceScan => DBArgument::NotFound,
         },
         //////////////////////////////////////////////////////////////////////////////
         //                                                                          //
@@ -268,21 +382,46 @@ fn handle_db_transaction(
         DBOperation::Delete => match &req.target {
             DBTarget::ParsedFile => match req.argument {
                 DBArgument::String(path) => {
+                    index_globals(state, &path, None);
                     state.parsed_files.remove(&path);
                     return Ok(());
                 }
                 _ => DBArgument::NotFound,
             },
-            DBTarget::Package => DBArgument::NotFound,
+            DBTarget::FunctionCandidates => DBArgument::NotFound,
+            DBTarget::Global => DBArgument::NotFound,
+            DBTarget::LinkSupport => DBArgument::NotFound,
+            DBTarget::Package => match req.argument {
+                DBArgument::String(pkg) => {
+                    let sub = format!("{pkg}.");
+                    state
+                        .workspace
+                        .packages
+                        .retain(|p| *p != pkg && !p.starts_with(&sub));
+                    return Ok(());
+                }
+                _ => DBArgument::NotFound,
+            },
             DBTarget::Script => DBArgument::NotFound,
             DBTarget::FunctionDefinition => match req.argument {
                 DBArgument::String(path) => {
-                    state.workspace.functions.retain(|_, f| f.path != path);
+                    delete_file_functions(state, &path);
                     return Ok(());
                 }
                 _ => DBArgument::NotFound,
             },
             DBTarget::RequestID => DBArgument::NotFound,
+            DBTarget::Settings => DBArgument::NotFound,
+            DBTarget::WorkspacePath => match req.argument {
+                DBArgument::String(path) => {
+                    state.ws_path.retain(|p| *p != path);
+                    evict_folder(state, &path);
+                    reload_settings(state);
+                    return Ok(());
+                }
+                _ => DBArgument::NotFound,
+            },
+            DBTarget::WorkspaceScan => DBArgument::NotFound,
         },
         //////////////////////////////////////////////////////////////////////////////
         //                                                                          //
@@ -291,6 +430,9 @@ fn handle_db_transaction(
         //////////////////////////////////////////////////////////////////////////////
         DBOperation::Fetch => match &req.target {
             DBTarget::ParsedFile => DBArgument::ParsedFiles(state.parsed_files.clone()),
+            DBTarget::FunctionCandidates => DBArgument::NotFound,
+            DBTarget::Global => DBArgument::NotFound,
+            DBTarget::LinkSupport => DBArgument::NotFound,
             DBTarget::Package => DBArgument::NotFound,
             DBTarget::Script => DBArgument::ParsedFiles(
                 state
@@ -304,6 +446,9 @@ fn handle_db_transaction(
                 DBArgument::FunctionDefinitions(state.workspace.functions.clone())
             }
             DBTarget::RequestID => DBArgument::NotFound,
+            DBTarget::Settings => DBArgument::NotFound,
+            DBTarget::WorkspacePath => DBArgument::Paths(state.ws_path.clone()),
+            DBTarget::WorkspaceScan => DBArgument::NotFound,
         },
     };
     sender.send(ThreadMessage {
@@ -316,3 +461,301 @@ fn handle_db_transaction(
     })?;
     Ok(())
 }
+
+fn init_path(
+    state: &mut State,
+    files: Vec<Arc<ParsedFile>>,
+    functions: Vec<Arc<FunctionDefinition>>,
+) {
+    for file in files {
+        // Library folders can be rescanned while some of their files are open.
+        if state.parsed_files.get(&file.path).is_some_and(|f| f.open) {
+            continue;
+        }
+        state.parsed_files.insert(file.path.clone(), file);
+    }
+    for function in functions {
+        insert_function(state, function);
+    }
+}
+
+/// Answers the database requests of the tests on `sender`, until every sender of `receiver` is
+/// dropped.
+#[cfg(test)]
+pub fn serve_db(state: State, receiver: Receiver<ThreadMessage>, sender: Sender<ThreadMessage>) {
+    let mut state = state;
+    while let Ok(msg) = receiver.recv() {
+        let from_handler = matches!(msg.sender, SenderThread::Handler);
+        let result = match msg.payload {
+            MessagePayload::DB(req) => {
+                handle_db_transaction(&mut state, sender.clone(), req, from_handler)
+            }
+            MessagePayload::InitPath((files, functions)) => {
+                init_path(&mut state, files, functions);
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+}
+
+/// Adds a definition of a function. The definitions of a name are kept in path order, and calls
+/// resolve to the first one, as MATLAB picks the first match on its path.
+fn insert_function(state: &mut State, function: Arc<FunctionDefinition>) {
+    let name = format!("{}.{}", function.package, function.name);
+    let name = name.strip_prefix('.').map(String::from).unwrap_or(name);
+    let mut candidates = state.function_candidates.remove(&name).unwrap_or_default();
+    candidates.retain(|f| f.path != function.path);
+    candidates.push(function);
+    candidates.sort_by_cached_key(|f| path_order(state, &f.path));
+    state
+        .workspace
+        .functions
+        .insert(name.clone(), Arc::clone(&candidates[0]));
+    state.function_candidates.insert(name, candidates);
+}
+
+/// Where a file comes on the path: the workspace folders first, then the library path in its
+/// order, and within a folder its files before the ones of its subfolders.
+fn path_order(state: &State, path: &str) -> (usize, usize, String) {
+    let root = state
+        .ws_path
+        .iter()
+        .chain(state.lib_path.iter())
+        .position(|r| !r.is_empty() && Path::new(path).starts_with(r))
+        .unwrap_or(usize::MAX);
+    (root, Path::new(path).components().count(), path.into())
+}
+
+/// Removes the functions defined in `path`. If a removed function was shadowing another one, the
+/// next one on the path takes its place.
+fn delete_file_functions(state: &mut State, path: &str) {
+    state.workspace.functions.retain(|_, f| f.path != path);
+    for candidates in state.function_candidates.values_mut() {
+        candidates.retain(|f| f.path != path);
+    }
+    state.function_candidates.retain(|_, c| !c.is_empty());
+    for (name, candidates) in &state.function_candidates {
+        if !state.workspace.functions.contains_key(name) {
+            state
+                .workspace
+                .functions
+                .insert(name.clone(), Arc::clone(&candidates[0]));
+        }
+    }
+}
+
+/// Drops what was indexed from a folder removed from the workspace. Open files are kept, and so
+/// are files still reachable through another workspace folder or the library path.
+fn evict_folder(state: &mut State, folder: &str) {
+    let roots: Vec<&String> = state.ws_path.iter().chain(state.lib_path.iter()).collect();
+    let inside = |path: &str, root: &str| Path::new(path).starts_with(root) && !root.is_empty();
+    let evicted: Vec<String> = state
+        .parsed_files
+        .values()
+        .filter(|f| !f.open && inside(&f.path, folder) && !roots.iter().any(|r| inside(&f.path, r)))
+        .map(|f| f.path.clone())
+        .collect();
+    evict_files(state, &evicted);
+}
+
+/// Drops the given files from every index, along with the packages left empty. The files that
+/// referenced them are analysed again on the background worker.
+fn evict_files(state: &mut State, paths: &[String]) {
+    let mut names = vec![];
+    for path in paths {
+        index_globals(state, path, None);
+        delete_file_functions(state, path);
+        if let Some(file) = state.parsed_files.remove(path) {
+            names.push(qualified_name(&file));
+        }
+    }
+    // Packages are only kept while some file is still in them.
+    let files: Vec<&String> = state.parsed_files.values().map(|f| &f.package).collect();
+    state.workspace.packages.retain(|p| {
+        let sub = format!("{p}.");
+        let kept = files.iter().any(|f| *f == p || f.starts_with(&sub));
+        if !kept {
+            names.push(p.clone());
+        }
+        kept
+    });
+    if !names.is_empty() {
+        state.bw_queue.push_back(ThreadMessage {
+            sender: SenderThread::Dispatcher,
+            payload: MessagePayload::AnalyseDependents((names, paths.to_vec())),
+        });
+    }
+}
+
+/// Merges the client settings with the project files and finds the library path again, on the
+/// background worker, which sends the result back to be applied. It runs before any scan waiting
+/// in the queue, and replaces a resolution still waiting there, which would be outdated.
+fn reload_settings(state: &mut State) {
+    state
+        .bw_queue
+        .retain(|m| !matches!(m.payload, MessagePayload::ResolveSettings(_)));
+    state.bw_queue.push_front(ThreadMessage {
+        sender: SenderThread::Dispatcher,
+        payload: MessagePayload::ResolveSettings((
+            state.client_settings.clone(),
+            state.ws_path.clone(),
+            state.cli_lib_path.clone(),
+        )),
+    });
+}
+
+/// Replaces the settings. Changes to the library path, the excludes or the workspace scan mode
+/// drop what is no longer reachable and rescan the rest, which republishes diagnostics when done.
+/// Any other change only needs the diagnostics of open files republished.
+fn apply_settings(state: &mut State, settings: Settings, lib_path: Vec<String>) {
+    let rescan = lib_path != state.lib_path
+        || settings.exclude != state.settings.exclude
+        || settings.workspace_scan != state.settings.workspace_scan;
+    let removed: Vec<String> = state
+        .lib_path
+        .iter()
+        .filter(|p| !lib_path.contains(p))
+        .cloned()
+        .collect();
+    let narrowed = state.settings.workspace_scan == ScanMode::Genpath
+        && settings.workspace_scan == ScanMode::Packages;
+    state.lib_path = lib_path;
+    state.settings = settings;
+    for folder in &removed {
+        evict_folder(state, folder);
+    }
+    // Without `genpath`, the files in plain subfolders of the roots are no longer scanned.
+    let roots: Vec<&String> = state.ws_path.iter().chain(state.lib_path.iter()).collect();
+    let reached = |path: &str| !narrowed || roots.iter().any(|r| reached_by_traversal(r, path));
+    let evicted: Vec<String> = state
+        .parsed_files
+        .values()
+        .filter(|f| !f.open && (state.settings.is_excluded(&f.path) || !reached(&f.path)))
+        .map(|f| f.path.clone())
+        .collect();
+    evict_files(state, &evicted);
+    if rescan {
+        state.bw_queue.push_back(ThreadMessage {
+            sender: SenderThread::Dispatcher,
+            payload: MessagePayload::ScanPath(state.lib_path.clone()),
+        });
+        state.bw_queue.push_back(ThreadMessage {
+            sender: SenderThread::Dispatcher,
+            payload: MessagePayload::ScanWorkspace(state.ws_path.clone()),
+        });
+    } else {
+        state.handler_queue.push_back(ThreadMessage {
+            sender: SenderThread::Dispatcher,
+            payload: MessagePayload::ScanOpen,
+        });
+    }
+}
+
+/// Keeps the global variable index in sync with the stored files. The entries of `path` are
+/// dropped and, if a new version of the file is given, its global declarations are added back.
+fn index_globals(state: &mut State, path: &str, file: Option<&ParsedFile>) {
+    for paths in state.globals.values_mut() {
+        paths.retain(|p| p != path);
+    }
+    state.globals.retain(|_, paths| !paths.is_empty());
+    if let Some(file) = file {
+        for var in &file.workspace.variables {
+            let v_ref = var.borrow();
+            if v_ref.is_global {
+                let paths = state.globals.entry(v_ref.name.clone()).or_default();
+                if !paths.iter().any(|p| p == path) {
+                    paths.push(path.to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::threads::db::{
+        db_delete_workspace_path, db_fetch_parsed_files, db_fetch_workspace_paths, db_get_function,
+        db_get_package,
+    };
+
+    fn function(path: &str) -> Arc<FunctionDefinition> {
+        Arc::new(FunctionDefinition {
+            name: "f".into(),
+            path: path.into(),
+            ..FunctionDefinition::default()
+        })
+    }
+
+    fn state() -> State {
+        State {
+            ws_path: vec!["/ws".into()],
+            lib_path: vec!["/first".into(), "/second".into()],
+            ..State::default()
+        }
+    }
+
+    fn candidates(state: &State) -> Vec<String> {
+        state.function_candidates["f"]
+            .iter()
+            .map(|f| f.path.clone())
+            .collect()
+    }
+
+    #[test]
+    fn functions_resolve_in_path_order() {
+        let mut state = state();
+        insert_function(&mut state, function("/second/f.m"));
+        insert_function(&mut state, function("/ws/sub/f.m"));
+        insert_function(&mut state, function("/first/f.m"));
+        insert_function(&mut state, function("/ws/f.m"));
+        assert_eq!(
+            candidates(&state),
+            vec!["/ws/f.m", "/ws/sub/f.m", "/first/f.m", "/second/f.m"]
+        );
+        assert_eq!(state.workspace.functions["f"].path, "/ws/f.m");
+    }
+
+    #[test]
+    fn shadowed_function_takes_the_place_of_a_deleted_one() {
+        let mut state = state();
+        insert_function(&mut state, function("/first/f.m"));
+        insert_function(&mut state, function("/ws/f.m"));
+        insert_function(&mut state, function("/second/f.m"));
+        delete_file_functions(&mut state, "/ws/f.m");
+        assert_eq!(state.workspace.functions["f"].path, "/first/f.m");
+        delete_file_functions(&mut state, "/first/f.m");
+        delete_file_functions(&mut state, "/second/f.m");
+        assert!(!state.workspace.functions.contains_key("f"));
+        assert!(state.function_candidates.is_empty());
+    }
+
+    #[test]
+    fn removed_workspace_folder_is_evicted() {
+        let fixture = crate::testing::Fixture::with_library(
+            "dispatcher-remove-folder",
+            &[
+                ("+pkg/helper.m", "function helper\nend\n"),
+                ("main.m", "pkg.helper();\n"),
+            ],
+            &[("util.m", "function util\nend\n")],
+        );
+        let (sender, receiver) = (&fixture.sender, &fixture.receiver);
+        let workspace = fixture.path("");
+        let workspace = workspace.trim_end_matches('/').to_string();
+        db_delete_workspace_path(sender, workspace, SenderThread::Handler).unwrap();
+        let files = db_fetch_parsed_files(sender, receiver, SenderThread::Handler).unwrap();
+        let paths: Vec<&String> = files.keys().collect();
+        assert_eq!(paths, vec![&fixture.path("lib/util.m")]);
+        assert!(
+            db_get_function(sender, receiver, "pkg.helper".into(), SenderThread::Handler).is_none()
+        );
+        assert!(db_get_function(sender, receiver, "util".into(), SenderThread::Handler).is_some());
+        assert!(db_get_package(sender, receiver, "pkg".into(), SenderThread::Handler).is_empty());
+        assert!(db_fetch_workspace_paths(sender, receiver, SenderThread::Handler).is_empty());
+    }
+}
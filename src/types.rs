@@ -10,8 +10,12 @@ use std::time::Instant;
 
 use atomic_refcell::AtomicRefCell;
 use lsp_server::{Message, Notification, Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tree_sitter::{Point, Tree};
 
+use crate::settings::Settings;
+
 //////////////////////////////////////////////////////////////////////////////
 //                                                                          //
 //                             Message Passing                              //
@@ -36,11 +40,17 @@ pub enum DBOperation {
 
 #[derive(Debug, Clone)]
 pub enum DBTarget {
+    FunctionCandidates,
     FunctionDefinition,
+    Global,
+    LinkSupport,
     Package,
     ParsedFile,
     RequestID,
     Script,
+    Settings,
+    WorkspacePath,
+    WorkspaceScan,
 }
 
 #[derive(Debug, Clone)]
@@ -48,10 +58,15 @@ pub enum DBArgument {
     ParsedFile(Arc<ParsedFile>),
     ParsedFiles(HashMap<String, Arc<ParsedFile>>),
     Packages(Vec<String>),
+    Paths(Vec<String>),
+    Settings(Settings),
+    Json(Value),
     FunctionDefinition(Arc<FunctionDefinition>),
     FunctionDefinitions(HashMap<String, Arc<FunctionDefinition>>),
+    FunctionDefinitionList(Vec<Arc<FunctionDefinition>>),
     String(String),
     Integer(i32),
+    Bool(bool),
     NotFound,
 }
 
@@ -70,6 +85,14 @@ pub enum MessagePayload {
     ScanPath(Vec<String>),
     ScanWorkspace(Vec<String>),
     ScanOpen,
+    /// Files and folders changed and deleted on disk.
+    RescanFiles((Vec<String>, Vec<String>)),
+    /// Names and paths of definitions that were removed from the index.
+    AnalyseDependents((Vec<String>, Vec<String>)),
+    /// Client settings, workspace roots and command line library path, to resolve the settings
+    /// and the library path from.
+    ResolveSettings((Value, Vec<String>, Vec<String>)),
+    SettingsResolved((Settings, Vec<String>)),
     Done,
     Exit,
 }
@@ -86,12 +109,21 @@ pub struct ThreadMessage {
 //                                                                          //
 //////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct State {
-    /// Path of libraries, given as an argument or ENV var.
+    /// Path of libraries, given as an argument, ENV var, startup files or in the settings.
     pub lib_path: Vec<String>,
     /// Path of the current workspace.
     pub ws_path: Vec<String>,
+    /// Path of libraries given as an argument or ENV vars, before adding the ones found in the
+    /// workspace and in `settings`.
+    pub cli_lib_path: Vec<String>,
+    /// Settings as sent by the client, before merging the project files.
+    pub client_settings: Value,
+    /// Settings in effect, see `Settings` for how they are merged.
+    pub settings: Settings,
+    /// Whether the client accepts location links as the result of goto definition.
+    pub link_support: bool,
 
     /// Request queue, of items waiting to be processed.
     pub requests_queue: VecDeque<Request>,
@@ -108,11 +140,19 @@ pub struct State {
     pub handler_idle: bool,
     /// Whether the Background Worker thread is idle.
     pub bw_idle: bool,
+    /// Whether the Background Worker is scanning the workspace, or waiting for the settings before
+    /// its first scan.
+    pub scanning_workspace: bool,
     /// The id of last sent request.
     pub request_id: i32,
 
     /// Map of parsed files. The key is the file's path.
     pub parsed_files: HashMap<String, Arc<ParsedFile>>,
+    /// Map of qualified function names to every definition found for them, including the ones
+    /// shadowed by the definition in `workspace.functions`.
+    pub function_candidates: HashMap<String, Vec<Arc<FunctionDefinition>>>,
+    /// Map of global variable names to the paths of the files declaring them.
+    pub globals: HashMap<String, Vec<String>>,
     /// Global Workspace
     pub workspace: Workspace,
 }
@@ -141,11 +181,16 @@ pub struct ParsedFile {
     pub package: String,
     /// Whether this file is a script
     pub is_script: bool,
+    /// Class defined by this file, if it is a classdef file.
+    pub class: Option<ClassDefinition>,
+    /// Range of the name of the method defined by this file, if it is a method file of a class
+    /// folder.
+    pub method_range: Option<Range>,
     /// Workspace
     pub workspace: Workspace,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct FunctionSignature {
     /// Range of the function's name.
     pub name_range: Range,
@@ -171,7 +216,7 @@ pub struct FunctionSignature {
     pub range: Range,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct FunctionDefinition {
     /// Location in the file of the whole function definition.
     pub loc: Range,
@@ -185,13 +230,33 @@ pub struct FunctionDefinition {
     pub package: String,
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ClassDefinition {
+    /// Location in the file of the whole class definition.
+    pub loc: Range,
+    /// Name of the class (without namespace).
+    pub name: String,
+    /// Range of the class's name.
+    pub name_range: Range,
+    /// Path of the file this class is in.
+    pub path: String,
+    /// Package this class is in (or empty if not)
+    pub package: String,
+    /// Superclasses, with their namespaces, as written after the `<`.
+    pub superclasses: Vec<String>,
+    /// Methods defined or declared in the classdef file, with the range of their names.
+    pub methods: Vec<(String, Range)>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct VariableDefinition {
     pub loc: Range,
     pub name: String,
-    pub cleared: usize,
     pub is_parameter: bool,
     pub is_global: bool,
+    /// Path of the script that left this variable behind, and where the script defines it. In
+    /// that case `loc` is the location of the script call.
+    pub script: Option<(String, Range)>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -212,6 +277,33 @@ pub struct Reference {
     pub target: ReferenceTarget,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ReachingDefinitions {
+    /// Assignments that may reach a reference, with the variable each one assigns.
+    pub assignments: Vec<(Range, Arc<AtomicRefCell<VariableDefinition>>)>,
+    /// Whether some path reaches the reference without assigning the variable.
+    pub maybe_undefined: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BodyDefinitions {
+    /// Range of the function, or of the whole file for the script code.
+    pub range: Range,
+    /// Assignments reaching the start of each statement, in order. Statements with blocks are
+    /// keyed by the code before their first block.
+    pub statements: Vec<(Range, Vec<Arc<AtomicRefCell<VariableDefinition>>>)>,
+    /// Assignments reaching the end of the body.
+    pub exit: Vec<Arc<AtomicRefCell<VariableDefinition>>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Reaching {
+    /// Reaching definitions of variable references, keyed by the reference location.
+    pub uses: HashMap<Range, ReachingDefinitions>,
+    /// Assignments reaching each statement of the script code, first, and of each function.
+    pub bodies: Vec<BodyDefinitions>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Workspace {
     /// Map of qualified function name to function definitions
@@ -222,6 +314,8 @@ pub struct Workspace {
     pub references: Vec<Arc<AtomicRefCell<Reference>>>,
     /// Variables
     pub variables: Vec<Arc<AtomicRefCell<VariableDefinition>>>,
+    /// Results of the reaching definitions analysis.
+    pub reaching: Reaching,
 }
 
 //////////////////////////////////////////////////////////////////////////////
@@ -4,16 +4,21 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use anyhow::{anyhow, Context, Result};
 use crossbeam_channel::{Receiver, Sender};
 use lsp_server::{Message, RequestId};
-use lsp_types::notification::{Notification, Progress};
-use lsp_types::request::{Request, SemanticTokensRefresh};
+use lsp_types::notification::{DidChangeWatchedFiles, Notification, Progress, ShowMessage};
+use lsp_types::request::{RegisterCapability, Request, SemanticTokensRefresh};
 use lsp_types::{
-    ProgressParams, ProgressParamsValue, WorkDoneProgress, WorkDoneProgressBegin,
-    WorkDoneProgressEnd, WorkDoneProgressReport,
+    DidChangeWatchedFilesRegistrationOptions, FileSystemWatcher, GlobPattern, MessageType,
+    ProgressParams, ProgressParamsValue, Registration, RegistrationParams, ShowMessageParams,
+    WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressEnd, WorkDoneProgressReport,
 };
 
+use crate::extractors::matlab_project::PROJECT_METADATA;
+use crate::settings::PROJECT_FILES;
 use crate::threads::db::db_get_request_id;
 use crate::types::{SenderThread, ThreadMessage};
 
@@ -33,6 +38,77 @@ pub fn request_semantic_tokens_refresh(
     Ok(())
 }
 
+/// Asks the client to report the files and folders changed on disk, as when switching branches.
+/// Package, class and private folders are watched too, as deleting or renaming one only reports
+/// the folder.
+pub fn register_file_watchers(lsp_sender: &Sender<Message>) -> Result<()> {
+    let project_files = PROJECT_FILES
+        .iter()
+        .map(|f| format!("**/{f}"))
+        .chain([format!("**/{PROJECT_METADATA}/**/*.xml")]);
+    let watchers = ["**/*.m", "**/+*", "**/@*", "**/private"]
+        .into_iter()
+        .map(String::from)
+        .chain(project_files)
+        .map(|glob| FileSystemWatcher {
+            glob_pattern: GlobPattern::String(glob),
+            kind: None,
+        })
+        .collect();
+    let registration = Registration {
+        id: "watched-files".into(),
+        method: DidChangeWatchedFiles::METHOD.into(),
+        register_options: Some(serde_json::to_value(
+            DidChangeWatchedFilesRegistrationOptions { watchers },
+        )?),
+    };
+    lsp_sender.send(Message::Request(lsp_server::Request {
+        id: RequestId::from(String::from("register-watched-files")),
+        method: RegisterCapability::METHOD.to_string(),
+        params: serde_json::to_value(RegistrationParams {
+            registrations: vec![registration],
+        })?,
+    }))?;
+    Ok(())
+}
+
+/// Maps `f` over `items` on as many threads as there are cores, keeping the order of the items.
+pub fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(items.len().max(1));
+    // Items are handed out one at a time, as some files take much longer to parse than others.
+    let next = AtomicUsize::new(0);
+    let (next, f) = (&next, &f);
+    let mut results: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut results = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        results.push((i, f(item)));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| {
+                w.join()
+                    .unwrap_or_else(|err| std::panic::resume_unwind(err))
+            })
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
 #[macro_export]
 macro_rules! code_loc {
     () => {
@@ -78,6 +154,22 @@ pub fn send_progress_report<T: AsRef<str>>(
     send_notification(lsp_sender, id, wd_begin)
 }
 
+/// Counts one more file done and reports the progress when the percentage changes. Progress
+/// is only informative, so a report that can not be sent is dropped.
+pub fn report_progress(
+    lsp_sender: &Sender<Message>,
+    id: i32,
+    message: &str,
+    done: &AtomicUsize,
+    total: usize,
+) {
+    let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+    let percentage = 100 * done / total.max(1);
+    if percentage != 100 * (done - 1) / total.max(1) {
+        let _ = send_progress_report(lsp_sender.clone(), id, message, percentage as u32);
+    }
+}
+
 pub fn send_progress_end<T: AsRef<str>>(
     lsp_sender: Sender<Message>,
     id: i32,
@@ -105,6 +197,23 @@ pub fn send_notification(
         .context(code_loc!())
 }
 
+/// Shows a message to the user, for problems they can fix, like invalid settings.
+pub fn show_message<S: AsRef<str>>(
+    lsp_sender: &Sender<Message>,
+    typ: MessageType,
+    message: S,
+) -> Result<()> {
+    lsp_sender
+        .send(Message::Notification(lsp_server::Notification {
+            method: ShowMessage::METHOD.to_string(),
+            params: serde_json::to_value(ShowMessageParams {
+                typ,
+                message: message.as_ref().into(),
+            })?,
+        }))
+        .context(code_loc!())
+}
+
 // ////////////////////////////////////////////////////////////////////////////////
 // ///                                                                          ///
 // ///           The functions below were taken from the helix editor           ///